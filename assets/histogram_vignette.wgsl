@@ -0,0 +1,37 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+struct VignetteParams {
+    // How the bins of the histogram are spaced along the log luminance.
+    bin_gamma: f32,
+}
+
+@group(0) @binding(0)
+var screen_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var screen_sampler: sampler;
+// The weighted pixel counts of the metering histogram. The first bin holds the pixels below the
+// metering range.
+@group(0) @binding(2)
+var<storage, read> histogram: array<u32>;
+@group(0) @binding(3)
+var<uniform> params: VignetteParams;
+
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    // The share of the metered pixels that are brighter than the middle of the metering range.
+    // A real effect would reduce the histogram once, in a compute pass, instead of per pixel.
+    let last_bin = arrayLength(&histogram) - 1u;
+    var total = 0.0;
+    var bright = 0.0;
+    for (var i = 1u; i <= last_bin; i += 1u) {
+        let log_lum = pow(f32(i) / f32(last_bin), 1.0 / params.bin_gamma);
+        let count = f32(histogram[i]);
+        total += count;
+        bright += select(0.0, count, log_lum > 0.5);
+    }
+    let strength = select(0.0, bright / total, total > 0.0);
+
+    let color = textureSample(screen_texture, screen_sampler, in.uv);
+    let distance = length(in.uv - 0.5) * sqrt(2.0);
+    return vec4<f32>(color.rgb * (1.0 - strength * smoothstep(0.4, 1.0, distance)), color.a);
+}
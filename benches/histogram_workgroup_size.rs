@@ -0,0 +1,219 @@
+//! Compares the metering time of a few `histogram_workgroup_size`s, on an image the size of a
+//! 1080p view, without a window. Run it with:
+//!
+//! ```sh
+//! cargo bench --bench histogram_workgroup_size
+//! ```
+//!
+//! The GPU time of metering is only measured on devices with timestamp queries. Elsewhere, the
+//! time of whole frames is measured instead, with the GPU waited for after each one, which
+//! includes the rest of the frame but still shows the difference between the sizes.
+
+use std::time::{Duration, Instant};
+
+use bevy::{
+    app::PluginsState,
+    diagnostic::DiagnosticsStore,
+    log::LogPlugin,
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        pipelined_rendering::PipelinedRenderingPlugin,
+        render_asset::RenderAssetUsages,
+        render_resource::{
+            CachedPipelineState, Extent3d, Maintain, PipelineCache, TextureDimension,
+            TextureFormat, TextureUsages,
+        },
+        renderer::RenderDevice,
+        Render, RenderApp, RenderSet,
+    },
+    window::ExitCondition,
+    winit::WinitPlugin,
+};
+use bevy_mod_auto_exposure::{
+    AutoExposure, AutoExposureDiagnosticsPlugin, AutoExposurePlugin, ExposureSettled,
+    MeteringSource,
+};
+
+const SIZES: [UVec2; 4] = [
+    UVec2::new(8, 8),
+    UVec2::new(16, 16),
+    UVec2::new(32, 8),
+    UVec2::new(16, 4),
+];
+
+/// The frames that are measured for every size, after the pipelines compiled.
+const FRAMES: u32 = 200;
+
+/// The most frames that are waited for the first metered exposure.
+const MAX_WARMUP_FRAMES: u32 = 1000;
+
+fn main() {
+    for size in SIZES {
+        let mut app = app(size);
+        warm_up(&mut app);
+
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            app.update();
+        }
+        let frame = start.elapsed() / FRAMES;
+
+        let store = app.world.resource::<DiagnosticsStore>();
+        let metering = store
+            .get(&AutoExposureDiagnosticsPlugin::METERING_TIME)
+            .and_then(|diagnostic| diagnostic.average());
+        let recording = store
+            .get(&AutoExposureDiagnosticsPlugin::METERING_CPU_TIME)
+            .and_then(|diagnostic| diagnostic.average())
+            .unwrap_or_default();
+
+        let metering = match metering {
+            Some(gpu_ms) => format!("metering {gpu_ms:.3} ms on the GPU"),
+            None => "no GPU timestamps".to_string(),
+        };
+        println!(
+            "{:>2}x{:<2}  {metering}, recording {recording:.3} ms, frame {:.3} ms",
+            size.x,
+            size.y,
+            millis(frame),
+        );
+    }
+}
+
+fn app(histogram_workgroup_size: UVec2) -> App {
+    let mut app = App::new();
+    app.add_plugins((
+        DefaultPlugins
+            .build()
+            .disable::<WinitPlugin>()
+            .disable::<PipelinedRenderingPlugin>()
+            .disable::<LogPlugin>()
+            .set(WindowPlugin {
+                primary_window: None,
+                exit_condition: ExitCondition::DontExit,
+                close_when_requested: false,
+            }),
+        AutoExposurePlugin {
+            histogram_workgroup_size,
+            ..default()
+        },
+        AutoExposureDiagnosticsPlugin,
+    ))
+    .add_systems(Startup, setup);
+
+    app.sub_app_mut(RenderApp)
+        .add_systems(Render, wait_for_gpu.in_set(RenderSet::Cleanup));
+
+    while app.plugins_state() != PluginsState::Ready {
+        bevy::tasks::tick_global_task_pools_on_main_thread();
+    }
+    app.finish();
+    app.cleanup();
+    app
+}
+
+/// Updates the app until the first metered exposure is read back and every pipeline compiled,
+/// then forgets the times that were measured so far.
+fn warm_up(app: &mut App) {
+    for _ in 0..MAX_WARMUP_FRAMES {
+        app.update();
+
+        let metered = app
+            .world
+            .query::<&ExposureSettled>()
+            .single(&app.world)
+            .exposure()
+            .is_some();
+        let compiling = app
+            .sub_app(RenderApp)
+            .world
+            .resource::<PipelineCache>()
+            .pipelines()
+            .any(|pipeline| {
+                matches!(
+                    pipeline.state,
+                    CachedPipelineState::Queued | CachedPipelineState::Creating(_)
+                )
+            });
+        if metered && !compiling {
+            let mut store = app.world.resource_mut::<DiagnosticsStore>();
+            for path in [
+                AutoExposureDiagnosticsPlugin::METERING_TIME,
+                AutoExposureDiagnosticsPlugin::METERING_CPU_TIME,
+            ] {
+                if let Some(diagnostic) = store.get_mut(&path) {
+                    diagnostic.clear_history();
+                }
+            }
+            return;
+        }
+    }
+
+    panic!("no metered exposure was read back within {MAX_WARMUP_FRAMES} frames");
+}
+
+/// Waits for the GPU to finish the frame, so the frames are timed with their GPU work.
+fn wait_for_gpu(device: Res<RenderDevice>) {
+    device.poll(Maintain::Wait);
+}
+
+fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let size = Extent3d {
+        width: 1920,
+        height: 1080,
+        depth_or_array_layers: 1,
+    };
+
+    // Spread the pixels over 16 EV, so the workgroups add to many bins of the histogram, like
+    // they do for a real image.
+    let mut data = Vec::with_capacity(size.width as usize * size.height as usize * 8);
+    for y in 0..size.height {
+        for x in 0..size.width {
+            let value = half_bytes((x / 8 + y / 8) % 16 + 7);
+            let one = half_bytes(15);
+            data.extend([value, value, value, one].concat());
+        }
+    }
+    let source = images.add(Image::new(
+        size,
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba16Float,
+        RenderAssetUsages::default(),
+    ));
+
+    let mut target = Image::new_fill(
+        size,
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::Bgra8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    target.texture_descriptor.usage |= TextureUsages::RENDER_ATTACHMENT;
+
+    commands.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                hdr: true,
+                target: RenderTarget::Image(images.add(target)),
+                ..default()
+            },
+            ..default()
+        },
+        AutoExposure {
+            metering_source: MeteringSource::Image(source),
+            ..default()
+        },
+        ExposureSettled::default(),
+    ));
+}
+
+/// The bytes of the half float with the biased `exponent`, a power of two.
+fn half_bytes(exponent: u32) -> [u8; 2] {
+    ((exponent as u16) << 10).to_le_bytes()
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
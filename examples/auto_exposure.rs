@@ -1,22 +1,47 @@
-use bevy::{input::mouse::MouseMotion, math::vec2, prelude::*, window::CursorGrabMode};
-use bevy_mod_auto_exposure::{AutoExposure, AutoExposurePlugin};
+//! Run with `--post-bloom` to meter the view after bloom instead of before it. The bright
+//! emissive sphere then darkens the rest of the scene more, since its bloom is metered too.
+//!
+//! Run with `--radial-mask` to meter with a mask that is generated at startup, instead of
+//! metering the whole view evenly. The center of the view then decides the exposure.
+//!
+//! With the `debug-text` feature, press `T` to toggle a readout of the exposure.
+
+use bevy::{
+    core_pipeline::bloom::BloomSettings, input::mouse::MouseMotion, math::vec2, prelude::*,
+    window::CursorGrabMode,
+};
+#[cfg(feature = "debug-text")]
+use bevy_mod_auto_exposure::AutoExposureDebugText;
+use bevy_mod_auto_exposure::{AutoExposure, AutoExposurePlugin, MeteringTap};
 
 #[derive(Component)]
 struct CameraMarker;
 
 fn main() {
-    App::new()
-        .add_plugins(DefaultPlugins)
-        .add_plugins(AutoExposurePlugin)
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins)
+        .add_plugins(AutoExposurePlugin {
+            metering_tap: if std::env::args().any(|arg| arg == "--post-bloom") {
+                MeteringTap::PostBloom
+            } else {
+                MeteringTap::PreBloom
+            },
+            ..default()
+        })
         .add_systems(Startup, setup)
-        .add_systems(Update, rotate_camera)
-        .run();
+        .add_systems(Update, rotate_camera);
+
+    #[cfg(feature = "debug-text")]
+    app.add_systems(Update, toggle_debug_text);
+
+    app.run();
 }
 
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
 ) {
     let ball = meshes.add(Sphere::default());
 
@@ -40,6 +65,16 @@ fn setup(
         ..default()
     });
 
+    commands.spawn(PbrBundle {
+        mesh: ball.clone(),
+        material: materials.add(StandardMaterial {
+            emissive: Color::rgb_linear(20000.0, 15000.0, 10000.0),
+            ..default()
+        }),
+        transform: Transform::from_xyz(0.0, 0.5, -2.0).with_scale(Vec3::splat(0.3)),
+        ..default()
+    });
+
     commands.spawn(PbrBundle {
         mesh: meshes.add(Plane3d::default().mesh().size(5.0, 5.0)),
         material: materials.add(StandardMaterial {
@@ -70,10 +105,16 @@ fn setup(
             transform: Transform::from_xyz(0.0, 0.0, 6.0),
             ..Default::default()
         },
+        BloomSettings::NATURAL,
         AutoExposure {
             min: -16.0,
             max: 16.0,
             compensation_curve: vec![vec2(-16.0, -4.0), vec2(0.0, -2.0), vec2(16.0, 0.0)],
+            metering_mask: if std::env::args().any(|arg| arg == "--radial-mask") {
+                AutoExposure::radial_mask(&mut images, 0.2, 0.8)
+            } else {
+                default()
+            },
             ..default()
         },
         CameraMarker,
@@ -108,3 +149,23 @@ fn rotate_camera(
         }
     }
 }
+
+#[cfg(feature = "debug-text")]
+fn toggle_debug_text(
+    mut commands: Commands,
+    key: Res<ButtonInput<KeyCode>>,
+    camera: Query<(Entity, Has<AutoExposureDebugText>), With<CameraMarker>>,
+) {
+    if !key.just_pressed(KeyCode::KeyT) {
+        return;
+    }
+
+    let (entity, has_text) = camera.single();
+    if has_text {
+        commands.entity(entity).remove::<AutoExposureDebugText>();
+    } else {
+        commands
+            .entity(entity)
+            .insert(AutoExposureDebugText::default());
+    }
+}
@@ -0,0 +1,180 @@
+//! Animates the compensation curve over a day/night cycle: flat at noon, with lifted shadows at
+//! dusk and dawn, and lowered at night so it stays dark. The curve is replaced every frame, and
+//! the adapted exposure is read back to show it on screen.
+
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_mod_auto_exposure::{AutoExposure, AutoExposurePlugin, ExposureSettled};
+
+/// The length of a day, in seconds.
+const DAY_LENGTH: f32 = 48.0;
+
+/// The metered luminances that the curves are defined at, in EV.
+const CURVE_EV: [f32; 3] = [-12.0, 0.0, 12.0];
+/// The compensation at `CURVE_EV` at noon: the scene is exposed as mid gray.
+const NOON: [f32; 3] = [0.0, 0.0, 0.0];
+/// At dusk and dawn, dark scenes are lifted to show detail in the shadows.
+const DUSK: [f32; 3] = [2.0, 1.0, 0.0];
+/// At night, dark scenes are kept dark, instead of being exposed up to mid gray.
+const NIGHT: [f32; 3] = [-4.0, -2.0, 0.0];
+
+#[derive(Component)]
+struct Sun;
+
+#[derive(Component)]
+struct Clock;
+
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .add_plugins(AutoExposurePlugin::default())
+        .insert_resource(AmbientLight {
+            color: Color::rgb(0.6, 0.7, 1.0),
+            brightness: 20.0,
+        })
+        .add_systems(Startup, setup)
+        .add_systems(Update, (day_night_cycle, update_clock))
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(Plane3d::default().mesh().size(40.0, 40.0)),
+        material: materials.add(StandardMaterial {
+            base_color: Color::rgb(0.3, 0.5, 0.3),
+            perceptual_roughness: 1.0,
+            ..default()
+        }),
+        ..default()
+    });
+
+    let material = materials.add(StandardMaterial {
+        base_color: Color::rgb(0.8, 0.7, 0.6),
+        ..default()
+    });
+    for x in -2..=2 {
+        commands.spawn(PbrBundle {
+            mesh: meshes.add(Cuboid::new(1.0, 1.0 + (x + 2) as f32, 1.0)),
+            material: material.clone(),
+            transform: Transform::from_xyz(x as f32 * 3.0, 0.5 + (x + 2) as f32 * 0.5, 0.0),
+            ..default()
+        });
+    }
+
+    commands.spawn((
+        DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                shadows_enabled: true,
+                ..default()
+            },
+            ..default()
+        },
+        Sun,
+    ));
+
+    commands.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                hdr: true,
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 6.0, 16.0).looking_at(Vec3::ZERO, Vec3::Y),
+            ..default()
+        },
+        AutoExposure {
+            min: -12.0,
+            max: 12.0,
+            ..default()
+        },
+        ExposureSettled::default(),
+    ));
+
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 20.0,
+                color: Color::WHITE,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(12.0),
+            left: Val::Px(12.0),
+            ..default()
+        }),
+        Clock,
+    ));
+}
+
+/// The time of day, in hours, starting at sunrise.
+fn hours(time: &Time) -> f32 {
+    (6.0 + time.elapsed_seconds() / DAY_LENGTH * 24.0) % 24.0
+}
+
+/// The height of the sun, from -1 at midnight to 1 at noon.
+fn sun_height(hours: f32) -> f32 {
+    ((hours - 6.0) / 12.0 * PI).sin()
+}
+
+fn day_night_cycle(
+    time: Res<Time>,
+    mut sun: Query<(&mut Transform, &mut DirectionalLight), With<Sun>>,
+    mut cameras: Query<&mut AutoExposure>,
+) {
+    let hours = hours(&time);
+    let height = sun_height(hours);
+
+    for (mut transform, mut light) in sun.iter_mut() {
+        transform.rotation = Quat::from_rotation_x(-(hours - 6.0) / 12.0 * PI);
+        light.illuminance = 10000.0 * height.max(0.0);
+    }
+
+    // Blend from the noon curve to the dusk curve as the sun sets, and on to the night curve
+    // once it's below the horizon.
+    let compensation = if height >= 0.0 {
+        blend(DUSK, NOON, (height / 0.3).min(1.0))
+    } else {
+        blend(DUSK, NIGHT, (-height / 0.3).min(1.0))
+    };
+
+    // Replacing the curve bakes it again for the GPU.
+    for mut auto_exposure in cameras.iter_mut() {
+        auto_exposure.compensation_curve = CURVE_EV
+            .iter()
+            .zip(compensation)
+            .map(|(&ev, compensation)| Vec2::new(ev, compensation))
+            .collect();
+    }
+}
+
+fn blend(from: [f32; 3], to: [f32; 3], t: f32) -> [f32; 3] {
+    [0, 1, 2].map(|i| from[i] + (to[i] - from[i]) * t)
+}
+
+fn update_clock(
+    time: Res<Time>,
+    cameras: Query<&ExposureSettled>,
+    mut clock: Query<&mut Text, With<Clock>>,
+) {
+    let hours = hours(&time);
+    let exposure = match cameras.single().exposure() {
+        Some(exposure) => format!("{exposure:.2} EV"),
+        None => "waiting for readback".to_string(),
+    };
+
+    for mut text in clock.iter_mut() {
+        text.sections[0].value = format!(
+            "Time: {:02}:{:02}\nExposure: {}",
+            hours as u32,
+            (hours.fract() * 60.0) as u32,
+            exposure,
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! Fades in from black once the exposure has settled, like a cutscene that waits for the
+//! camera to adapt to the scene before it starts.
+
+use bevy::prelude::*;
+use bevy_mod_auto_exposure::{AutoExposure, AutoExposurePlugin, ExposureSettled};
+
+/// The black overlay that is faded out.
+#[derive(Component)]
+struct Fade;
+
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .add_plugins(AutoExposurePlugin::default())
+        .add_systems(Startup, setup)
+        .add_systems(Update, fade_in)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(Sphere::default()),
+        material: materials.add(StandardMaterial {
+            base_color: Color::rgb(0.5, 0.5, 1.0),
+            ..default()
+        }),
+        ..default()
+    });
+
+    commands.spawn(PointLightBundle {
+        point_light: PointLight {
+            intensity: 900000.0,
+            range: 100.,
+            shadows_enabled: true,
+            ..default()
+        },
+        transform: Transform::from_xyz(8.0, 16.0, 8.0),
+        ..default()
+    });
+
+    commands.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                hdr: true,
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 0.0, 6.0),
+            ..default()
+        },
+        AutoExposure {
+            min: -16.0,
+            max: 16.0,
+            ..default()
+        },
+        ExposureSettled::default(),
+    ));
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                ..default()
+            },
+            background_color: Color::BLACK.into(),
+            ..default()
+        },
+        Fade,
+    ));
+}
+
+fn fade_in(
+    time: Res<Time>,
+    camera: Query<&ExposureSettled>,
+    mut fade: Query<&mut BackgroundColor, With<Fade>>,
+) {
+    if !camera.single().is_settled() {
+        return;
+    }
+
+    for mut color in fade.iter_mut() {
+        let alpha = (color.0.a() - time.delta_seconds()).max(0.0);
+        color.0.set_a(alpha);
+    }
+}
@@ -0,0 +1,261 @@
+//! Reads the metering histogram from a custom render node, to darken the edges of the view by
+//! how much of it is brighter than the middle of the metering range.
+//!
+//! This shows how a post-process effect, like local tone mapping, can reuse the histogram that
+//! auto exposure already meters instead of building its own.
+
+use bevy::{
+    core_pipeline::{
+        core_3d::graph::{Core3d, Node3d},
+        fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    },
+    ecs::query::QueryItem,
+    prelude::*,
+    render::{
+        render_graph::{
+            NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel, ViewNode, ViewNodeRunner,
+        },
+        render_resource::{
+            binding_types::{sampler, storage_buffer_read_only_sized, texture_2d, uniform_buffer},
+            *,
+        },
+        renderer::{RenderContext, RenderDevice},
+        view::ViewTarget,
+        RenderApp,
+    },
+};
+use bevy_mod_auto_exposure::{
+    AutoExposure, AutoExposureLabel, AutoExposurePlugin, ShareHistogram, ViewHistogramBuffer,
+};
+
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .add_plugins(AutoExposurePlugin::default())
+        .add_plugins(HistogramVignettePlugin)
+        .add_systems(Startup, setup)
+        .add_systems(Update, pulse_light)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(Sphere::default()),
+        material: materials.add(StandardMaterial {
+            base_color: Color::rgb(0.5, 0.5, 1.0),
+            ..default()
+        }),
+        ..default()
+    });
+
+    commands.spawn(PointLightBundle {
+        point_light: PointLight {
+            intensity: 900000.0,
+            range: 100.,
+            shadows_enabled: true,
+            ..default()
+        },
+        transform: Transform::from_xyz(8.0, 16.0, 8.0),
+        ..default()
+    });
+
+    commands.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                hdr: true,
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 0.0, 6.0),
+            ..default()
+        },
+        AutoExposure {
+            min: -16.0,
+            max: 16.0,
+            ..default()
+        },
+        // Copies the histogram into a `ViewHistogramBuffer` for the vignette node.
+        ShareHistogram,
+    ));
+}
+
+/// Sweeps the light over a wide range, so the histogram, and the vignette with it, moves.
+fn pulse_light(time: Res<Time>, mut lights: Query<&mut PointLight>) {
+    for mut light in lights.iter_mut() {
+        light.intensity = 900000.0 * ((time.elapsed_seconds() * 0.5).sin() * 6.0).exp2();
+    }
+}
+
+struct HistogramVignettePlugin;
+
+impl Plugin for HistogramVignettePlugin {
+    fn build(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        // The histogram is updated by the auto exposure node, so the vignette runs after it.
+        render_app
+            .add_render_graph_node::<ViewNodeRunner<HistogramVignetteNode>>(
+                Core3d,
+                HistogramVignetteLabel,
+            )
+            .add_render_graph_edges(
+                Core3d,
+                (
+                    Node3d::Tonemapping,
+                    HistogramVignetteLabel,
+                    Node3d::EndMainPassPostProcessing,
+                ),
+            )
+            .add_render_graph_edge(Core3d, AutoExposureLabel, HistogramVignetteLabel);
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<HistogramVignettePipeline>();
+    }
+}
+
+#[derive(RenderLabel, Debug, Clone, Hash, PartialEq, Eq)]
+struct HistogramVignetteLabel;
+
+/// How the bins of the histogram are spaced, see `ViewHistogramBuffer::bin_gamma`.
+#[derive(ShaderType)]
+struct VignetteParams {
+    bin_gamma: f32,
+}
+
+#[derive(Resource)]
+struct HistogramVignettePipeline {
+    layout: BindGroupLayout,
+    sampler: Sampler,
+    pipeline: CachedRenderPipelineId,
+}
+
+impl FromWorld for HistogramVignettePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let layout = render_device.create_bind_group_layout(
+            "histogram vignette bind group",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::FRAGMENT,
+                (
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    sampler(SamplerBindingType::Filtering),
+                    // The histogram has a bin count of `AutoExposurePlugin`, which the shader
+                    // reads from the length of the array.
+                    storage_buffer_read_only_sized(false, None),
+                    uniform_buffer::<VignetteParams>(false),
+                ),
+            ),
+        );
+        let sampler = render_device.create_sampler(&SamplerDescriptor::default());
+        let shader = world
+            .resource::<AssetServer>()
+            .load("histogram_vignette.wgsl");
+
+        let pipeline =
+            world
+                .resource_mut::<PipelineCache>()
+                .queue_render_pipeline(RenderPipelineDescriptor {
+                    label: Some("histogram vignette pipeline".into()),
+                    layout: vec![layout.clone()],
+                    push_constant_ranges: vec![],
+                    vertex: fullscreen_shader_vertex_state(),
+                    primitive: default(),
+                    depth_stencil: None,
+                    multisample: default(),
+                    fragment: Some(FragmentState {
+                        shader,
+                        shader_defs: vec![],
+                        entry_point: "fragment".into(),
+                        targets: vec![Some(ColorTargetState {
+                            format: ViewTarget::TEXTURE_FORMAT_HDR,
+                            blend: None,
+                            write_mask: ColorWrites::ALL,
+                        })],
+                    }),
+                });
+
+        Self {
+            layout,
+            sampler,
+            pipeline,
+        }
+    }
+}
+
+#[derive(Default)]
+struct HistogramVignetteNode;
+
+impl ViewNode for HistogramVignetteNode {
+    type ViewQuery = (&'static ViewTarget, &'static ViewHistogramBuffer);
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (view_target, histogram): QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let vignette = world.resource::<HistogramVignettePipeline>();
+        let Some(pipeline) = world
+            .resource::<PipelineCache>()
+            .get_render_pipeline(vignette.pipeline)
+        else {
+            return Ok(());
+        };
+
+        let mut params = encase::UniformBuffer::new(Vec::new());
+        params
+            .write(&VignetteParams {
+                bin_gamma: histogram.bin_gamma,
+            })
+            .unwrap();
+        let params =
+            render_context
+                .render_device()
+                .create_buffer_with_data(&BufferInitDescriptor {
+                    label: Some("histogram vignette params"),
+                    contents: params.as_ref(),
+                    usage: BufferUsages::UNIFORM,
+                });
+
+        let post_process = view_target.post_process_write();
+        let bind_group = render_context.render_device().create_bind_group(
+            "histogram vignette bind group",
+            &vignette.layout,
+            &BindGroupEntries::sequential((
+                post_process.source,
+                &vignette.sampler,
+                histogram.buffer.as_entire_binding(),
+                params.as_entire_binding(),
+            )),
+        );
+
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("histogram vignette pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: post_process.destination,
+                resolve_target: None,
+                ops: Operations::default(),
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_render_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+//! Shows a rear view mirror in the corner of the window, which adapts on its own.
+//!
+//! The inset camera renders to a `Viewport` of the same window as the main camera. Each camera
+//! only meters its own viewport, so the dim room behind the camera doesn't brighten the bright
+//! view ahead, and the other way around.
+
+use bevy::{prelude::*, render::camera::Viewport, window::PrimaryWindow};
+use bevy_mod_auto_exposure::{AutoExposure, AutoExposurePlugin};
+
+/// The camera that shows the inset.
+#[derive(Component)]
+struct Inset;
+
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .add_plugins(AutoExposurePlugin::default())
+        .add_systems(Startup, setup)
+        .add_systems(Update, place_inset)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    // A brightly lit sphere ahead of the cameras, and a dimly lit one behind them.
+    for (z, intensity) in [(-6.0, 4000000.0), (6.0, 4000.0)] {
+        commands.spawn(PbrBundle {
+            mesh: meshes.add(Sphere::default()),
+            material: materials.add(StandardMaterial {
+                base_color: Color::rgb(0.5, 0.5, 1.0),
+                ..default()
+            }),
+            transform: Transform::from_xyz(0.0, 0.0, z),
+            ..default()
+        });
+
+        commands.spawn(PointLightBundle {
+            point_light: PointLight {
+                intensity,
+                range: 100.,
+                ..default()
+            },
+            transform: Transform::from_xyz(2.0, 3.0, z * 0.5),
+            ..default()
+        });
+    }
+
+    let auto_exposure = AutoExposure {
+        min: -16.0,
+        max: 16.0,
+        ..default()
+    };
+
+    commands.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                hdr: true,
+                ..default()
+            },
+            ..default()
+        },
+        auto_exposure.clone(),
+    ));
+
+    // The inset is drawn after the main camera. It clears its own background, so it doesn't
+    // meter the tone mapped image of the main camera.
+    commands.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                hdr: true,
+                order: 1,
+                ..default()
+            },
+            transform: Transform::IDENTITY.looking_to(Vec3::Z, Vec3::Y),
+            ..default()
+        },
+        auto_exposure,
+        Inset,
+    ));
+}
+
+/// Keeps the inset in the top right corner of the window, at a quarter of its size.
+fn place_inset(
+    windows: Query<&Window, (With<PrimaryWindow>, Changed<Window>)>,
+    mut insets: Query<&mut Camera, With<Inset>>,
+) {
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+    let size = UVec2::new(window.physical_width(), window.physical_height()) / 4;
+    for mut camera in insets.iter_mut() {
+        camera.viewport = Some(Viewport {
+            physical_position: UVec2::new(window.physical_width() - size.x, 0),
+            physical_size: size,
+            ..default()
+        });
+    }
+}
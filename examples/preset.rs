@@ -0,0 +1,72 @@
+//! Loads the auto exposure settings of a camera from a RON preset.
+//! Run with `cargo run --example preset --features serde`.
+
+use bevy::prelude::*;
+use bevy_mod_auto_exposure::{with_asset_server, AutoExposure, AutoExposurePlugin};
+
+const PRESET: &str = r#"(
+    min: -16.0,
+    max: 16.0,
+    low_percent: 50,
+    high_percent: 90,
+    speed_up: 2.0,
+    speed_down: 0.5,
+    metering_mask: Some("mask.png"),
+    compensation_curve: [(-16.0, -4.0), (0.0, -2.0), (16.0, 0.0)],
+)"#;
+
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .add_plugins(AutoExposurePlugin::default())
+        .add_systems(Startup, setup)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let auto_exposure: AutoExposure =
+        with_asset_server(&asset_server, || ron::from_str(PRESET)).expect("invalid preset");
+
+    // Print the preset again, to show that it round-trips.
+    info!(
+        "{}",
+        ron::ser::to_string_pretty(&auto_exposure, default()).unwrap()
+    );
+
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(Sphere::default()),
+        material: materials.add(StandardMaterial {
+            base_color: Color::rgb(0.5, 0.5, 1.0),
+            ..default()
+        }),
+        ..default()
+    });
+
+    commands.spawn(PointLightBundle {
+        point_light: PointLight {
+            intensity: 900000.0,
+            range: 100.,
+            shadows_enabled: true,
+            ..default()
+        },
+        transform: Transform::from_xyz(8.0, 16.0, 8.0),
+        ..default()
+    });
+
+    commands.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                hdr: true,
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 0.0, 6.0),
+            ..default()
+        },
+        auto_exposure,
+    ));
+}
@@ -0,0 +1,101 @@
+//! Saves the adapted exposure when the app quits, and restores it when it starts again, so the
+//! scene doesn't adapt from scratch after loading a save.
+
+use std::{fs, path::PathBuf};
+
+use bevy::{app::AppExit, prelude::*};
+use bevy_mod_auto_exposure::{
+    AutoExposure, AutoExposurePlugin, AutoExposureState, ExposureSettled,
+};
+
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .add_plugins(AutoExposurePlugin::default())
+        .add_systems(Startup, setup)
+        .add_systems(PostStartup, load)
+        .add_systems(Last, save)
+        .run();
+}
+
+/// Where the exposure is saved, in place of a real save game.
+fn save_path() -> PathBuf {
+    std::env::temp_dir().join("bevy_mod_auto_exposure_save_state.txt")
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(Sphere::default()),
+        material: materials.add(StandardMaterial {
+            base_color: Color::rgb(0.5, 0.5, 1.0),
+            ..default()
+        }),
+        ..default()
+    });
+
+    commands.spawn(PointLightBundle {
+        point_light: PointLight {
+            intensity: 900000.0,
+            range: 100.,
+            shadows_enabled: true,
+            ..default()
+        },
+        transform: Transform::from_xyz(8.0, 16.0, 8.0),
+        ..default()
+    });
+
+    commands.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                hdr: true,
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 0.0, 6.0),
+            ..default()
+        },
+        AutoExposure {
+            min: -16.0,
+            max: 16.0,
+            // Adapt slowly, so the restored exposure is easy to tell from adapting from scratch.
+            speed_up: 0.2,
+            speed_down: 0.2,
+            ..default()
+        },
+        // Reads the exposure back, which is what is saved.
+        ExposureSettled::default(),
+    ));
+}
+
+fn load(mut state: AutoExposureState, camera: Query<Entity, With<AutoExposure>>) {
+    let Some(ev) = fs::read_to_string(save_path())
+        .ok()
+        .and_then(|saved| saved.trim().parse().ok())
+    else {
+        info!("No saved exposure, adapting from scratch");
+        return;
+    };
+
+    info!("Restoring an exposure of {ev:.2} EV");
+    state.load_state(camera.single(), ev);
+}
+
+fn save(
+    mut exits: EventReader<AppExit>,
+    state: AutoExposureState,
+    camera: Query<Entity, With<AutoExposure>>,
+) {
+    if exits.read().next().is_none() {
+        return;
+    }
+
+    if let Some(ev) = state.save_state(camera.single()) {
+        info!("Saving an exposure of {ev:.2} EV");
+        if let Err(error) = fs::write(save_path(), ev.to_string()) {
+            warn!("Failed to save the exposure: {error}");
+        }
+    }
+}
@@ -1,6 +1,20 @@
+#import bevy_mod_auto_exposure::bins::{logLumToBin, binToLogLum, percentileBin}
+
 // Taken from RTR vol 4 pg. 278
 const RGB_TO_LUM = vec3<f32>(0.2125, 0.7154, 0.0721);
 
+// Linear sRGB to the cone responses of OKLab, and their cube roots to its lightness, from
+// https://bottosson.github.io/posts/oklab/.
+const RGB_TO_LMS = mat3x3<f32>(
+    vec3<f32>(0.4122214708, 0.2119034982, 0.0883024619),
+    vec3<f32>(0.5363325363, 0.6806995451, 0.2817188376),
+    vec3<f32>(0.0514459929, 0.1073969566, 0.6299787005),
+);
+const LMS_TO_LIGHTNESS = vec3<f32>(0.2104542553, 0.7936177850, -0.0040720468);
+
+// The size of `Params::exclusion_rects`, see `MAX_EXCLUSION_RECTS` on the Rust side.
+const MAX_EXCLUSION_RECTS = 8u;
+
 struct Params {
     min_log_lum: f32,
     inv_log_lum_range: f32,
@@ -9,6 +23,101 @@ struct Params {
     high_percent: u32,
     speed_up: f32,
     speed_down: f32,
+    delta_time: f32,
+    // A radius of zero disables spot metering.
+    spot_center: vec2<f32>,
+    spot_radius: f32,
+    // Only pixels within this rect of the view are metered, as (min x, min y, max x, max y).
+    spot_rect: vec4<f32>,
+    // How far to cross-fade from `tex_mask` to `tex_mask_blend`.
+    mask_blend: f32,
+    // Pixels with a log luminance above this aren't metered at all.
+    saturation_cutoff: f32,
+    // When set, pixels are weighed by the alpha of `tex_color`.
+    weight_by_alpha: u32,
+    // When set, snap to the target instead of adapting to it.
+    reset: u32,
+    // The exposure is held while the target is within this many EV of it.
+    deadband: f32,
+    // The most the exposure may change this frame, in EV.
+    max_step: f32,
+    // Scenes darker than this log luminance are exposed as if they were at it.
+    dark_floor: f32,
+    // The target exposure is capped at this, in EV.
+    max_gain: f32,
+    // The spacing of the histogram bins, see `logLumToBin`.
+    bin_gamma: f32,
+    // When set, bins that the percentiles cut off partially are interpolated.
+    interpolate_percentiles: u32,
+    // When set, `tex_compensation` holds the target exposure instead of a compensation.
+    compensation_override: u32,
+    // Added to the target exposure, in EV.
+    correction: f32,
+    // The environment exposure is the exposure times the scale, plus the correction.
+    environment_scale: f32,
+    environment_correction: f32,
+    // The share of `histogram_history` that is blended into the histogram.
+    temporal_blend: f32,
+    // How far the target exposure follows the key of the scene, zero to ignore it.
+    key_strength: f32,
+    // The region of the metering masks that is stretched across the view, as (x, y, width, height).
+    mask_region: vec4<f32>,
+    // The mask weights are scaled by the gain and offset by the bias.
+    mask_gain: f32,
+    mask_bias: f32,
+    // When set, pixels are weighed by `tex_radial` at their distance from the view center.
+    radial: u32,
+    // Only every this many pixels are metered, in each dimension.
+    sample_stride: u32,
+    // The adapted exposure that the camera was already rendered with, in EV, which is undone
+    // before metering.
+    camera_exposure: f32,
+    // The rect of `tex_color` to meter, as (x, y, width, height).
+    viewport: vec4<u32>,
+    // The number of `exclusion_rects` in use.
+    exclusion_count: u32,
+    // Pixels within these rects of the view aren't metered, as (min x, min y, max x, max y).
+    exclusion_rects: array<vec4<f32>, MAX_EXCLUSION_RECTS>,
+    // How far the metering mode has faded in from `tex_mode_from`. One when no fade is in
+    // progress.
+    mode_blend: f32,
+    // When set, the exposure follows `tex_easing` from where it started adapting to the target.
+    easing: u32,
+    // When set, pixels are metered by their perceptual lightness instead of their luma.
+    perceptual_luminance: u32,
+    // How `tex_mask_layer` is combined with the metering masks: 1 multiplies, 2 adds and 3 takes
+    // the larger weight. Zero without a layer.
+    mask_combine: u32,
+    // When set, `target_log_lum` is exposed as mid gray instead of the metered average.
+    absolute_target: u32,
+    // The calibrated luminance to expose as mid gray, as a log luminance of the metered source.
+    target_log_lum: f32,
+    // The metered pixels below and above this log luminance are weighed by `shadow_weight` and
+    // `highlight_weight` when they are averaged.
+    tone_split: f32,
+    shadow_weight: f32,
+    highlight_weight: f32,
+}
+
+struct State {
+    // The adapted exposure, in EV.
+    exposure: f32,
+    // The metered average log luminance, of the last frame that metered anything.
+    average: f32,
+    // The exposure for the environment, derived from the adapted exposure.
+    environment: f32,
+    // The number of frames that metered anything, counting up to 65535 and starting over at one.
+    // It's a float, so the fragment fallback can write it in a texel with the values above.
+    metered_frames: f32,
+    // The metered log luminance at `RANGE_LOW_PERCENT` and `RANGE_HIGH_PERCENT`, of the last
+    // frame that metered anything, which spans the dynamic range of the scene.
+    range_low: f32,
+    range_high: f32,
+    // The exposure that the current eased transition started at, the target it heads for, and
+    // its linear progress, which is one when no transition is in progress.
+    transition_start: f32,
+    transition_target: f32,
+    transition_progress: f32,
 }
 
 @group(0) @binding(0)
@@ -20,15 +129,98 @@ var tex_mask: texture_2d<f32>;
 @group(0) @binding(3)
 var tex_compensation: texture_1d<f32>;
 @group(0) @binding(4)
-var<storage, read_write> histogram: array<atomic<u32>, 256>;
+var<storage, read_write> histogram: array<atomic<u32>, #{HISTOGRAM_BINS}>;
 @group(0) @binding(5)
-var<storage, read_write> result: f32;
+var<storage, read_write> state: State;
+@group(0) @binding(6)
+var tex_mask_blend: texture_2d<f32>;
+@group(0) @binding(7)
+var tex_radial: texture_1d<f32>;
+@group(0) @binding(8)
+var tex_lut: texture_3d<f32>;
+@group(0) @binding(9)
+var lut_sampler: sampler;
+// The blended histogram of the previous frames.
+@group(0) @binding(10)
+var<storage, read_write> histogram_history: array<f32, #{HISTOGRAM_BINS}>;
+// The weights of the view that the metering mode fades from.
+@group(0) @binding(11)
+var tex_mode_from: texture_2d<f32>;
+// The easing curve, which maps the linear progress of a transition to the eased progress.
+@group(0) @binding(12)
+var tex_easing: texture_1d<f32>;
+// The mask that is layered onto the cross-faded metering masks, by `mask_combine`.
+@group(0) @binding(13)
+var tex_mask_layer: texture_2d<f32>;
+
+#ifdef DEPTH_PREPASS
+struct DepthParams {
+    // The rect of `tex_depth` that covers the view, as (x, y, width, height).
+    viewport: vec4<u32>,
+    // Pixels with a depth at or below this aren't metered. The depth is reversed, so this
+    // excludes everything at or beyond a distance, including the sky at zero.
+    exclude_depth: f32,
+    // Pixels are weighed by their distance from `focus_distance`, which halves their weight
+    // every `focus_falloff`. A falloff of zero weighs every pixel evenly.
+    focus_distance: f32,
+    focus_falloff: f32,
+    // Maps a depth to the distance along the view direction, as
+    // `(x * depth + y) / (z * depth + w)`.
+    depth_to_distance: vec4<f32>,
+}
+
+@group(1) @binding(0)
+#ifdef DEPTH_MULTISAMPLED
+var tex_depth: texture_depth_multisampled_2d;
+#else
+var tex_depth: texture_depth_2d;
+#endif
+@group(1) @binding(1)
+var<uniform> depth_params: DepthParams;
+#endif
+
+var<workgroup> histogram_shared: array<atomic<u32>, #{HISTOGRAM_BINS}>;
+
+// The histogram pass runs a workgroup per tile of the view. Its invocations may be fewer or more
+// than the bins, so every invocation clears and flushes every `HISTOGRAM_INVOCATIONS`th bin.
+const HISTOGRAM_INVOCATIONS = #{HISTOGRAM_WORKGROUP_SIZE_X}u * #{HISTOGRAM_WORKGROUP_SIZE_Y}u;
 
-var<workgroup> histogram_shared: array<atomic<u32>, 256>;
+// The average pass needs the prefix sums over the whole histogram to trim it by percentile, so
+// it runs as a single workgroup. Its size is the bin count, capped at 256 invocations, which
+// every device supports. Every invocation reduces `BINS_PER_INVOCATION` consecutive bins.
+const AVERAGE_WORKGROUP_SIZE = #{AVERAGE_WORKGROUP_SIZE}u;
+const BINS_PER_INVOCATION = #{HISTOGRAM_BINS}u / AVERAGE_WORKGROUP_SIZE;
+
+var<workgroup> prefix_shared: array<u32, AVERAGE_WORKGROUP_SIZE>;
+var<workgroup> sum_shared: array<f32, AVERAGE_WORKGROUP_SIZE>;
+var<workgroup> count_shared: array<u32, AVERAGE_WORKGROUP_SIZE>;
+// The pixel counts weighed by `shadow_weight` and `highlight_weight`.
+var<workgroup> weight_shared: array<f32, AVERAGE_WORKGROUP_SIZE>;
+// The log luminance, normalized to the metering range, at the low and high percentile.
+var<workgroup> spread_shared: array<f32, 2>;
+// The log luminance, normalized to the metering range, at the ends of the dynamic range.
+var<workgroup> range_shared: array<f32, 2>;
+
+// The percentiles that the dynamic range of the scene is read back at. They don't depend on the
+// metering percentiles, so the range also shows what those trim off.
+const RANGE_LOW_PERCENT = 1u;
+const RANGE_HIGH_PERCENT = 99u;
+
+// The luminance of a linear color, by `params.perceptual_luminance`. The perceptual lightness is
+// cubed back into the units of luminance, so that the histogram and the average stay in log
+// luminance, and grays meter the same either way.
+fn pixelLuminance(color: vec3<f32>) -> f32 {
+    if params.perceptual_luminance != 0u {
+        let lms = pow(max(RGB_TO_LMS * color, vec3<f32>(0.0)), vec3<f32>(1.0 / 3.0));
+        let lightness = dot(lms, LMS_TO_LIGHTNESS);
+        return lightness * lightness * lightness;
+    }
+    return dot(color, RGB_TO_LUM);
+}
 
 // For a given color and luminance range, return the histogram bin index
 fn colorToBin(hdrColor: vec3<f32>, minLogLum: f32, inverseLogLumRange: f32) -> u32 {
-    let lum = dot(hdrColor, RGB_TO_LUM);
+    let lum = pixelLuminance(hdrColor);
 
     if lum < exp2(minLogLum) {
         return 0u;
@@ -38,63 +230,375 @@ fn colorToBin(hdrColor: vec3<f32>, minLogLum: f32, inverseLogLumRange: f32) -> u
     // where 0.0 represents the minimum luminance, and 1.0 represents the max.
     let logLum = saturate((log2(lum) - minLogLum) * inverseLogLumRange);
 
-    // Map [0, 1] to [1, 255]. The zeroth bin is handled by the epsilon check above.
-    return u32(logLum * 254.0 + 1.0);
+    // Map [0, 1] to [1, LAST_BIN]. The zeroth bin is handled by the epsilon check above.
+    return logLumToBin(logLum, params.bin_gamma);
+}
+
+// Samples the compensation curve at a log luminance, in EV. The curve is baked over the metering
+// range into texels 1 to 255, and texel 0 repeats the first one. Neighboring texels are
+// interpolated, so a wide range doesn't step the exposure.
+fn compensationAt(log_lum: f32) -> f32 {
+    let x = saturate((log_lum - params.min_log_lum) * params.inv_log_lum_range) * 254.0 + 1.0;
+    let texel = min(u32(x), 254u);
+    let low = textureLoad(tex_compensation, texel, 0).r;
+    let high = textureLoad(tex_compensation, texel + 1u, 0).r;
+    return mix(low, high, x - f32(texel));
+}
+
+// Samples the easing curve at a linear progress. The curve is baked over the progress from zero
+// to one into all 256 texels, and neighboring texels are interpolated.
+fn easingAt(progress: f32) -> f32 {
+    let x = saturate(progress) * 255.0;
+    let texel = min(u32(x), 254u);
+    let low = textureLoad(tex_easing, texel, 0).r;
+    let high = textureLoad(tex_easing, texel + 1u, 0).r;
+    return mix(low, high, x - f32(texel));
+}
+
+// Approximately undoes the tonemapping of an LDR image, so it has some dynamic range to meter.
+// This inverts the Reinhard curve, which the actual tonemapper only resembles, and can't recover
+// more than 8 EV above the white point of the clipped image.
+fn inverseTonemap(color: vec3<f32>) -> vec3<f32> {
+    let clipped = min(color, vec3<f32>(254.0 / 255.0));
+    return clipped / (1.0 - clipped);
+}
+
+// Transforms a color by the metering LUT, which is indexed by the log2 of every channel,
+// normalized to the metering range.
+fn applyLut(color: vec3<f32>) -> vec3<f32> {
+    let uvw = saturate((log2(max(color, vec3<f32>(1e-10))) - params.min_log_lum) * params.inv_log_lum_range);
+    return textureSampleLevel(tex_lut, lut_sampler, uvw, 0.0).rgb;
+}
+
+// Returns the texel of the radial weights at `uv`, for a view with the aspect ratio `aspect`. The
+// distance from the center is normalized so that the corners are at one.
+fn radialTexel(uv: vec2<f32>, aspect: f32) -> i32 {
+    let offset = (uv - 0.5) * vec2<f32>(aspect, 1.0);
+    let distance = length(offset) / (0.5 * length(vec2<f32>(aspect, 1.0)));
+    return i32(saturate(distance) * 255.0);
+}
+
+// Returns the weight of a spot and a spot rect at `uv`, for a view with the aspect ratio
+// `aspect`. A radius of zero leaves the spot out.
+fn spotWeight(uv: vec2<f32>, aspect: f32, center: vec2<f32>, radius: f32, rect: vec4<f32>) -> f32 {
+    var weight = 1.0;
+    if radius > 0.0 {
+        // The radius is relative to the view height, so correct for the aspect ratio.
+        let offset = (uv - center) * vec2<f32>(aspect, 1.0);
+        weight = select(0.0, 1.0, length(offset) <= radius);
+    }
+    return weight * select(0.0, 1.0, all(uv >= rect.xy) && all(uv <= rect.zw));
+}
+
+// Returns the weight of the metering mode at `uv`, cross-faded from the previous mode.
+fn modeWeight(uv: vec2<f32>, aspect: f32) -> f32 {
+    let texel = radialTexel(uv, aspect);
+    var weight = spotWeight(uv, aspect, params.spot_center, params.spot_radius, params.spot_rect);
+    if params.radial != 0u {
+        weight *= textureLoad(tex_radial, texel, 0).r;
+    }
+    if params.mode_blend < 1.0 {
+        let size = textureDimensions(tex_mode_from);
+        let from_texel = min(vec2<u32>(saturate(uv) * vec2<f32>(size)), size - 1u);
+        weight = mix(textureLoad(tex_mode_from, from_texel, 0).r, weight, params.mode_blend);
+    }
+    return weight;
+}
+
+// Whether `uv` lies within one of the exclusion rects.
+fn isExcluded(uv: vec2<f32>) -> bool {
+    for (var i = 0u; i < params.exclusion_count; i += 1u) {
+        let rect = params.exclusion_rects[i];
+        if all(uv >= rect.xy) && all(uv <= rect.zw) {
+            return true;
+        }
+    }
+    return false;
+}
+
+// Samples the metering mask at `uv`, cross-faded to the blend mask, combined with the mask layer,
+// and scaled by the gain and bias.
+fn meteringMask(view_uv: vec2<f32>) -> f32 {
+    let uv = params.mask_region.xy + view_uv * params.mask_region.zw;
+    let mask = textureLoad(tex_mask, vec2<i32>(uv * vec2<f32>(textureDimensions(tex_mask))), 0).r;
+    let mask_blend = textureLoad(tex_mask_blend, vec2<i32>(uv * vec2<f32>(textureDimensions(tex_mask_blend))), 0).r;
+    var weight = mix(mask, mask_blend, params.mask_blend);
+    // The layer covers the whole view, regardless of the mask region.
+    let layer = textureLoad(tex_mask_layer, vec2<i32>(view_uv * vec2<f32>(textureDimensions(tex_mask_layer))), 0).r;
+    switch params.mask_combine {
+        case 1u: {
+            weight *= layer;
+        }
+        case 2u: {
+            weight += layer;
+        }
+        case 3u: {
+            weight = max(weight, layer);
+        }
+        default: {}
+    }
+    return saturate(weight * params.mask_gain + params.mask_bias);
 }
 
-@compute @workgroup_size(16, 16, 1)
+@compute @workgroup_size(#{HISTOGRAM_WORKGROUP_SIZE_X}, #{HISTOGRAM_WORKGROUP_SIZE_Y}, 1)
 fn computeHistogram(
     @builtin(global_invocation_id) global_invocation_id: vec3<u32>,
     @builtin(local_invocation_index) local_invocation_index: u32
 ) {
-    histogram_shared[local_invocation_index] = 0u;
-    storageBarrier();
+    for (var bin = local_invocation_index; bin < #{HISTOGRAM_BINS}u; bin += HISTOGRAM_INVOCATIONS) {
+        atomicStore(&histogram_shared[bin], 0u);
+    }
+    workgroupBarrier();
 
-    let dim = vec2<u32>(textureDimensions(tex_color));
-    let uv = vec2<f32>(global_invocation_id.xy) / vec2<f32>(dim);
+    // Only meter the viewport, which doesn't have to start at the origin of the texture.
+    let dim = params.viewport.zw;
+    let pixel = global_invocation_id.xy * params.sample_stride;
+    let uv = vec2<f32>(pixel) / vec2<f32>(dim);
 
-    if global_invocation_id.x < dim.x && global_invocation_id.y < dim.y {
-        let col = textureLoad(tex_color, vec2<i32>(global_invocation_id.xy), 0).rgb;
+    if pixel.x < dim.x && pixel.y < dim.y {
+        let color = textureLoad(tex_color, vec2<i32>(params.viewport.xy + pixel), 0);
+#ifdef INVERSE_TONEMAP
+        let rendered = inverseTonemap(color.rgb);
+#else
+        let rendered = color.rgb;
+#endif
+        let col = rendered * exp2(-params.camera_exposure);
+#ifdef METERING_LUT
+        let index = colorToBin(applyLut(col), params.min_log_lum, params.inv_log_lum_range);
+#else
         let index = colorToBin(col, params.min_log_lum, params.inv_log_lum_range);
-        let mask = textureLoad(tex_mask, vec2<i32>(uv * vec2<f32>(textureDimensions(tex_mask))), 0).r;
+#endif
+        var mask = meteringMask(uv);
+
+        if params.weight_by_alpha != 0u {
+            mask *= saturate(color.a);
+        }
+
+        mask *= modeWeight(uv, f32(dim.x) / f32(dim.y));
+        mask *= select(1.0, 0.0, isExcluded(uv));
+
+        // Leave saturated pixels out entirely, instead of counting them in the top bin.
+        if log2(pixelLuminance(rendered)) > params.saturation_cutoff {
+            mask = 0.0;
+        }
+
+#ifdef DEPTH_PREPASS
+        // The metered texture may be downsampled, so find the pixel in the depth texture by uv.
+        // Of a multisampled depth texture, the first sample is used.
+        let depth_pixel = depth_params.viewport.xy + vec2<u32>(uv * vec2<f32>(depth_params.viewport.zw));
+        let depth = textureLoad(tex_depth, depth_pixel, 0);
+        if depth <= depth_params.exclude_depth {
+            mask = 0.0;
+        }
+        if depth_params.focus_falloff > 0.0 {
+            // The sky at a depth of zero is infinitely far away, which the clamp keeps finite.
+            let to_distance = depth_params.depth_to_distance;
+            let distance = (to_distance.x * depth + to_distance.y) / max(to_distance.z * depth + to_distance.w, 1e-20);
+            mask *= exp2(-abs(distance - depth_params.focus_distance) / depth_params.focus_falloff);
+        }
+#endif
 
         atomicAdd(&histogram_shared[index], u32(mask * 8.0));
     }
 
     workgroupBarrier();
-    atomicAdd(&histogram[local_invocation_index], histogram_shared[local_invocation_index]);
+    for (var bin = local_invocation_index; bin < #{HISTOGRAM_BINS}u; bin += HISTOGRAM_INVOCATIONS) {
+        atomicAdd(&histogram[bin], atomicLoad(&histogram_shared[bin]));
+    }
 }
 
-@compute @workgroup_size(1, 1, 1)
+@compute @workgroup_size(AVERAGE_WORKGROUP_SIZE, 1, 1)
 fn computeAverage(@builtin(local_invocation_index) local_index: u32) {
-    var histogram_sum = 0u;
-    for (var i=0u; i<256u; i+=1u) {
-        histogram_sum += histogram[i];
-        histogram_shared[i] = histogram_sum;
-        histogram[i] = 0u;
+    let first_bin = local_index * BINS_PER_INVOCATION;
+
+    var bins_sum = 0u;
+    for (var i = 0u; i < BINS_PER_INVOCATION; i += 1u) {
+        bins_sum += blendBin(first_bin + i);
     }
+    prefix_shared[local_index] = bins_sum;
+    workgroupBarrier();
 
+    // Inclusive prefix sum over the invocations. Every step reads before the barrier and
+    // writes after it, so no invocation reads a value that was already updated in this step.
+    for (var offset = 1u; offset < AVERAGE_WORKGROUP_SIZE; offset *= 2u) {
+        var value = prefix_shared[local_index];
+        if local_index >= offset {
+            value += prefix_shared[local_index - offset];
+        }
+        workgroupBarrier();
+        prefix_shared[local_index] = value;
+        workgroupBarrier();
+    }
+
+    let histogram_sum = prefix_shared[AVERAGE_WORKGROUP_SIZE - 1u];
     let first_index = histogram_sum * params.low_percent / 100u;
     let last_index = histogram_sum * params.high_percent / 100u;
+    let range_first = histogram_sum * RANGE_LOW_PERCENT / 100u;
+    let range_last = histogram_sum * RANGE_HIGH_PERCENT / 100u;
 
+    // Sum the bins of this invocation that fall within the percentiles. The first bin holds
+    // the pixels below the metering range, which are never metered.
+    var previous = prefix_shared[local_index] - bins_sum;
     var count = 0u;
     var sum = 0.0;
-    for (var i=1u; i<256u; i+=1u) {
-        let bin_count =
-            clamp(histogram_shared[i], first_index, last_index) -
-            clamp(histogram_shared[i - 1u], first_index, last_index);
+    var weight = 0.0;
+    let split = (params.tone_split - params.min_log_lum) * params.inv_log_lum_range;
+    for (var i = 0u; i < BINS_PER_INVOCATION; i += 1u) {
+        let bin = first_bin + i;
+        let current = previous + atomicLoad(&histogram[bin]);
+        atomicStore(&histogram[bin], 0u);
+
+        // Exactly one bin holds each percentile, so only one invocation writes each end.
+        if previous <= first_index && first_index < current {
+            spread_shared[0] = binToLogLum(f32(bin), params.bin_gamma);
+        }
+        if previous < last_index && last_index <= current {
+            spread_shared[1] = binToLogLum(f32(bin), params.bin_gamma);
+        }
+        if previous <= range_first && range_first < current {
+            range_shared[0] = binToLogLum(f32(bin), params.bin_gamma);
+        }
+        if previous < range_last && range_last <= current {
+            range_shared[1] = binToLogLum(f32(bin), params.bin_gamma);
+        }
+
+        if bin > 0u {
+            let bin_count =
+                clamp(current, first_index, last_index) -
+                clamp(previous, first_index, last_index);
 
-        sum += f32(bin_count) * f32(i);
-        count += bin_count;
+            let position = percentileBin(bin, previous, current, first_index, last_index, params.interpolate_percentiles != 0u);
+            let log_lum = binToLogLum(position, params.bin_gamma);
+            // The shadows and the highlights are weighed apart, as if they were two histograms
+            // that are averaged together.
+            let tone_weight = select(params.highlight_weight, params.shadow_weight, log_lum < split);
+            sum += tone_weight * f32(bin_count) * log_lum;
+            weight += tone_weight * f32(bin_count);
+            count += bin_count;
+        }
+        previous = current;
     }
+    sum_shared[local_index] = sum;
+    count_shared[local_index] = count;
+    weight_shared[local_index] = weight;
+    workgroupBarrier();
 
-    var target_exposure = 0.0;
+    // Reduce the sums in a fixed order, so the float sum is the same every frame.
+    for (var stride = 1u; stride < AVERAGE_WORKGROUP_SIZE; stride *= 2u) {
+        if local_index % (stride * 2u) == 0u && local_index + stride < AVERAGE_WORKGROUP_SIZE {
+            sum_shared[local_index] += sum_shared[local_index + stride];
+            count_shared[local_index] += count_shared[local_index + stride];
+            weight_shared[local_index] += weight_shared[local_index + stride];
+        }
+        workgroupBarrier();
+    }
 
-    if count > 0u {
-        let avg_bin = sum / f32(count);
-        let avg_lum = avg_bin / 255.0 * params.log_lum_range + params.min_log_lum;
-        target_exposure += -8.0 + textureLoad(tex_compensation, i32(avg_bin), 0).r * 16.0 - avg_lum;
+    if local_index != 0u {
+        return;
     }
 
-    result = result + clamp(target_exposure - result, -params.speed_up, params.speed_down);
+    count = count_shared[0];
+    sum = sum_shared[0];
+    // With even weights, the sum is divided by the exact pixel count, like a single histogram.
+    let even = params.shadow_weight == 1.0 && params.highlight_weight == 1.0;
+    weight = select(weight_shared[0], f32(count), even);
+
+    // Nothing was metered, for example because the metering mask is black, or every metered
+    // pixel has a weight of zero. Hold the exposure instead of adapting to an arbitrary target.
+    if count == 0u || weight <= 0.0 {
+        updateEnvironment();
+        return;
+    }
+    state.metered_frames = select(state.metered_frames + 1.0, 1.0, state.metered_frames >= 65535.0);
+
+    let avg_lum = sum / weight * params.log_lum_range + params.min_log_lum;
+    state.average = avg_lum;
+    state.range_low = range_shared[0] * params.log_lum_range + params.min_log_lum;
+    state.range_high = range_shared[1] * params.log_lum_range + params.min_log_lum;
+
+    // Expose scenes darker than the floor as if they were at it.
+    let target_lum = max(avg_lum, params.dark_floor);
+    let compensation = compensationAt(target_lum);
+
+    // Offset the exposure by how far the average leans towards either end of the spread, to
+    // keep the key of the scene.
+    let spread_middle = (spread_shared[0] + spread_shared[1]) * 0.5 * params.log_lum_range + params.min_log_lum;
+    let key = params.key_strength * (avg_lum - spread_middle);
+
+    // With an absolute target, the metered average only drives the compensation curve and the
+    // key, and the calibrated luminance is exposed as mid gray.
+    let exposed_lum = select(target_lum, params.target_log_lum, params.absolute_target != 0u);
+    let target_exposure = min(
+        select(compensation - exposed_lum, compensation, params.compensation_override != 0u) + key + params.correction,
+        params.max_gain,
+    );
+
+    if params.reset != 0u {
+        state.exposure = target_exposure;
+        state.transition_progress = 1.0;
+        updateEnvironment();
+        return;
+    }
+
+    let delta = target_exposure - state.exposure;
+    if abs(delta) <= params.deadband {
+        updateEnvironment();
+        return;
+    }
+
+    if params.easing == 0u {
+        // Approach the target exponentially, which is independent of the frame rate and never
+        // overshoots, no matter how long the frame took.
+        let speed = select(params.speed_up, params.speed_down, delta > 0.0);
+        let step = delta * (1.0 - exp(-speed * params.delta_time));
+        state.exposure = state.exposure + clamp(step, -params.max_step, params.max_step);
+        state.transition_progress = 1.0;
+    } else {
+        adaptEased(target_exposure);
+    }
+    updateEnvironment();
+}
+
+// Moves the exposure along the easing curve, from where the transition started towards its
+// target. The linear progress approaches one like the exponential adaptation approaches the
+// target, so a linear curve adapts the same way while the target holds still.
+fn adaptEased(target_exposure: f32) {
+    // A new transition starts once the last one is done, when the target turns around, or when
+    // it moves further than the exposure has left to go. Smaller moves of the target are caught
+    // up with by the next transition.
+    let remaining = state.transition_target - state.exposure;
+    let turned = (target_exposure - state.exposure) * (state.transition_target - state.transition_start) <= 0.0;
+    if state.transition_progress >= 1.0 || turned || abs(target_exposure - state.transition_target) > abs(remaining) {
+        state.transition_start = state.exposure;
+        state.transition_target = target_exposure;
+        state.transition_progress = 0.0;
+    }
+
+    let delta = state.transition_target - state.transition_start;
+    let speed = select(params.speed_up, params.speed_down, delta > 0.0);
+    let progress = mix(state.transition_progress, 1.0, 1.0 - exp(-speed * params.delta_time));
+    let eased = state.transition_start + easingAt(progress) * delta;
+    state.exposure = state.exposure + clamp(eased - state.exposure, -params.max_step, params.max_step);
+    state.transition_progress = progress;
+}
+
+// Blends a bin of the histogram with its history, and keeps the result as the history of the
+// next frame. The blended count replaces the count of the bin.
+fn blendBin(bin: u32) -> u32 {
+    let count = atomicLoad(&histogram[bin]);
+    if params.temporal_blend <= 0.0 || params.reset != 0u {
+        histogram_history[bin] = f32(count);
+        return count;
+    }
+
+    let blended = mix(f32(count), histogram_history[bin], params.temporal_blend);
+    histogram_history[bin] = blended;
+    let blended_count = u32(round(blended));
+    atomicStore(&histogram[bin], blended_count);
+    return blended_count;
+}
+
+// Derives the environment exposure from the adapted exposure.
+fn updateEnvironment() {
+    state.environment = state.exposure * params.environment_scale + params.environment_correction;
 }
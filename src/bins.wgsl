@@ -0,0 +1,38 @@
+#define_import_path bevy_mod_auto_exposure::bins
+
+// The index of the last histogram bin.
+const LAST_BIN = #{HISTOGRAM_BINS}u - 1u;
+
+// Maps a log luminance, normalized to the metering range, to its histogram bin in
+// [1, LAST_BIN]. The zeroth bin is reserved for pixels below the metering range.
+//
+// The bins are spaced by `gamma` along the log luminance: at one they are evenly spaced, below
+// one more bins cover the darker part of the range, and above one the brighter part.
+fn logLumToBin(log_lum: f32, gamma: f32) -> u32 {
+    return u32(pow(log_lum, gamma) * f32(LAST_BIN - 1u) + 1.0);
+}
+
+// Maps a histogram bin back to the log luminance it starts at, normalized to the metering range.
+// This is the inverse of `logLumToBin`, so the average of a histogram is taken in log luminance,
+// whatever the spacing of its bins. The zeroth bin, and positions below the first bin, map to
+// zero.
+fn binToLogLum(bin: f32, gamma: f32) -> f32 {
+    return pow(max(bin - 1.0, 0.0) / f32(LAST_BIN - 1u), 1.0 / gamma);
+}
+
+// Returns the bin to average the pixels of `bin` at that lie within the percentiles, which
+// are counted from `first_index` to `last_index`. `previous` counts the pixels of the bins
+// below `bin`, and `current` of those and `bin` itself.
+//
+// Without `interpolate`, that's the bin itself. With it, the pixels are assumed to be spread
+// evenly over the bin, so a bin that the percentiles cut off partially is averaged at the
+// middle of the part that is kept, relative to the middle of the whole bin.
+fn percentileBin(bin: u32, previous: u32, current: u32, first_index: u32, last_index: u32, interpolate: bool) -> f32 {
+    if !interpolate || current == previous {
+        return f32(bin);
+    }
+    let count = f32(current - previous);
+    let low = f32(clamp(first_index, previous, current) - previous) / count;
+    let high = f32(clamp(last_index, previous, current) - previous) / count;
+    return f32(bin) + (low + high) * 0.5 - 0.5;
+}
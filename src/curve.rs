@@ -0,0 +1,102 @@
+use bevy::{math::vec2, prelude::*};
+
+/// An exposure compensation curve asset, for [`AutoExposure::compensation_curve_asset`].
+///
+/// Cameras using the curve re-bake it when the asset changes, so it can be tweaked at runtime
+/// through [`Assets<CompensationCurve>`], or by editing a `.curve.ron` file with asset hot
+/// reloading. Loading files requires the `serde` feature. A curve file looks like this:
+///
+/// ```ron
+/// (points: [(-16.0, -4.0), (0.0, 0.0), (16.0, 0.0)])
+/// ```
+///
+/// [`AutoExposure::compensation_curve_asset`]: crate::AutoExposure::compensation_curve_asset
+#[derive(Asset, Clone, Debug, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CompensationCurve {
+    /// The points of the curve, see [`AutoExposure::compensation_curve`].
+    ///
+    /// [`AutoExposure::compensation_curve`]: crate::AutoExposure::compensation_curve
+    pub points: Vec<Vec2>,
+}
+
+impl Default for CompensationCurve {
+    fn default() -> Self {
+        Self {
+            points: vec![vec2(-8.0, 0.0), vec2(8.0, 0.0)],
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+pub use loader::*;
+
+#[cfg(feature = "serde")]
+mod loader {
+    use std::fmt;
+
+    use bevy::{
+        asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+        utils::BoxedFuture,
+    };
+
+    use super::CompensationCurve;
+
+    /// Loads a [`CompensationCurve`] from a `.curve.ron` file.
+    #[derive(Default)]
+    pub struct CompensationCurveLoader;
+
+    /// An error that occurred while loading a [`CompensationCurve`].
+    #[non_exhaustive]
+    #[derive(Debug)]
+    pub enum CompensationCurveLoaderError {
+        /// The file couldn't be read.
+        Io(std::io::Error),
+        /// The file isn't a valid curve.
+        Ron(ron::error::SpannedError),
+    }
+
+    impl fmt::Display for CompensationCurveLoaderError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Self::Io(error) => write!(f, "could not read compensation curve: {error}"),
+                Self::Ron(error) => write!(f, "invalid compensation curve: {error}"),
+            }
+        }
+    }
+
+    impl std::error::Error for CompensationCurveLoaderError {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            match self {
+                Self::Io(error) => Some(error),
+                Self::Ron(error) => Some(error),
+            }
+        }
+    }
+
+    impl AssetLoader for CompensationCurveLoader {
+        type Asset = CompensationCurve;
+        type Settings = ();
+        type Error = CompensationCurveLoaderError;
+
+        fn load<'a>(
+            &'a self,
+            reader: &'a mut Reader,
+            _settings: &'a (),
+            _load_context: &'a mut LoadContext,
+        ) -> BoxedFuture<'a, Result<CompensationCurve, Self::Error>> {
+            Box::pin(async move {
+                let mut bytes = Vec::new();
+                reader
+                    .read_to_end(&mut bytes)
+                    .await
+                    .map_err(CompensationCurveLoaderError::Io)?;
+                ron::de::from_bytes(&bytes).map_err(CompensationCurveLoaderError::Ron)
+            })
+        }
+
+        fn extensions(&self) -> &[&str] {
+            &["curve.ron"]
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Debug and diagnostic tools, compiled only with the `debug` feature so shipping builds keep
+//! just the metering path.
+//!
+//! Without the feature, these APIs don't exist:
+//! - [`AutoExposureDebugOverlay`] and [`AutoExposureCurveOverlay`], with their render nodes;
+//! - [`HistogramReadback`] and [`HistogramReadbackEvent`];
+//! - [`AutoExposureGizmos`], which also needs the `debug-gizmos` feature;
+//! - [`AutoExposureDebugText`] and [`DebugTextCorner`], which also need the `debug-text` feature.
+//!
+//! [`MeteredLuminance`](crate::MeteredLuminance), [`ExposureSettled`](crate::ExposureSettled)
+//! and [`AutoExposureDiagnosticsPlugin`](crate::AutoExposureDiagnosticsPlugin) stay, because
+//! they are cheap and meant for gameplay and profiling in shipping builds too.
+
+use bevy::{
+    ecs::{query::QueryItem, system::lifetimeless::Read},
+    prelude::*,
+    render::render_resource::Buffer,
+};
+
+mod curve;
+#[cfg(feature = "debug-gizmos")]
+mod gizmos;
+mod overlay;
+mod readback;
+#[cfg(feature = "debug-text")]
+mod text;
+
+pub use curve::AutoExposureCurveOverlay;
+#[cfg(feature = "debug-gizmos")]
+pub use gizmos::AutoExposureGizmos;
+pub use overlay::AutoExposureDebugOverlay;
+pub use readback::{HistogramReadback, HistogramReadbackEvent};
+#[cfg(feature = "debug-text")]
+pub use text::{AutoExposureDebugText, DebugTextCorner};
+
+use curve::DebugCurvePlugin;
+use overlay::{DebugOverlayPlugin, ViewDebugHistogram};
+use readback::{HistogramReadbackPlugin, ViewHistogramReadbackBuffer};
+
+/// Adds the debug tools of the enabled features.
+pub(crate) struct DebugPlugin;
+
+impl Plugin for DebugPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            DebugOverlayPlugin,
+            DebugCurvePlugin,
+            HistogramReadbackPlugin,
+        ));
+        #[cfg(feature = "debug-gizmos")]
+        app.add_plugins(gizmos::GizmosPlugin);
+        #[cfg(feature = "debug-text")]
+        app.add_plugins(text::DebugTextPlugin);
+    }
+}
+
+/// The buffers of a view that the metering node copies the histogram to for debugging, before
+/// it is cleared.
+pub(crate) type DebugHistogramCopies = (
+    Option<Read<ViewDebugHistogram>>,
+    Option<Read<ViewHistogramReadbackBuffer>>,
+);
+
+pub(crate) fn histogram_copies<'w>(
+    (overlay, readback): QueryItem<'w, DebugHistogramCopies>,
+) -> impl Iterator<Item = &'w Buffer> {
+    overlay
+        .map(|histogram| &histogram.buffer)
+        .into_iter()
+        .chain(readback.map(|readback| &readback.buffer))
+}
@@ -0,0 +1,326 @@
+use std::num::NonZeroU64;
+
+use bevy::{
+    asset::load_internal_asset,
+    core_pipeline::{
+        core_3d::graph::{Core3d, Node3d},
+        fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    },
+    ecs::{
+        query::{QueryItem, QueryState},
+        system::lifetimeless::Read,
+    },
+    prelude::*,
+    render::{
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        render_graph::{Node, NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        view::{ExtractedView, ViewTarget},
+        Render, RenderApp, RenderSet,
+    },
+};
+
+use crate::{
+    compute_supported,
+    pipeline::{ViewAutoExposurePipeline, STATE_SIZE},
+    AutoExposure,
+};
+
+/// Component that draws the compensation curve of an [`AutoExposure`] camera as a line graph
+/// overlay, to see what the curve does to the scene that is currently metered.
+///
+/// The horizontal axis spans the metered luminance from `min` to `max`, and the vertical axis
+/// the output of the curve, which is a compensation or the exposure itself, depending on the
+/// [`CompensationMode`](crate::CompensationMode). The vertical axis is fitted to the curve, and
+/// zero is marked by a gray line. A red dot marks where the metered luminance currently sits on
+/// the curve.
+///
+/// The overlay isn't drawn on devices without compute shaders.
+#[derive(Component, Clone, Reflect)]
+#[reflect(Component)]
+pub struct AutoExposureCurveOverlay {
+    /// The screen rect to draw the overlay in, in normalized screen coordinates
+    /// (`0..=1`, top left is zero).
+    pub rect: Rect,
+    /// The opacity of the overlay.
+    pub opacity: f32,
+}
+
+impl Default for AutoExposureCurveOverlay {
+    fn default() -> Self {
+        // Below the default rect of the histogram overlay, so both can be shown at once.
+        Self {
+            rect: Rect::new(0.02, 0.24, 0.32, 0.44),
+            opacity: 0.8,
+        }
+    }
+}
+
+impl ExtractComponent for AutoExposureCurveOverlay {
+    type QueryData = Read<Self>;
+    type QueryFilter = (With<Camera>, With<AutoExposure>);
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::QueryData>) -> Option<Self> {
+        Some(item.clone())
+    }
+}
+
+const CURVE_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(5209713846215390417);
+
+/// Draws the compensation curve overlays.
+pub(crate) struct DebugCurvePlugin;
+
+impl Plugin for DebugCurvePlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(app, CURVE_SHADER_HANDLE, "curve.wgsl", Shader::from_wgsl);
+
+        app.register_type::<AutoExposureCurveOverlay>();
+        app.add_plugins(ExtractComponentPlugin::<AutoExposureCurveOverlay>::default());
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<SpecializedRenderPipelines<DebugCurvePipeline>>()
+            .add_systems(
+                Render,
+                queue_debug_curve_pipelines
+                    .in_set(RenderSet::Queue)
+                    .run_if(resource_exists::<DebugCurvePipeline>),
+            )
+            .add_render_graph_node::<DebugCurveNode>(Core3d, DebugCurveOverlay)
+            .add_render_graph_edges(
+                Core3d,
+                (
+                    Node3d::Tonemapping,
+                    DebugCurveOverlay,
+                    Node3d::EndMainPassPostProcessing,
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        // The overlay reads the exposure state from a storage buffer, like the histogram overlay.
+        if compute_supported(&render_app.world) {
+            render_app.init_resource::<DebugCurvePipeline>();
+        }
+    }
+}
+
+#[derive(Component)]
+struct ViewDebugCurvePipeline(CachedRenderPipelineId);
+
+#[derive(ShaderType)]
+struct DebugCurveParams {
+    opacity: f32,
+    min_log_lum: f32,
+    log_lum_range: f32,
+    dark_floor: f32,
+    size: Vec2,
+}
+
+#[derive(Resource)]
+struct DebugCurvePipeline {
+    layout: BindGroupLayout,
+}
+
+impl FromWorld for DebugCurvePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        Self {
+            layout: render_device.create_bind_group_layout(
+                "auto exposure debug curve bind group",
+                &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: Some(DebugCurveParams::min_size()),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D1,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: NonZeroU64::new(STATE_SIZE),
+                        },
+                        count: None,
+                    },
+                ],
+            ),
+        }
+    }
+}
+
+impl SpecializedRenderPipeline for DebugCurvePipeline {
+    /// The format and the sample count of the view's main texture.
+    type Key = (TextureFormat, u32);
+
+    fn specialize(&self, (format, samples): Self::Key) -> RenderPipelineDescriptor {
+        RenderPipelineDescriptor {
+            label: Some("auto exposure debug curve pipeline".into()),
+            layout: vec![self.layout.clone()],
+            push_constant_ranges: vec![],
+            vertex: fullscreen_shader_vertex_state(),
+            primitive: default(),
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: samples,
+                ..default()
+            },
+            fragment: Some(FragmentState {
+                shader: CURVE_SHADER_HANDLE,
+                shader_defs: vec![],
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+        }
+    }
+}
+
+fn queue_debug_curve_pipelines(
+    mut commands: Commands,
+    pipeline_cache: Res<PipelineCache>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<DebugCurvePipeline>>,
+    pipeline: Res<DebugCurvePipeline>,
+    msaa: Res<Msaa>,
+    views: Query<(Entity, &ViewTarget), With<AutoExposureCurveOverlay>>,
+) {
+    for (entity, view_target) in views.iter() {
+        let id = pipelines.specialize(
+            &pipeline_cache,
+            &pipeline,
+            (view_target.main_texture_format(), msaa.samples()),
+        );
+        commands.entity(entity).insert(ViewDebugCurvePipeline(id));
+    }
+}
+
+#[derive(RenderLabel, Debug, Clone, Hash, PartialEq, Eq)]
+struct DebugCurveOverlay;
+
+type DebugCurveQuery = (
+    Read<ViewTarget>,
+    Read<ExtractedView>,
+    Read<AutoExposureCurveOverlay>,
+    Read<ViewDebugCurvePipeline>,
+    Read<ViewAutoExposurePipeline>,
+);
+
+struct DebugCurveNode {
+    query: QueryState<DebugCurveQuery>,
+}
+
+impl FromWorld for DebugCurveNode {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            query: QueryState::new(world),
+        }
+    }
+}
+
+impl Node for DebugCurveNode {
+    fn update(&mut self, world: &mut World) {
+        self.query.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let Ok((view_target, view, overlay, curve_pipeline, auto_exposure)) =
+            self.query.get_manual(world, graph.view_entity())
+        else {
+            return Ok(());
+        };
+
+        let Some(render_pipeline) = world
+            .resource::<PipelineCache>()
+            .get_render_pipeline(curve_pipeline.0)
+        else {
+            return Ok(());
+        };
+
+        // Draw the overlay by restricting the fullscreen triangle to the overlay rect.
+        let origin = view.viewport.xy().as_vec2();
+        let size = view.viewport.zw().as_vec2();
+        let min = origin + (overlay.rect.min.clamp(Vec2::ZERO, Vec2::ONE) * size).floor();
+        let max = origin + (overlay.rect.max.clamp(Vec2::ZERO, Vec2::ONE) * size).ceil();
+        if min.x >= max.x || min.y >= max.y {
+            return Ok(());
+        }
+
+        let mut params = encase::UniformBuffer::new(Vec::new());
+        params
+            .write(&DebugCurveParams {
+                opacity: overlay.opacity,
+                min_log_lum: auto_exposure.params.min_log_lum,
+                log_lum_range: auto_exposure.params.log_lum_range,
+                dark_floor: auto_exposure.params.dark_floor,
+                size: max - min,
+            })
+            .unwrap();
+        let params =
+            render_context
+                .render_device()
+                .create_buffer_with_data(&BufferInitDescriptor {
+                    label: None,
+                    contents: params.as_ref(),
+                    usage: BufferUsages::UNIFORM,
+                });
+
+        let bind_group = render_context.render_device().create_bind_group(
+            None,
+            &world.resource::<DebugCurvePipeline>().layout,
+            &BindGroupEntries::sequential((
+                params.as_entire_binding(),
+                &auto_exposure.compensation_curve,
+                auto_exposure.state.as_entire_binding(),
+            )),
+        );
+
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("auto_exposure_debug_curve_pass"),
+            color_attachments: &[Some(view_target.get_color_attachment())],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        render_pass.set_viewport(min.x, min.y, max.x - min.x, max.y - min.y, 0.0, 1.0);
+        render_pass.set_render_pipeline(render_pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        Ok(())
+    }
+}
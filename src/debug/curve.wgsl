@@ -0,0 +1,92 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+struct Params {
+    opacity: f32,
+    min_log_lum: f32,
+    log_lum_range: f32,
+    dark_floor: f32,
+    // The size of the overlay, in pixels.
+    size: vec2<f32>,
+}
+
+struct State {
+    exposure: f32,
+    average: f32,
+    environment: f32,
+    padding: f32,
+    range_low: f32,
+    range_high: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var tex_compensation: texture_1d<f32>;
+@group(0) @binding(2)
+var<storage, read> state: State;
+
+const BACKGROUND = vec4<f32>(0.0, 0.0, 0.0, 0.5);
+const ZERO = vec4<f32>(0.4, 0.4, 0.4, 1.0);
+const CURVE = vec4<f32>(0.8, 0.8, 0.8, 1.0);
+const POINT = vec4<f32>(1.0, 0.0, 0.0, 1.0);
+
+// Half the width of the curve, and the radius of the operating point, in pixels.
+const LINE_WIDTH = 1.0;
+const POINT_RADIUS = 4.0;
+
+// Returns the baked curve at `x` in `0..=1`, which spans the metering range, the same way the
+// metering pass looks it up.
+fn curve(x: f32) -> f32 {
+    let texel_x = saturate(x) * 254.0 + 1.0;
+    let texel = min(u32(texel_x), 254u);
+    let low = textureLoad(tex_compensation, texel, 0).r;
+    let high = textureLoad(tex_compensation, texel + 1u, 0).r;
+    return mix(low, high, texel_x - f32(texel));
+}
+
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    // Fit the vertical axis to the curve, keeping at least 1 EV above and below zero.
+    var low = -1.0;
+    var high = 1.0;
+    for (var i = 0; i < 256; i += 1) {
+        let value = textureLoad(tex_compensation, i, 0).r;
+        low = min(low, value);
+        high = max(high, value);
+    }
+    let margin = (high - low) * 0.05;
+    low -= margin;
+    high += margin;
+
+    // Works in pixels, with the y axis pointing up.
+    let pixel = vec2<f32>(in.uv.x, 1.0 - in.uv.y) * params.size;
+    let to_pixel_y = params.size.y / (high - low);
+
+    var color = BACKGROUND;
+
+    if abs(pixel.y + low * to_pixel_y) <= 0.5 {
+        color = ZERO;
+    }
+
+    // Cover everything the curve passes through between the neighboring pixels, so steep parts
+    // of the curve don't leave gaps.
+    let texel = 1.0 / params.size.x;
+    let left = (curve(in.uv.x - texel) - low) * to_pixel_y;
+    let right = (curve(in.uv.x + texel) - low) * to_pixel_y;
+    let center = (curve(in.uv.x) - low) * to_pixel_y;
+    let line_low = min(center, min(left, right)) - LINE_WIDTH;
+    let line_high = max(center, max(left, right)) + LINE_WIDTH;
+    if pixel.y >= line_low && pixel.y <= line_high {
+        color = CURVE;
+    }
+
+    // The curve is looked up at the target luminance, which the dark floor can raise.
+    let target_lum = max(state.average, params.dark_floor);
+    let x = saturate((target_lum - params.min_log_lum) / params.log_lum_range);
+    let operating_point = vec2<f32>(x * params.size.x, (curve(x) - low) * to_pixel_y);
+    if distance(pixel, operating_point) <= POINT_RADIUS {
+        color = POINT;
+    }
+
+    return vec4<f32>(color.rgb, color.a * params.opacity);
+}
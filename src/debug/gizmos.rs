@@ -0,0 +1,167 @@
+use bevy::{math::vec3, prelude::*};
+
+use crate::{sample_curve, AutoExposure, MeteringFocus, MeteringMode};
+
+/// Component that outlines the metered region of an [`AutoExposure`] camera with gizmos.
+///
+/// Spot metering is drawn as a circle or a rectangle on the view. Center weighted and radial
+/// metering are drawn as rings at a few distances from the center, faded by the weight at their
+/// distance. Nothing is drawn when the whole view is metered. Gizmos are visible to every camera that renders them, so the outline is drawn just
+/// in front of this camera's near plane, where other cameras are unlikely to see it.
+#[derive(Component, Clone, Reflect)]
+#[reflect(Component)]
+pub struct AutoExposureGizmos {
+    /// The color of the outline.
+    pub color: Color,
+}
+
+impl Default for AutoExposureGizmos {
+    fn default() -> Self {
+        Self {
+            color: Color::YELLOW,
+        }
+    }
+}
+
+/// Draws the metering gizmos.
+pub(crate) struct GizmosPlugin;
+
+impl Plugin for GizmosPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<AutoExposureGizmos>()
+            .add_systems(PostUpdate, draw_metering_gizmos);
+    }
+}
+
+fn draw_metering_gizmos(
+    mut gizmos: Gizmos,
+    cameras: Query<(
+        &Camera,
+        &GlobalTransform,
+        &AutoExposure,
+        &AutoExposureGizmos,
+        Option<&MeteringFocus>,
+    )>,
+) {
+    for (camera, transform, auto_exposure, settings, focus) in cameras.iter() {
+        if !camera.is_active || !auto_exposure.enabled {
+            continue;
+        }
+
+        let metering_mode = match focus {
+            Some(focus) => focus
+                .project(camera.projection_matrix(), transform)
+                .unwrap_or(MeteringMode::Average),
+            None => auto_exposure.metering_mode.clone(),
+        };
+
+        match metering_mode {
+            MeteringMode::Spot { center, radius } => {
+                draw_spot(
+                    &mut gizmos,
+                    camera,
+                    transform,
+                    center,
+                    radius,
+                    settings.color,
+                );
+            }
+            MeteringMode::SpotRect { rect } => {
+                draw_spot_rect(&mut gizmos, camera, transform, rect, settings.color);
+            }
+            MeteringMode::CenterWeighted { .. } | MeteringMode::Radial { .. } => {
+                let curve = metering_mode.radial_curve().unwrap_or_default();
+                draw_radial(&mut gizmos, camera, transform, &curve, settings.color);
+            }
+            MeteringMode::Average => {}
+        }
+    }
+}
+
+/// Returns the world position of a point on the view, in normalized screen coordinates, on a
+/// plane facing the camera just in front of the near plane with bevy's reversed depth.
+fn view_to_world(camera: &Camera, transform: &GlobalTransform, point: Vec2) -> Option<Vec3> {
+    camera.ndc_to_world(
+        transform,
+        vec3(point.x * 2.0 - 1.0, 1.0 - point.y * 2.0, 0.5),
+    )
+}
+
+fn draw_spot(
+    gizmos: &mut Gizmos,
+    camera: &Camera,
+    transform: &GlobalTransform,
+    center: Vec2,
+    radius: f32,
+    color: Color,
+) {
+    // The radius is a fraction of the view height, which spans 1 in screen coordinates.
+    let (Some(position), Some(edge), Ok(normal)) = (
+        view_to_world(camera, transform, center),
+        view_to_world(camera, transform, center - Vec2::Y * radius),
+        Direction3d::new(transform.back()),
+    ) else {
+        return;
+    };
+
+    gizmos.circle(position, normal, position.distance(edge), color);
+}
+
+/// The distances from the center that the rings of radial metering are drawn at.
+const RING_DISTANCES: [f32; 4] = [0.2, 0.4, 0.6, 0.8];
+
+fn draw_radial(
+    gizmos: &mut Gizmos,
+    camera: &Camera,
+    transform: &GlobalTransform,
+    curve: &[Vec2],
+    color: Color,
+) {
+    let Some(size) = camera.logical_viewport_size() else {
+        return;
+    };
+
+    // Like the metering, the distance is normalized so that the corners are at one, and the
+    // radius of a spot is a fraction of the view height.
+    let half_diagonal = 0.5 * Vec2::new(size.x / size.y, 1.0).length();
+    for distance in RING_DISTANCES {
+        let weight = sample_curve(curve, distance).clamp(0.0, 1.0);
+        if weight > 0.0 {
+            draw_spot(
+                gizmos,
+                camera,
+                transform,
+                Vec2::splat(0.5),
+                distance * half_diagonal,
+                color.with_a(color.a() * weight),
+            );
+        }
+    }
+}
+
+fn draw_spot_rect(
+    gizmos: &mut Gizmos,
+    camera: &Camera,
+    transform: &GlobalTransform,
+    rect: Rect,
+    color: Color,
+) {
+    // Like the metering, outline the part of the rect on the view, or nothing when the whole
+    // view is metered instead.
+    let rect = rect.intersect(Rect::new(0.0, 0.0, 1.0, 1.0));
+    if rect.is_empty() {
+        return;
+    }
+
+    let corners = [
+        rect.min,
+        Vec2::new(rect.max.x, rect.min.y),
+        rect.max,
+        Vec2::new(rect.min.x, rect.max.y),
+        rect.min,
+    ]
+    .map(|corner| view_to_world(camera, transform, corner));
+    if let Some(corners) = corners.into_iter().collect::<Option<Vec<_>>>() {
+        gizmos.linestrip(corners, color);
+    }
+}
@@ -0,0 +1,377 @@
+use std::num::NonZeroU64;
+
+use bevy::{
+    asset::load_internal_asset,
+    core_pipeline::{
+        core_3d::graph::{Core3d, Node3d},
+        fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    },
+    ecs::{
+        query::{QueryItem, QueryState},
+        system::lifetimeless::Read,
+    },
+    prelude::*,
+    render::{
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        render_graph::{Node, NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        view::{ExtractedView, ViewTarget},
+        Render, RenderApp, RenderSet,
+    },
+    utils::HashMap,
+};
+
+use crate::{
+    compute_supported,
+    pipeline::{HistogramBins, ViewAutoExposurePipeline, STATE_SIZE},
+    AutoExposure,
+};
+
+/// Component that draws a debug overlay of the metering histogram on an [`AutoExposure`] camera.
+///
+/// The overlay shows the histogram as a bar graph, with vertical markers for the low (yellow)
+/// and high (yellow) percentile cutoffs, the metered average (red), the luminance the current
+/// exposure has adapted to (green) and both ends of the dynamic range that is read back into
+/// [`MeteredLuminance`](crate::MeteredLuminance) (blue).
+///
+/// The overlay isn't drawn on devices without compute shaders.
+#[derive(Component, Clone, Reflect)]
+#[reflect(Component)]
+pub struct AutoExposureDebugOverlay {
+    /// The screen rect to draw the overlay in, in normalized screen coordinates
+    /// (`0..=1`, top left is zero).
+    pub rect: Rect,
+    /// The opacity of the overlay.
+    pub opacity: f32,
+}
+
+impl Default for AutoExposureDebugOverlay {
+    fn default() -> Self {
+        Self {
+            rect: Rect::new(0.02, 0.02, 0.32, 0.22),
+            opacity: 0.8,
+        }
+    }
+}
+
+impl ExtractComponent for AutoExposureDebugOverlay {
+    type QueryData = Read<Self>;
+    type QueryFilter = (With<Camera>, With<AutoExposure>);
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::QueryData>) -> Option<Self> {
+        Some(item.clone())
+    }
+}
+
+const OVERLAY_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(8154916398140671203);
+
+/// Draws the debug overlays.
+pub(crate) struct DebugOverlayPlugin;
+
+impl Plugin for DebugOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            OVERLAY_SHADER_HANDLE,
+            "overlay.wgsl",
+            Shader::from_wgsl
+        );
+
+        app.register_type::<AutoExposureDebugOverlay>();
+        app.add_plugins(ExtractComponentPlugin::<AutoExposureDebugOverlay>::default());
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<SpecializedRenderPipelines<DebugOverlayPipeline>>()
+            .init_resource::<DebugHistogramBuffers>()
+            .add_systems(
+                Render,
+                (
+                    queue_debug_overlay_pipelines
+                        .in_set(RenderSet::Queue)
+                        .run_if(resource_exists::<DebugOverlayPipeline>),
+                    prepare_debug_histogram_buffers.in_set(RenderSet::Prepare),
+                ),
+            )
+            .add_render_graph_node::<DebugOverlayNode>(Core3d, DebugOverlay)
+            .add_render_graph_edges(
+                Core3d,
+                (
+                    Node3d::Tonemapping,
+                    DebugOverlay,
+                    Node3d::EndMainPassPostProcessing,
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        // The overlay reads the histogram from a storage buffer, which requires the same
+        // support as the compute path.
+        if compute_supported(&render_app.world) {
+            render_app.init_resource::<DebugOverlayPipeline>();
+        }
+    }
+}
+
+/// A copy of the histogram of a view, made by the metering node before it is cleared.
+#[derive(Component)]
+pub(crate) struct ViewDebugHistogram {
+    pub buffer: Buffer,
+}
+
+#[derive(Resource, Default)]
+struct DebugHistogramBuffers {
+    buffers: HashMap<Entity, Buffer>,
+}
+
+#[derive(Component)]
+struct ViewDebugOverlayPipeline(CachedRenderPipelineId);
+
+#[derive(ShaderType)]
+struct DebugOverlayParams {
+    opacity: f32,
+    low_percent: u32,
+    high_percent: u32,
+    min_log_lum: f32,
+    log_lum_range: f32,
+    bin_gamma: f32,
+    interpolate_percentiles: u32,
+}
+
+#[derive(Resource)]
+struct DebugOverlayPipeline {
+    layout: BindGroupLayout,
+    histogram_bins: HistogramBins,
+}
+
+impl FromWorld for DebugOverlayPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        Self {
+            layout: render_device.create_bind_group_layout(
+                "auto exposure debug overlay bind group",
+                &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: Some(DebugOverlayParams::min_size()),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: Some(world.resource::<HistogramBins>().buffer_size),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: NonZeroU64::new(STATE_SIZE),
+                        },
+                        count: None,
+                    },
+                ],
+            ),
+            histogram_bins: *world.resource::<HistogramBins>(),
+        }
+    }
+}
+
+impl SpecializedRenderPipeline for DebugOverlayPipeline {
+    /// The format and the sample count of the view's main texture.
+    type Key = (TextureFormat, u32);
+
+    fn specialize(&self, (format, samples): Self::Key) -> RenderPipelineDescriptor {
+        RenderPipelineDescriptor {
+            label: Some("auto exposure debug overlay pipeline".into()),
+            layout: vec![self.layout.clone()],
+            push_constant_ranges: vec![],
+            vertex: fullscreen_shader_vertex_state(),
+            primitive: default(),
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: samples,
+                ..default()
+            },
+            fragment: Some(FragmentState {
+                shader: OVERLAY_SHADER_HANDLE,
+                shader_defs: self.histogram_bins.shader_defs(),
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+        }
+    }
+}
+
+fn queue_debug_overlay_pipelines(
+    mut commands: Commands,
+    pipeline_cache: Res<PipelineCache>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<DebugOverlayPipeline>>,
+    pipeline: Res<DebugOverlayPipeline>,
+    msaa: Res<Msaa>,
+    views: Query<(Entity, &ViewTarget), With<AutoExposureDebugOverlay>>,
+) {
+    for (entity, view_target) in views.iter() {
+        let id = pipelines.specialize(
+            &pipeline_cache,
+            &pipeline,
+            (view_target.main_texture_format(), msaa.samples()),
+        );
+        commands.entity(entity).insert(ViewDebugOverlayPipeline(id));
+    }
+}
+
+fn prepare_debug_histogram_buffers(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    bins: Res<HistogramBins>,
+    mut buffers: ResMut<DebugHistogramBuffers>,
+    views: Query<Entity, With<AutoExposureDebugOverlay>>,
+) {
+    buffers.buffers.retain(|entity, _| views.contains(*entity));
+
+    for entity in views.iter() {
+        let buffer = buffers.buffers.entry(entity).or_insert_with(|| {
+            device.create_buffer(&BufferDescriptor {
+                label: Some("auto exposure debug histogram buffer"),
+                size: bins.buffer_size.get(),
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        });
+
+        commands.entity(entity).insert(ViewDebugHistogram {
+            buffer: buffer.clone(),
+        });
+    }
+}
+
+#[derive(RenderLabel, Debug, Clone, Hash, PartialEq, Eq)]
+struct DebugOverlay;
+
+type DebugOverlayQuery = (
+    Read<ViewTarget>,
+    Read<ExtractedView>,
+    Read<AutoExposureDebugOverlay>,
+    Read<ViewDebugOverlayPipeline>,
+    Read<ViewDebugHistogram>,
+    Read<ViewAutoExposurePipeline>,
+);
+
+struct DebugOverlayNode {
+    query: QueryState<DebugOverlayQuery>,
+}
+
+impl FromWorld for DebugOverlayNode {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            query: QueryState::new(world),
+        }
+    }
+}
+
+impl Node for DebugOverlayNode {
+    fn update(&mut self, world: &mut World) {
+        self.query.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let Ok((view_target, view, overlay, overlay_pipeline, histogram, auto_exposure)) =
+            self.query.get_manual(world, graph.view_entity())
+        else {
+            return Ok(());
+        };
+
+        let Some(render_pipeline) = world
+            .resource::<PipelineCache>()
+            .get_render_pipeline(overlay_pipeline.0)
+        else {
+            return Ok(());
+        };
+
+        let mut params = encase::UniformBuffer::new(Vec::new());
+        params
+            .write(&DebugOverlayParams {
+                opacity: overlay.opacity,
+                low_percent: auto_exposure.params.low_percent,
+                high_percent: auto_exposure.params.high_percent,
+                min_log_lum: auto_exposure.params.min_log_lum,
+                log_lum_range: auto_exposure.params.log_lum_range,
+                bin_gamma: auto_exposure.params.bin_gamma,
+                interpolate_percentiles: auto_exposure.params.interpolate_percentiles,
+            })
+            .unwrap();
+        let params =
+            render_context
+                .render_device()
+                .create_buffer_with_data(&BufferInitDescriptor {
+                    label: None,
+                    contents: params.as_ref(),
+                    usage: BufferUsages::UNIFORM,
+                });
+
+        let bind_group = render_context.render_device().create_bind_group(
+            None,
+            &world.resource::<DebugOverlayPipeline>().layout,
+            &BindGroupEntries::sequential((
+                params.as_entire_binding(),
+                histogram.buffer.as_entire_binding(),
+                auto_exposure.state.as_entire_binding(),
+            )),
+        );
+
+        // Draw the overlay by restricting the fullscreen triangle to the overlay rect.
+        let origin = view.viewport.xy().as_vec2();
+        let size = view.viewport.zw().as_vec2();
+        let min = origin + (overlay.rect.min.clamp(Vec2::ZERO, Vec2::ONE) * size).floor();
+        let max = origin + (overlay.rect.max.clamp(Vec2::ZERO, Vec2::ONE) * size).ceil();
+        if min.x >= max.x || min.y >= max.y {
+            return Ok(());
+        }
+
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("auto_exposure_debug_overlay_pass"),
+            color_attachments: &[Some(view_target.get_color_attachment())],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        render_pass.set_viewport(min.x, min.y, max.x - min.x, max.y - min.y, 0.0, 1.0);
+        render_pass.set_render_pipeline(render_pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        Ok(())
+    }
+}
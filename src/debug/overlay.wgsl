@@ -0,0 +1,100 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#import bevy_mod_auto_exposure::bins::{logLumToBin, binToLogLum, percentileBin}
+
+struct Params {
+    opacity: f32,
+    low_percent: u32,
+    high_percent: u32,
+    min_log_lum: f32,
+    log_lum_range: f32,
+    bin_gamma: f32,
+    interpolate_percentiles: u32,
+}
+
+struct State {
+    exposure: f32,
+    average: f32,
+    environment: f32,
+    padding: f32,
+    range_low: f32,
+    range_high: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var<storage, read> histogram: array<u32, #{HISTOGRAM_BINS}>;
+@group(0) @binding(2)
+var<storage, read> state: State;
+
+const BACKGROUND = vec4<f32>(0.0, 0.0, 0.0, 0.5);
+const BAR = vec4<f32>(0.8, 0.8, 0.8, 1.0);
+const CUTOFF = vec4<f32>(1.0, 1.0, 0.0, 1.0);
+const AVERAGE = vec4<f32>(1.0, 0.0, 0.0, 1.0);
+const ADAPTED = vec4<f32>(0.0, 1.0, 0.0, 1.0);
+const RANGE = vec4<f32>(0.0, 0.5, 1.0, 1.0);
+
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let bin = min(u32(in.uv.x * f32(#{HISTOGRAM_BINS})), #{HISTOGRAM_BINS}u - 1u);
+
+    var total = 0u;
+    var highest = 1u;
+    for (var i = 0u; i < #{HISTOGRAM_BINS}u; i += 1u) {
+        total += histogram[i];
+        highest = max(highest, histogram[i]);
+    }
+
+    // Find the cutoff bins and the average the same way `computeAverage` does.
+    let first_index = total * params.low_percent / 100u;
+    let last_index = total * params.high_percent / 100u;
+
+    var low_bin = 0u;
+    var high_bin = 0u;
+    var count = 0u;
+    var sum = 0.0;
+    var cumulative = histogram[0];
+    for (var i = 1u; i < #{HISTOGRAM_BINS}u; i += 1u) {
+        let previous = cumulative;
+        cumulative += histogram[i];
+
+        if previous <= first_index && first_index < cumulative {
+            low_bin = i;
+        }
+        if previous <= last_index && last_index < cumulative {
+            high_bin = i;
+        }
+
+        let bin_count = clamp(cumulative, first_index, last_index) - clamp(previous, first_index, last_index);
+        let position = percentileBin(i, previous, cumulative, first_index, last_index, params.interpolate_percentiles != 0u);
+        sum += f32(bin_count) * binToLogLum(position, params.bin_gamma);
+        count += bin_count;
+    }
+
+    var color = BACKGROUND;
+
+    if 1.0 - in.uv.y <= f32(histogram[bin]) / f32(highest) {
+        color = BAR;
+    }
+    if bin == logLumToRangeBin(state.range_low) || bin == logLumToRangeBin(state.range_high) {
+        color = RANGE;
+    }
+    if bin == low_bin || bin == high_bin {
+        color = CUTOFF;
+    }
+    if count > 0u && bin == logLumToBin(sum / f32(count), params.bin_gamma) {
+        color = AVERAGE;
+    }
+
+    // The exposure compensates for the metered luminance, so negate it to find the luminance.
+    if bin == logLumToRangeBin(-state.exposure) {
+        color = ADAPTED;
+    }
+
+    return vec4<f32>(color.rgb, color.a * params.opacity);
+}
+
+// Returns the bin of a log luminance, clamped to the metering range.
+fn logLumToRangeBin(log_lum: f32) -> u32 {
+    return logLumToBin(saturate((log_lum - params.min_log_lum) / params.log_lum_range), params.bin_gamma);
+}
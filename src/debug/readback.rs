@@ -0,0 +1,192 @@
+use std::sync::{
+    mpsc::{channel, Receiver, Sender},
+    Mutex,
+};
+
+use bevy::{
+    ecs::{query::QueryItem, system::lifetimeless::Read},
+    prelude::*,
+    render::{
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        render_resource::{Buffer, BufferDescriptor, BufferUsages, Maintain, MapMode},
+        renderer::RenderDevice,
+        Render, RenderApp, RenderSet,
+    },
+};
+
+use crate::{pipeline::HistogramBins, AutoExposure, AutoExposureResources, AutoExposureSet};
+
+/// Component that reads the metering histogram of an [`AutoExposure`] camera back to the CPU.
+///
+/// Call [`HistogramReadback::request`] to read back the histogram of the next rendered frame.
+/// The result is delivered as a [`HistogramReadbackEvent`] a frame or two later.
+/// Reading back stalls the render thread until the GPU has finished the frame, so this only
+/// happens on frames where it was requested.
+///
+/// The histogram is only built by the compute path, so nothing is read back on devices without
+/// compute shaders.
+#[derive(Component, Clone, Default, Reflect)]
+#[reflect(Component)]
+pub struct HistogramReadback {
+    requested: bool,
+}
+
+impl HistogramReadback {
+    /// Requests a readback of the histogram of the next rendered frame.
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    /// Whether a readback was requested this frame.
+    pub fn is_requested(&self) -> bool {
+        self.requested
+    }
+}
+
+/// Event sent when a histogram requested through [`HistogramReadback`] was read back.
+#[derive(Event, Clone, Debug)]
+pub struct HistogramReadbackEvent {
+    /// The camera entity that the histogram belongs to.
+    pub entity: Entity,
+    /// The weighted pixel count of every histogram bin. Every metered pixel adds up to 8 to its
+    /// bin, depending on the metering mask.
+    /// The first bin holds the pixels darker than the minimum exposure.
+    pub bins: Vec<u32>,
+}
+
+impl ExtractComponent for HistogramReadback {
+    type QueryData = Read<Self>;
+    type QueryFilter = (With<Camera>, With<AutoExposure>);
+    type Out = ViewHistogramReadback;
+
+    fn extract_component(item: QueryItem<'_, Self::QueryData>) -> Option<Self::Out> {
+        item.requested.then_some(ViewHistogramReadback)
+    }
+}
+
+/// Marks a view that needs its histogram read back this frame.
+#[derive(Component, Clone)]
+pub struct ViewHistogramReadback;
+
+/// A mappable copy of the histogram of a view, made by the metering node before it is cleared.
+#[derive(Component)]
+pub(crate) struct ViewHistogramReadbackBuffer {
+    pub buffer: Buffer,
+}
+
+#[derive(Resource)]
+struct HistogramReadbackSender(Sender<HistogramReadbackEvent>);
+
+#[derive(Resource)]
+struct HistogramReadbackReceiver(Mutex<Receiver<HistogramReadbackEvent>>);
+
+#[derive(Resource, Default)]
+struct PendingHistogramReadbacks {
+    readbacks: Vec<(Entity, Buffer)>,
+}
+
+/// Reads back the histograms that were requested through [`HistogramReadback`].
+pub(crate) struct HistogramReadbackPlugin;
+
+impl Plugin for HistogramReadbackPlugin {
+    fn build(&self, app: &mut App) {
+        let (sender, receiver) = channel();
+
+        app.register_type::<HistogramReadback>()
+            .add_event::<HistogramReadbackEvent>()
+            .insert_resource(HistogramReadbackReceiver(Mutex::new(receiver)))
+            .add_plugins(ExtractComponentPlugin::<HistogramReadback>::default())
+            .add_systems(
+                First,
+                (
+                    receive_histogram_readbacks.in_set(AutoExposureSet::Readback),
+                    clear_histogram_readback_requests,
+                ),
+            );
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .insert_resource(HistogramReadbackSender(sender))
+            .init_resource::<PendingHistogramReadbacks>()
+            .add_systems(
+                Render,
+                (
+                    prepare_histogram_readbacks
+                        .in_set(RenderSet::Prepare)
+                        .run_if(resource_exists::<AutoExposureResources>),
+                    map_histogram_readbacks.in_set(RenderSet::Cleanup),
+                ),
+            );
+    }
+}
+
+fn receive_histogram_readbacks(
+    receiver: Res<HistogramReadbackReceiver>,
+    mut events: EventWriter<HistogramReadbackEvent>,
+) {
+    let receiver = receiver.0.lock().unwrap();
+    events.send_batch(receiver.try_iter());
+}
+
+fn clear_histogram_readback_requests(mut readbacks: Query<&mut HistogramReadback>) {
+    for mut readback in readbacks.iter_mut() {
+        if readback.requested {
+            readback.requested = false;
+        }
+    }
+}
+
+fn prepare_histogram_readbacks(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    bins: Res<HistogramBins>,
+    mut pending: ResMut<PendingHistogramReadbacks>,
+    views: Query<Entity, With<ViewHistogramReadback>>,
+) {
+    for entity in views.iter() {
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("auto exposure histogram readback buffer"),
+            size: bins.buffer_size.get(),
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        pending.readbacks.push((entity, buffer.clone()));
+        commands
+            .entity(entity)
+            .insert(ViewHistogramReadbackBuffer { buffer });
+    }
+}
+
+fn map_histogram_readbacks(
+    device: Res<RenderDevice>,
+    sender: Res<HistogramReadbackSender>,
+    mut pending: ResMut<PendingHistogramReadbacks>,
+) {
+    if pending.readbacks.is_empty() {
+        return;
+    }
+
+    for (_, buffer) in pending.readbacks.iter() {
+        device.map_buffer(&buffer.slice(..), MapMode::Read, |_| {});
+    }
+
+    // Block until the frame has finished on the GPU and the buffers are mapped.
+    device.poll(Maintain::Wait);
+
+    for (entity, buffer) in pending.readbacks.drain(..) {
+        let bins = buffer
+            .slice(..)
+            .get_mapped_range()
+            .chunks_exact(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        buffer.unmap();
+
+        // The receiver only goes away when the app is shutting down.
+        let _ = sender.0.send(HistogramReadbackEvent { entity, bins });
+    }
+}
@@ -0,0 +1,137 @@
+use bevy::prelude::*;
+
+use crate::{AutoExposure, ExposureSettled, MeteredLuminance};
+
+/// Component that shows the exposure of an [`AutoExposure`] camera as text in a corner of its
+/// view: the adapted exposure, the metered luminance, its dynamic range and whether the exposure
+/// has settled.
+///
+/// The values are read back from the GPU, so they lag a few frames behind. The camera gets a
+/// [`MeteredLuminance`] and an [`ExposureSettled`] for this if it doesn't have them yet, which
+/// stay when the text is removed.
+#[derive(Component, Clone, Default, Reflect)]
+#[reflect(Component)]
+pub struct AutoExposureDebugText {
+    /// The corner of the view to show the text in.
+    pub corner: DebugTextCorner,
+}
+
+/// A corner of the view, see [`AutoExposureDebugText`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+pub enum DebugTextCorner {
+    #[default]
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// The text node that shows the exposure of a camera.
+#[derive(Component)]
+struct DebugTextNode {
+    camera: Entity,
+}
+
+/// Shows the debug texts.
+pub(crate) struct DebugTextPlugin;
+
+impl Plugin for DebugTextPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<AutoExposureDebugText>()
+            .register_type::<DebugTextCorner>()
+            .add_systems(
+                Update,
+                (spawn_debug_texts, despawn_debug_texts, update_debug_texts).chain(),
+            );
+    }
+}
+
+type AddedDebugTextFilter = (Added<AutoExposureDebugText>, With<AutoExposure>);
+
+fn spawn_debug_texts(
+    mut commands: Commands,
+    cameras: Query<(Entity, Has<MeteredLuminance>, Has<ExposureSettled>), AddedDebugTextFilter>,
+) {
+    for (camera, has_luminance, has_settled) in cameras.iter() {
+        if !has_luminance {
+            commands.entity(camera).insert(MeteredLuminance::default());
+        }
+        if !has_settled {
+            commands.entity(camera).insert(ExposureSettled::default());
+        }
+
+        commands.spawn((
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+            TargetCamera(camera),
+            DebugTextNode { camera },
+        ));
+    }
+}
+
+fn despawn_debug_texts(
+    mut commands: Commands,
+    nodes: Query<(Entity, &DebugTextNode)>,
+    cameras: Query<(), With<AutoExposureDebugText>>,
+) {
+    for (entity, node) in nodes.iter() {
+        if !cameras.contains(node.camera) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+fn update_debug_texts(
+    mut nodes: Query<(&DebugTextNode, &mut Text, &mut Style)>,
+    cameras: Query<(
+        &AutoExposureDebugText,
+        Option<&MeteredLuminance>,
+        Option<&ExposureSettled>,
+    )>,
+) {
+    for (node, mut text, mut style) in nodes.iter_mut() {
+        let Ok((debug_text, luminance, settled)) = cameras.get(node.camera) else {
+            continue;
+        };
+
+        let margin = Val::Px(8.0);
+        let (top, bottom, left, right) = match debug_text.corner {
+            DebugTextCorner::TopLeft => (margin, Val::Auto, margin, Val::Auto),
+            DebugTextCorner::TopRight => (margin, Val::Auto, Val::Auto, margin),
+            DebugTextCorner::BottomLeft => (Val::Auto, margin, margin, Val::Auto),
+            DebugTextCorner::BottomRight => (Val::Auto, margin, Val::Auto, margin),
+        };
+        if (style.top, style.bottom, style.left, style.right) != (top, bottom, left, right) {
+            style.position_type = PositionType::Absolute;
+            (style.top, style.bottom, style.left, style.right) = (top, bottom, left, right);
+        }
+
+        let exposure = settled.and_then(ExposureSettled::exposure);
+        let value = match (exposure, luminance) {
+            (Some(exposure), Some(luminance)) => format!(
+                "Exposure: {:.2} EV\nMetered: {:.2} EV100 ({:.1} nits)\n{}{}",
+                exposure,
+                luminance.ev100,
+                luminance.nits,
+                luminance
+                    .dynamic_range()
+                    .map_or(String::new(), |stops| format!("Range: {stops:.1} stops\n")),
+                if settled.is_some_and(ExposureSettled::is_settled) {
+                    "Settled"
+                } else {
+                    "Adapting"
+                },
+            ),
+            _ => "Exposure: waiting for readback".to_string(),
+        };
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+}
@@ -0,0 +1,515 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, DiagnosticsStore, RegisterDiagnostic},
+    ecs::system::EntityCommands,
+    prelude::*,
+    render::{
+        render_resource::{
+            Buffer, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Maintain, MapMode,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        settings::WgpuFeatures,
+        Render, RenderApp, RenderSet,
+    },
+    utils::HashMap,
+};
+use wgpu::{QuerySet, QuerySetDescriptor, QueryType};
+
+use crate::{AutoExposure, ExposureSettled, MeteredLuminance};
+
+/// The most views per frame whose metering is timed on the GPU. Views beyond these only add to
+/// the CPU time.
+const MAX_TIMED_VIEWS: u32 = 16;
+
+/// Plugin that reports how long metering takes as diagnostics, for example to show them with
+/// [`LogDiagnosticsPlugin`](bevy::diagnostic::LogDiagnosticsPlugin) while tuning
+/// [`AutoExposurePlugin::histogram_workgroup_size`](crate::AutoExposurePlugin).
+///
+/// The GPU time of the metering commands is only measured on devices with
+/// [`WgpuFeatures::TIMESTAMP_QUERY`], and is left out elsewhere. The CPU time of recording them is
+/// always measured. Both add up all views that are metered in a frame.
+///
+/// Reading the GPU time back waits for the GPU to finish every frame, which costs frame rate, so
+/// only add this plugin while profiling.
+pub struct AutoExposureDiagnosticsPlugin;
+
+impl AutoExposureDiagnosticsPlugin {
+    /// The GPU time of metering, in milliseconds.
+    pub const METERING_TIME: DiagnosticPath =
+        DiagnosticPath::const_new("auto_exposure/metering_ms");
+    /// The CPU time of recording the metering commands, in milliseconds.
+    pub const METERING_CPU_TIME: DiagnosticPath =
+        DiagnosticPath::const_new("auto_exposure/metering_cpu_ms");
+}
+
+impl Plugin for AutoExposureDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        let (sender, receiver) = channel();
+
+        app.register_diagnostic(Diagnostic::new(Self::METERING_TIME).with_suffix("ms"))
+            .register_diagnostic(Diagnostic::new(Self::METERING_CPU_TIME).with_suffix("ms"))
+            .insert_resource(MeteringTimesReceiver(Mutex::new(receiver)))
+            .add_systems(Update, receive_metering_times);
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .insert_resource(MeteringTimesSender(sender))
+            .add_systems(
+                Render,
+                send_metering_times
+                    .in_set(RenderSet::Cleanup)
+                    .run_if(resource_exists::<MeteringTimers>),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<MeteringTimers>();
+    }
+}
+
+/// The metering times of a frame.
+struct MeteringTimes {
+    gpu_ms: Option<f64>,
+    cpu_ms: f64,
+}
+
+#[derive(Resource)]
+struct MeteringTimesSender(Sender<MeteringTimes>);
+
+#[derive(Resource)]
+struct MeteringTimesReceiver(Mutex<Receiver<MeteringTimes>>);
+
+/// The timers that the [`AutoExposureLabel`](crate::AutoExposureLabel) node records the metering
+/// of every view into, while the diagnostics are enabled.
+#[derive(Resource)]
+pub(crate) struct MeteringTimers {
+    /// Timestamps of the start and end of metering every view, when the device supports them.
+    timestamps: Option<TimestampQueries>,
+    /// The number of views that started metering this frame.
+    views: AtomicU32,
+    /// The CPU time of recording the metering commands this frame, in nanoseconds.
+    cpu_nanos: AtomicU64,
+}
+
+struct TimestampQueries {
+    query_set: QuerySet,
+    /// The buffer that the timestamps are resolved to.
+    resolve: Buffer,
+    /// The buffer that the resolved timestamps are copied to, to read them back.
+    readback: Buffer,
+    /// The nanoseconds per timestamp tick.
+    period: f32,
+}
+
+/// Times the metering of a view, see [`MeteringTimers::start`].
+pub(crate) struct MeteringTimer {
+    view: u32,
+    start: Instant,
+}
+
+impl FromWorld for MeteringTimers {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+        let timestamps = device
+            .features()
+            .contains(WgpuFeatures::TIMESTAMP_QUERY)
+            .then(|| {
+                let size = u64::from(MAX_TIMED_VIEWS) * 2 * 8;
+                TimestampQueries {
+                    query_set: device.wgpu_device().create_query_set(&QuerySetDescriptor {
+                        label: Some("auto exposure metering timestamps"),
+                        ty: QueryType::Timestamp,
+                        count: MAX_TIMED_VIEWS * 2,
+                    }),
+                    resolve: device.create_buffer(&BufferDescriptor {
+                        label: Some("auto exposure metering timestamp resolve buffer"),
+                        size,
+                        usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+                        mapped_at_creation: false,
+                    }),
+                    readback: device.create_buffer(&BufferDescriptor {
+                        label: Some("auto exposure metering timestamp readback buffer"),
+                        size,
+                        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    }),
+                    period: world.resource::<RenderQueue>().get_timestamp_period(),
+                }
+            });
+
+        Self {
+            timestamps,
+            views: AtomicU32::new(0),
+            cpu_nanos: AtomicU64::new(0),
+        }
+    }
+}
+
+impl MeteringTimers {
+    /// Starts timing the metering of a view, before its commands are recorded.
+    pub(crate) fn start(&self, render_context: &mut RenderContext) -> MeteringTimer {
+        let view = self.views.fetch_add(1, Ordering::Relaxed);
+        if let Some(timestamps) = self.timestamps.as_ref().filter(|_| view < MAX_TIMED_VIEWS) {
+            render_context
+                .command_encoder()
+                .write_timestamp(&timestamps.query_set, view * 2);
+        }
+
+        MeteringTimer {
+            view,
+            start: Instant::now(),
+        }
+    }
+
+    /// Stops timing the metering of a view, after its commands are recorded.
+    pub(crate) fn stop(&self, render_context: &mut RenderContext, timer: MeteringTimer) {
+        self.cpu_nanos
+            .fetch_add(timer.start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        if let Some(timestamps) = self
+            .timestamps
+            .as_ref()
+            .filter(|_| timer.view < MAX_TIMED_VIEWS)
+        {
+            render_context
+                .command_encoder()
+                .write_timestamp(&timestamps.query_set, timer.view * 2 + 1);
+        }
+    }
+}
+
+/// Sends the metering times of the frame to the main world, once the frame was submitted.
+fn send_metering_times(
+    timers: Res<MeteringTimers>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    sender: Res<MeteringTimesSender>,
+) {
+    let views = timers.views.swap(0, Ordering::Relaxed);
+    let cpu_nanos = timers.cpu_nanos.swap(0, Ordering::Relaxed);
+    if views == 0 {
+        return;
+    }
+
+    let gpu_ms = timers.timestamps.as_ref().map(|timestamps| {
+        let queries = views.min(MAX_TIMED_VIEWS) * 2;
+        let size = u64::from(queries) * 8;
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("auto exposure metering timestamp resolve"),
+        });
+        encoder.resolve_query_set(&timestamps.query_set, 0..queries, &timestamps.resolve, 0);
+        encoder.copy_buffer_to_buffer(&timestamps.resolve, 0, &timestamps.readback, 0, size);
+        queue.submit([encoder.finish()]);
+
+        // Block until the timestamps are resolved and the buffer is mapped.
+        let slice = timestamps.readback.slice(..size);
+        device.map_buffer(&slice, MapMode::Read, |_| {});
+        device.poll(Maintain::Wait);
+
+        let ticks: u64 = slice
+            .get_mapped_range()
+            .chunks_exact(16)
+            .map(|pair| {
+                let start = u64::from_le_bytes(pair[0..8].try_into().unwrap());
+                let end = u64::from_le_bytes(pair[8..16].try_into().unwrap());
+                end.saturating_sub(start)
+            })
+            .sum();
+        timestamps.readback.unmap();
+
+        ticks as f64 * f64::from(timestamps.period) / 1_000_000.0
+    });
+
+    // The receiver only goes away when the app is shutting down.
+    let _ = sender.0.send(MeteringTimes {
+        gpu_ms,
+        cpu_ms: cpu_nanos as f64 / 1_000_000.0,
+    });
+}
+
+/// Plugin that reports the metering results of every [`AutoExposure`] camera as diagnostics, so
+/// they can be logged or graphed over a play session, for example to catch an exposure that
+/// keeps oscillating in automated QA runs.
+///
+/// Every camera gets a [`MeteringDiagnosticsSlot`] with its own diagnostics, see
+/// [`exposure`](Self::exposure), [`average`](Self::average) and
+/// [`adaptation_rate`](Self::adaptation_rate). When a camera loses its [`AutoExposure`], the
+/// diagnostics of its slot are disabled and cleared, and the slot is reused by the next camera,
+/// so there are only as many diagnostics as cameras at once.
+///
+/// The results come from the same readback as [`MeteredLuminance`] and [`ExposureSettled`].
+/// Cameras get these while the diagnostics of their slot are enabled, if they don't have them
+/// yet, and lose them again once the diagnostics are disabled. Without a [`DiagnosticsStore`],
+/// like without the `DiagnosticsPlugin` of bevy, nothing is read back or reported. The readback
+/// doesn't stall the GPU, so the measurements lag a few frames behind, and unlike
+/// [`AutoExposureDiagnosticsPlugin`], this plugin can stay in shipping builds.
+pub struct AutoExposureMeteringDiagnosticsPlugin;
+
+impl AutoExposureMeteringDiagnosticsPlugin {
+    /// The adapted exposure of the camera in `slot`, in EV.
+    pub fn exposure(slot: MeteringDiagnosticsSlot) -> DiagnosticPath {
+        DiagnosticPath::new(format!("auto_exposure/{}/exposure_ev", slot.0))
+    }
+
+    /// The metered average luminance of the camera in `slot`, in EV100, like
+    /// [`MeteredLuminance::ev100`].
+    pub fn average(slot: MeteringDiagnosticsSlot) -> DiagnosticPath {
+        DiagnosticPath::new(format!("auto_exposure/{}/average_ev100", slot.0))
+    }
+
+    /// How fast the exposure of the camera in `slot` adapts, in EV per second. Positive while it
+    /// brightens the image, and zero once it has settled.
+    pub fn adaptation_rate(slot: MeteringDiagnosticsSlot) -> DiagnosticPath {
+        DiagnosticPath::new(format!("auto_exposure/{}/adaptation_ev_per_s", slot.0))
+    }
+}
+
+impl Plugin for AutoExposureMeteringDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<MeteringDiagnosticsSlot>()
+            .init_resource::<MeteringDiagnosticsSlots>()
+            .add_systems(
+                Update,
+                (
+                    release_metering_diagnostics,
+                    register_metering_diagnostics,
+                    update_metering_readbacks,
+                    measure_metering.run_if(resource_exists::<DiagnosticsStore>),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// The slot of the diagnostics of an [`AutoExposure`] camera, which
+/// [`AutoExposureMeteringDiagnosticsPlugin`] adds. Slots are numbered from zero, in the order that
+/// cameras get their [`AutoExposure`], and reused once a camera loses it.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect)]
+#[reflect(Component)]
+pub struct MeteringDiagnosticsSlot(usize);
+
+impl MeteringDiagnosticsSlot {
+    /// The number of the slot.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+type MeteringDiagnostics = AutoExposureMeteringDiagnosticsPlugin;
+
+/// The diagnostic paths of a slot, with their suffixes.
+struct SlotPaths([(DiagnosticPath, &'static str); 3]);
+
+impl SlotPaths {
+    fn new(slot: MeteringDiagnosticsSlot) -> Self {
+        Self([
+            (MeteringDiagnostics::exposure(slot), "EV"),
+            (MeteringDiagnostics::average(slot), "EV100"),
+            (MeteringDiagnostics::adaptation_rate(slot), "EV/s"),
+        ])
+    }
+}
+
+/// The camera in every slot, `None` for free slots, and the diagnostic paths of the slot, so
+/// they aren't formatted every frame.
+#[derive(Resource, Default)]
+struct MeteringDiagnosticsSlots(Vec<(Option<Entity>, SlotPaths)>);
+
+/// The readback components that [`AutoExposureMeteringDiagnosticsPlugin`] added to a camera,
+/// which it removes again once the diagnostics are disabled.
+#[derive(Component, Clone, Copy, Default)]
+struct AddedReadbacks {
+    luminance: bool,
+    settled: bool,
+}
+
+impl AddedReadbacks {
+    fn remove(self, entity: &mut EntityCommands) {
+        if self.luminance {
+            entity.remove::<MeteredLuminance>();
+        }
+        if self.settled {
+            entity.remove::<ExposureSettled>();
+        }
+        entity.remove::<Self>();
+    }
+}
+
+type AddedCameraFilter = (Added<AutoExposure>, With<Camera>);
+
+fn release_metering_diagnostics(
+    mut commands: Commands,
+    mut store: Option<ResMut<DiagnosticsStore>>,
+    mut slots: ResMut<MeteringDiagnosticsSlots>,
+    mut removed: RemovedComponents<AutoExposure>,
+    added: Query<&AddedReadbacks>,
+) {
+    for camera in removed.read() {
+        let Some((slot, paths)) = slots.0.iter_mut().find(|(slot, _)| *slot == Some(camera)) else {
+            continue;
+        };
+        *slot = None;
+
+        if let Some(store) = store.as_deref_mut() {
+            for (path, _) in &paths.0 {
+                if let Some(diagnostic) = store.get_mut(path) {
+                    diagnostic.is_enabled = false;
+                    diagnostic.clear_history();
+                }
+            }
+        }
+
+        // The camera may have been despawned.
+        if let Some(mut entity) = commands.get_entity(camera) {
+            entity.remove::<MeteringDiagnosticsSlot>();
+            if let Ok(added) = added.get(camera) {
+                added.remove(&mut entity);
+            }
+        }
+    }
+}
+
+fn register_metering_diagnostics(
+    mut commands: Commands,
+    store: Option<ResMut<DiagnosticsStore>>,
+    mut slots: ResMut<MeteringDiagnosticsSlots>,
+    cameras: Query<Entity, AddedCameraFilter>,
+) {
+    let Some(mut store) = store else {
+        return;
+    };
+
+    for camera in cameras.iter() {
+        let index = match slots.0.iter().position(|(slot, _)| slot.is_none()) {
+            Some(index) => index,
+            None => {
+                let paths = SlotPaths::new(MeteringDiagnosticsSlot(slots.0.len()));
+                slots.0.push((None, paths));
+                slots.0.len() - 1
+            }
+        };
+        let (slot, paths) = &mut slots.0[index];
+        *slot = Some(camera);
+        commands
+            .entity(camera)
+            .insert(MeteringDiagnosticsSlot(index));
+
+        for (path, suffix) in &paths.0 {
+            match store.get_mut(path) {
+                // A reused slot was disabled when its last camera lost its auto exposure.
+                Some(diagnostic) => diagnostic.is_enabled = true,
+                None => store.add(Diagnostic::new(path.clone()).with_suffix(*suffix)),
+            }
+        }
+    }
+}
+
+type SlottedCameraQuery = (
+    Entity,
+    &'static MeteringDiagnosticsSlot,
+    Has<MeteredLuminance>,
+    Has<ExposureSettled>,
+    Option<&'static AddedReadbacks>,
+);
+
+/// Adds the readback components to the cameras whose diagnostics are enabled, and removes the
+/// ones that it added from the cameras whose diagnostics are disabled.
+fn update_metering_readbacks(
+    mut commands: Commands,
+    store: Option<Res<DiagnosticsStore>>,
+    slots: Res<MeteringDiagnosticsSlots>,
+    cameras: Query<SlottedCameraQuery>,
+) {
+    for (camera, slot, has_luminance, has_settled, added) in cameras.iter() {
+        let (_, paths) = &slots.0[slot.0];
+        let enabled = store.as_ref().is_some_and(|store| {
+            paths.0.iter().any(|(path, _)| {
+                store
+                    .get(path)
+                    .is_some_and(|diagnostic| diagnostic.is_enabled)
+            })
+        });
+        let added = added.copied().unwrap_or_default();
+
+        let mut entity = commands.entity(camera);
+        if enabled && !(has_luminance && has_settled) {
+            if !has_luminance {
+                entity.insert(MeteredLuminance::default());
+            }
+            if !has_settled {
+                entity.insert(ExposureSettled::default());
+            }
+            entity.insert(AddedReadbacks {
+                luminance: added.luminance || !has_luminance,
+                settled: added.settled || !has_settled,
+            });
+        } else if !enabled && (added.luminance || added.settled) {
+            added.remove(&mut entity);
+        }
+    }
+}
+
+fn measure_metering(
+    mut diagnostics: Diagnostics,
+    time: Res<Time<Real>>,
+    slots: Res<MeteringDiagnosticsSlots>,
+    cameras: Query<
+        (
+            Entity,
+            &MeteringDiagnosticsSlot,
+            &MeteredLuminance,
+            Ref<ExposureSettled>,
+        ),
+        With<AutoExposure>,
+    >,
+    mut last_exposures: Local<HashMap<Entity, (f32, f64)>>,
+) {
+    last_exposures.retain(|camera, _| cameras.contains(*camera));
+
+    let now = time.elapsed_seconds_f64();
+    for (camera, slot, luminance, settled) in cameras.iter() {
+        let Some(exposure) = settled.exposure().filter(|_| settled.is_changed()) else {
+            continue;
+        };
+
+        let (_, SlotPaths([(exposure_path, _), (average_path, _), (rate_path, _)])) =
+            &slots.0[slot.0];
+        diagnostics.add_measurement(exposure_path, || f64::from(exposure));
+        diagnostics.add_measurement(average_path, || f64::from(luminance.ev100));
+        // The rate is measured between the readbacks that arrive in different frames.
+        if let Some((last_exposure, last_time)) = last_exposures.insert(camera, (exposure, now)) {
+            if now > last_time {
+                diagnostics.add_measurement(rate_path, || {
+                    f64::from(exposure - last_exposure) / (now - last_time)
+                });
+            }
+        }
+    }
+}
+
+fn receive_metering_times(receiver: Res<MeteringTimesReceiver>, mut diagnostics: Diagnostics) {
+    let receiver = receiver.0.lock().unwrap();
+    for times in receiver.try_iter() {
+        if let Some(gpu_ms) = times.gpu_ms {
+            diagnostics.add_measurement(&AutoExposureDiagnosticsPlugin::METERING_TIME, || gpu_ms);
+        }
+        diagnostics.add_measurement(&AutoExposureDiagnosticsPlugin::METERING_CPU_TIME, || {
+            times.cpu_ms
+        });
+    }
+}
@@ -0,0 +1,32 @@
+@group(0) @binding(0)
+var tex_source: texture_2d<f32>;
+@group(0) @binding(1)
+var tex_target: texture_storage_2d<rgba16float, write>;
+// The rect of the source to downsample, as (x, y, width, height).
+@group(0) @binding(2)
+var<uniform> source_viewport: vec4<u32>;
+
+// Box filter the source into the smaller target, so the histogram pass has fewer pixels to meter.
+@compute @workgroup_size(8, 8, 1)
+fn downsample(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let target_dim = textureDimensions(tex_target);
+    if global_invocation_id.x >= target_dim.x || global_invocation_id.y >= target_dim.y {
+        return;
+    }
+
+    // Every target texel averages the block of source texels that it covers.
+    let source_dim = source_viewport.zw;
+    let start = global_invocation_id.xy * source_dim / target_dim;
+    let end = min(max((global_invocation_id.xy + 1u) * source_dim / target_dim, start + 1u), source_dim);
+
+    // Alpha is averaged too, as the histogram pass may weigh pixels by it.
+    var sum = vec4<f32>(0.0);
+    for (var y = start.y; y < end.y; y += 1u) {
+        for (var x = start.x; x < end.x; x += 1u) {
+            sum += textureLoad(tex_source, source_viewport.xy + vec2<u32>(x, y), 0);
+        }
+    }
+
+    let size = end - start;
+    textureStore(tex_target, global_invocation_id.xy, sum / f32(size.x * size.y));
+}
@@ -0,0 +1,37 @@
+use std::fmt;
+
+/// A reason that auto exposure can't run, or can't apply the exposure.
+///
+/// These don't stop the app. They are logged once, and the affected cameras keep a fixed
+/// exposure instead. This includes the errors that happen while rendering, which aren't returned
+/// as a [`NodeRunError`](bevy::render::render_graph::NodeRunError): the render graph panics on
+/// those, which would stop the app over a camera that can't be auto exposed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AutoExposureError {
+    /// The app has no render app, for example because it was built without rendering.
+    MissingRenderApp,
+    /// The device lacks a capability that metering needs, which is named.
+    UnsupportedDevice(String),
+    /// The view uniforms buffer doesn't exist, so the exposure can't be written into it.
+    MissingViewUniforms,
+    /// The layout of bevy's view uniform isn't the one the exposure is written into, for
+    /// example after a bevy upgrade, so writing it would corrupt another field. The reason is
+    /// described.
+    InvalidViewUniformLayout(String),
+}
+
+impl fmt::Display for AutoExposureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingRenderApp => write!(f, "the app has no render app"),
+            Self::UnsupportedDevice(missing) => write!(f, "the device lacks {missing}"),
+            Self::MissingViewUniforms => write!(f, "the view uniforms buffer doesn't exist"),
+            Self::InvalidViewUniformLayout(reason) => {
+                write!(f, "the view uniform layout has changed: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for AutoExposureError {}
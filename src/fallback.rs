@@ -0,0 +1,381 @@
+//! Metering for devices without compute shaders, like WebGL2 and some older mobile GPUs.
+//!
+//! Instead of building a histogram, the weighted log luminance of the view is rendered to a
+//! small texture, which is then halved in size until a single texel holds the average. The
+//! exposure is adapted towards that average in a last pass, and copied to the state buffer
+//! that the compute path would have written.
+//!
+//! This is less accurate than the compute path:
+//! - the view is sampled on a fixed grid of 256x256 points, so small bright spots can be missed;
+//! - the average is not trimmed by `low_percent` and `high_percent`;
+//! - `metering_resolution`, `exclude_depth`, `focus_distance`, `bin_distribution`,
+//!   `metering_lut`, `sample_stride`, `temporal_blend`, `adaptive_key` and `tone_weights` are
+//!   ignored, and the histogram isn't available to
+//!   [`HistogramReadback`](crate::HistogramReadback) and the debug overlay.
+
+use bevy::{
+    core_pipeline::fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    ecs::system::SystemParam,
+    prelude::*,
+    render::{
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        texture::{CachedTexture, TextureCache},
+        view::{ExtractedView, ViewTarget},
+    },
+};
+
+use crate::{
+    node::{mask_views, metering_source},
+    pipeline::{AutoExposureParams, HistogramBins, MeteringPipelines, ViewAutoExposurePipeline},
+    MeteringSource,
+};
+
+pub(crate) const FALLBACK_SHADER_HANDLE: Handle<Shader> =
+    Handle::weak_from_u128(6207163386925218345);
+
+/// Size of the log luminance texture that the view is rendered to. Every texel averages a 4x4
+/// grid of samples of the view. Must be a power of two.
+const LUMINANCE_SIZE: u32 = 64;
+
+const LUMINANCE_FORMAT: TextureFormat = TextureFormat::Rg32Float;
+/// Holds the adapted exposure, the metered average and the environment exposure, like the state
+/// buffer.
+const STATE_FORMAT: TextureFormat = TextureFormat::Rgba32Float;
+
+#[derive(Resource)]
+pub(crate) struct FallbackPipeline {
+    layout: BindGroupLayout,
+    histogram_bins: HistogramBins,
+}
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+pub(crate) enum FallbackPass {
+    Luminance,
+    Reduce,
+    Adapt,
+}
+
+pub(crate) struct FallbackPipelines {
+    luminance: CachedRenderPipelineId,
+    reduce: CachedRenderPipelineId,
+    adapt: CachedRenderPipelineId,
+}
+
+/// The textures that the view is reduced in, and the exposure is adapted in.
+#[derive(Component)]
+pub(crate) struct ViewFallbackTextures {
+    /// The levels of the reduction, from `LUMINANCE_SIZE` down to a single texel.
+    reduction: Vec<CachedTexture>,
+    previous: CachedTexture,
+    next: CachedTexture,
+}
+
+impl FromWorld for FallbackPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let texture = |binding, view_dimension| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: false },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        Self {
+            layout: render_device.create_bind_group_layout(
+                "auto exposure fallback bind group",
+                &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: Some(AutoExposureParams::min_size()),
+                        },
+                        count: None,
+                    },
+                    texture(1, TextureViewDimension::D2),
+                    texture(2, TextureViewDimension::D2),
+                    texture(3, TextureViewDimension::D1),
+                    texture(4, TextureViewDimension::D2),
+                    texture(5, TextureViewDimension::D2),
+                    texture(6, TextureViewDimension::D2),
+                    texture(7, TextureViewDimension::D1),
+                    texture(8, TextureViewDimension::D2),
+                    texture(9, TextureViewDimension::D2),
+                ],
+            ),
+            histogram_bins: *world.resource::<HistogramBins>(),
+        }
+    }
+}
+
+impl SpecializedRenderPipeline for FallbackPipeline {
+    type Key = FallbackPass;
+
+    fn specialize(&self, pass: FallbackPass) -> RenderPipelineDescriptor {
+        let (entry_point, format) = match pass {
+            FallbackPass::Luminance => ("luminance", LUMINANCE_FORMAT),
+            FallbackPass::Reduce => ("reduce", LUMINANCE_FORMAT),
+            FallbackPass::Adapt => ("adapt", STATE_FORMAT),
+        };
+
+        RenderPipelineDescriptor {
+            label: Some("auto exposure fallback pipeline".into()),
+            layout: vec![self.layout.clone()],
+            push_constant_ranges: vec![],
+            vertex: fullscreen_shader_vertex_state(),
+            primitive: default(),
+            depth_stencil: None,
+            multisample: default(),
+            fragment: Some(FragmentState {
+                shader: FALLBACK_SHADER_HANDLE,
+                shader_defs: vec![
+                    ShaderDefVal::UInt("LUMINANCE_SIZE".into(), LUMINANCE_SIZE),
+                    // The average is quantized to the bins of the compute path.
+                    ShaderDefVal::UInt("HISTOGRAM_BINS".into(), self.histogram_bins.bins),
+                ],
+                entry_point: entry_point.into(),
+                targets: vec![Some(ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+        }
+    }
+}
+
+/// Specializes the fallback pipelines, when the fallback is in use.
+#[derive(SystemParam)]
+pub(crate) struct FallbackPipelineSpecializer<'w> {
+    pipeline: Option<Res<'w, FallbackPipeline>>,
+    pipelines: ResMut<'w, SpecializedRenderPipelines<FallbackPipeline>>,
+}
+
+impl FallbackPipelineSpecializer<'_> {
+    pub fn specialize(&mut self, pipeline_cache: &PipelineCache) -> Option<FallbackPipelines> {
+        let pipeline = self.pipeline.as_ref()?;
+        let mut specialize = |pass| self.pipelines.specialize(pipeline_cache, pipeline, pass);
+
+        Some(FallbackPipelines {
+            luminance: specialize(FallbackPass::Luminance),
+            reduce: specialize(FallbackPass::Reduce),
+            adapt: specialize(FallbackPass::Adapt),
+        })
+    }
+}
+
+pub(crate) fn prepare_fallback_textures(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    mut texture_cache: ResMut<TextureCache>,
+    views: Query<(Entity, &ViewAutoExposurePipeline)>,
+) {
+    let mut texture = |label, size, format, usage| {
+        texture_cache.get(
+            &device,
+            TextureDescriptor {
+                label: Some(label),
+                size: Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format,
+                usage,
+                view_formats: &[],
+            },
+        )
+    };
+
+    for (entity, auto_exposure) in views.iter() {
+        if !matches!(auto_exposure.pipelines, MeteringPipelines::Fragment(_)) {
+            continue;
+        }
+
+        let reduction = (0..=LUMINANCE_SIZE.ilog2())
+            .map(|level| {
+                texture(
+                    "auto exposure fallback reduction",
+                    LUMINANCE_SIZE >> level,
+                    LUMINANCE_FORMAT,
+                    TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                )
+            })
+            .collect();
+        let previous = texture(
+            "auto exposure fallback previous state",
+            1,
+            STATE_FORMAT,
+            TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+        );
+        let next = texture(
+            "auto exposure fallback next state",
+            1,
+            STATE_FORMAT,
+            TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+        );
+
+        commands.entity(entity).insert(ViewFallbackTextures {
+            reduction,
+            previous,
+            next,
+        });
+    }
+}
+
+/// Records the fallback metering passes, which update the exposure in the state buffer.
+pub(crate) fn meter(
+    render_context: &mut RenderContext,
+    world: &World,
+    view_entity: Entity,
+    view_target: &ViewTarget,
+    auto_exposure: &ViewAutoExposurePipeline,
+    pipelines: &FallbackPipelines,
+    view: &ExtractedView,
+) {
+    let pipeline_cache = world.resource::<PipelineCache>();
+    let (Some(luminance_pipeline), Some(reduce_pipeline), Some(adapt_pipeline)) = (
+        pipeline_cache.get_render_pipeline(pipelines.luminance),
+        pipeline_cache.get_render_pipeline(pipelines.reduce),
+        pipeline_cache.get_render_pipeline(pipelines.adapt),
+    ) else {
+        return;
+    };
+
+    let Some(textures) = world.get::<ViewFallbackTextures>(view_entity) else {
+        return;
+    };
+    // Light sensors are only averaged by the compute path, so the exposure is held.
+    if matches!(
+        auto_exposure.metering_source,
+        MeteringSource::LightSensor(_)
+    ) {
+        return;
+    }
+    let Some((source, viewport)) = metering_source(world, view_target, auto_exposure, view) else {
+        return;
+    };
+
+    let (mask, mask_blend, mask_layer) =
+        mask_views(render_context, world, view_entity, auto_exposure);
+
+    let mut settings = encase::UniformBuffer::new(Vec::new());
+    settings
+        .write(&AutoExposureParams {
+            viewport,
+            ..auto_exposure.params
+        })
+        .unwrap();
+    let settings = render_context
+        .render_device()
+        .create_buffer_with_data(&BufferInitDescriptor {
+            label: None,
+            contents: settings.as_ref(),
+            usage: BufferUsages::UNIFORM,
+        });
+
+    let layout = &world.resource::<FallbackPipeline>().layout;
+    let bind_group = |render_context: &RenderContext, reduced: &TextureView| {
+        render_context.render_device().create_bind_group(
+            None,
+            layout,
+            &BindGroupEntries::sequential((
+                settings.as_entire_binding(),
+                &source,
+                mask,
+                &auto_exposure.compensation_curve,
+                &textures.previous.default_view,
+                reduced,
+                mask_blend,
+                &auto_exposure.radial_weights,
+                &auto_exposure.from_weights,
+                mask_layer,
+            )),
+        )
+    };
+
+    // Textures can't be bound as storage, so the exposure of the previous frame is copied into
+    // a texture, and the adapted exposure back out of one.
+    render_context.command_encoder().copy_buffer_to_texture(
+        ImageCopyBuffer {
+            buffer: &auto_exposure.state,
+            layout: default(),
+        },
+        textures.previous.texture.as_image_copy(),
+        Extent3d::default(),
+    );
+
+    let last = textures.reduction.last().unwrap();
+    let meter_bind_group = bind_group(render_context, &last.default_view);
+
+    draw(
+        render_context,
+        &textures.reduction[0].default_view,
+        luminance_pipeline,
+        &meter_bind_group,
+    );
+
+    for levels in textures.reduction.windows(2) {
+        let reduce_bind_group = bind_group(render_context, &levels[0].default_view);
+        draw(
+            render_context,
+            &levels[1].default_view,
+            reduce_pipeline,
+            &reduce_bind_group,
+        );
+    }
+
+    draw(
+        render_context,
+        &textures.next.default_view,
+        adapt_pipeline,
+        &meter_bind_group,
+    );
+
+    render_context.command_encoder().copy_texture_to_buffer(
+        textures.next.texture.as_image_copy(),
+        ImageCopyBuffer {
+            buffer: &auto_exposure.state,
+            layout: default(),
+        },
+        Extent3d::default(),
+    );
+}
+
+/// Draws a fullscreen triangle to `target`.
+fn draw(
+    render_context: &mut RenderContext,
+    target: &TextureView,
+    pipeline: &RenderPipeline,
+    bind_group: &BindGroup,
+) {
+    let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+        label: Some("auto_exposure_fallback_pass"),
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: Operations {
+                load: LoadOp::Clear(default()),
+                store: StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        timestamp_writes: None,
+        occlusion_query_set: None,
+    });
+
+    render_pass.set_render_pipeline(pipeline);
+    render_pass.set_bind_group(0, bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+}
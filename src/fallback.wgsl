@@ -0,0 +1,312 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+// Taken from RTR vol 4 pg. 278
+const RGB_TO_LUM = vec3<f32>(0.2125, 0.7154, 0.0721);
+
+// Linear sRGB to the cone responses of OKLab, and their cube roots to its lightness, from
+// https://bottosson.github.io/posts/oklab/.
+const RGB_TO_LMS = mat3x3<f32>(
+    vec3<f32>(0.4122214708, 0.2119034982, 0.0883024619),
+    vec3<f32>(0.5363325363, 0.6806995451, 0.2817188376),
+    vec3<f32>(0.0514459929, 0.1073969566, 0.6299787005),
+);
+const LMS_TO_LIGHTNESS = vec3<f32>(0.2104542553, 0.7936177850, -0.0040720468);
+
+// The size of `Params::exclusion_rects`, see `MAX_EXCLUSION_RECTS` on the Rust side.
+const MAX_EXCLUSION_RECTS = 8u;
+
+struct Params {
+    min_log_lum: f32,
+    inv_log_lum_range: f32,
+    log_lum_range: f32,
+    low_percent: u32,
+    high_percent: u32,
+    speed_up: f32,
+    speed_down: f32,
+    delta_time: f32,
+    // A radius of zero disables spot metering.
+    spot_center: vec2<f32>,
+    spot_radius: f32,
+    // Only pixels within this rect of the view are metered, as (min x, min y, max x, max y).
+    spot_rect: vec4<f32>,
+    // How far to cross-fade from `tex_mask` to `tex_mask_blend`.
+    mask_blend: f32,
+    // Pixels with a log luminance above this aren't metered at all.
+    saturation_cutoff: f32,
+    // When set, pixels are weighed by the alpha of `tex_color`.
+    weight_by_alpha: u32,
+    // When set, snap to the target instead of adapting to it.
+    reset: u32,
+    // The exposure is held while the target is within this many EV of it.
+    deadband: f32,
+    // The most the exposure may change this frame, in EV.
+    max_step: f32,
+    // Scenes darker than this log luminance are exposed as if they were at it.
+    dark_floor: f32,
+    // The target exposure is capped at this, in EV.
+    max_gain: f32,
+    // Unused, as there is no histogram.
+    bin_gamma: f32,
+    // Unused, as there are no percentiles.
+    interpolate_percentiles: u32,
+    // When set, `tex_compensation` holds the target exposure instead of a compensation.
+    compensation_override: u32,
+    // Added to the target exposure, in EV.
+    correction: f32,
+    // The environment exposure is the exposure times the scale, plus the correction.
+    environment_scale: f32,
+    environment_correction: f32,
+    // Unused, as there is no histogram.
+    temporal_blend: f32,
+    // How far the target exposure follows the key of the scene, zero to ignore it.
+    key_strength: f32,
+    // The region of the metering masks that is stretched across the view, as (x, y, width, height).
+    mask_region: vec4<f32>,
+    // The mask weights are scaled by the gain and offset by the bias.
+    mask_gain: f32,
+    mask_bias: f32,
+    // When set, pixels are weighed by `tex_radial` at their distance from the view center.
+    radial: u32,
+    // Only every this many pixels are metered, in each dimension. Unused, as the view is sampled
+    // on a fixed grid.
+    sample_stride: u32,
+    // The adapted exposure that the camera was already rendered with, in EV, which is undone
+    // before metering.
+    camera_exposure: f32,
+    // The rect of `tex_color` to meter, as (x, y, width, height).
+    viewport: vec4<u32>,
+    // The number of `exclusion_rects` in use.
+    exclusion_count: u32,
+    // Pixels within these rects of the view aren't metered, as (min x, min y, max x, max y).
+    exclusion_rects: array<vec4<f32>, MAX_EXCLUSION_RECTS>,
+    // How far the metering mode has faded in from `tex_mode_from`. One when no fade is in
+    // progress.
+    mode_blend: f32,
+    // Unused, as the fallback adapts exponentially.
+    easing: u32,
+    // When set, pixels are metered by their perceptual lightness instead of their luma.
+    perceptual_luminance: u32,
+    // How `tex_mask_layer` is combined with the metering masks: 1 multiplies, 2 adds and 3 takes
+    // the larger weight. Zero without a layer.
+    mask_combine: u32,
+    // When set, `target_log_lum` is exposed as mid gray instead of the metered average.
+    absolute_target: u32,
+    // The calibrated luminance to expose as mid gray, as a log luminance of the metered source.
+    target_log_lum: f32,
+    // Unused, as the fallback doesn't meter a histogram to split.
+    tone_split: f32,
+    shadow_weight: f32,
+    highlight_weight: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var tex_color: texture_2d<f32>;
+@group(0) @binding(2)
+var tex_mask: texture_2d<f32>;
+@group(0) @binding(3)
+var tex_compensation: texture_1d<f32>;
+// The exposure of the previous frame.
+@group(0) @binding(4)
+var tex_state: texture_2d<f32>;
+// The previous level of the reduction, or its last level in the `adapt` pass.
+@group(0) @binding(5)
+var tex_reduced: texture_2d<f32>;
+@group(0) @binding(6)
+var tex_mask_blend: texture_2d<f32>;
+@group(0) @binding(7)
+var tex_radial: texture_1d<f32>;
+// The weights of the view that the metering mode fades from.
+@group(0) @binding(8)
+var tex_mode_from: texture_2d<f32>;
+// The mask that is layered onto the cross-faded metering masks, by `mask_combine`.
+@group(0) @binding(9)
+var tex_mask_layer: texture_2d<f32>;
+
+// Returns the texel of the radial weights at `uv`, for a view with the aspect ratio `aspect`. The
+// distance from the center is normalized so that the corners are at one.
+fn radialTexel(uv: vec2<f32>, aspect: f32) -> i32 {
+    let offset = (uv - 0.5) * vec2<f32>(aspect, 1.0);
+    let distance = length(offset) / (0.5 * length(vec2<f32>(aspect, 1.0)));
+    return i32(saturate(distance) * 255.0);
+}
+
+// Returns the weight of a spot and a spot rect at `uv`, for a view with the aspect ratio
+// `aspect`. A radius of zero leaves the spot out.
+fn spotWeight(uv: vec2<f32>, aspect: f32, center: vec2<f32>, radius: f32, rect: vec4<f32>) -> f32 {
+    var weight = 1.0;
+    if radius > 0.0 {
+        // The radius is relative to the view height, so correct for the aspect ratio.
+        let offset = (uv - center) * vec2<f32>(aspect, 1.0);
+        weight = select(0.0, 1.0, length(offset) <= radius);
+    }
+    return weight * select(0.0, 1.0, all(uv >= rect.xy) && all(uv <= rect.zw));
+}
+
+// Returns the weight of the metering mode at `uv`, cross-faded from the previous mode.
+fn modeWeight(uv: vec2<f32>, aspect: f32) -> f32 {
+    let texel = radialTexel(uv, aspect);
+    var weight = spotWeight(uv, aspect, params.spot_center, params.spot_radius, params.spot_rect);
+    if params.radial != 0u {
+        weight *= textureLoad(tex_radial, texel, 0).r;
+    }
+    if params.mode_blend < 1.0 {
+        let size = textureDimensions(tex_mode_from);
+        let from_texel = min(vec2<u32>(saturate(uv) * vec2<f32>(size)), size - 1u);
+        weight = mix(textureLoad(tex_mode_from, from_texel, 0).r, weight, params.mode_blend);
+    }
+    return weight;
+}
+
+// Whether `uv` lies within one of the exclusion rects.
+fn isExcluded(uv: vec2<f32>) -> bool {
+    for (var i = 0u; i < params.exclusion_count; i += 1u) {
+        let rect = params.exclusion_rects[i];
+        if all(uv >= rect.xy) && all(uv <= rect.zw) {
+            return true;
+        }
+    }
+    return false;
+}
+
+// Samples the metering mask at `uv`, cross-faded to the blend mask, combined with the mask layer,
+// and scaled by the gain and bias.
+fn meteringMask(view_uv: vec2<f32>) -> f32 {
+    let uv = params.mask_region.xy + view_uv * params.mask_region.zw;
+    let mask = textureLoad(tex_mask, vec2<i32>(uv * vec2<f32>(textureDimensions(tex_mask))), 0).r;
+    let mask_blend = textureLoad(tex_mask_blend, vec2<i32>(uv * vec2<f32>(textureDimensions(tex_mask_blend))), 0).r;
+    var weight = mix(mask, mask_blend, params.mask_blend);
+    // The layer covers the whole view, regardless of the mask region.
+    let layer = textureLoad(tex_mask_layer, vec2<i32>(view_uv * vec2<f32>(textureDimensions(tex_mask_layer))), 0).r;
+    switch params.mask_combine {
+        case 1u: {
+            weight *= layer;
+        }
+        case 2u: {
+            weight += layer;
+        }
+        case 3u: {
+            weight = max(weight, layer);
+        }
+        default: {}
+    }
+    return saturate(weight * params.mask_gain + params.mask_bias);
+}
+
+// The luminance of a linear color, by `params.perceptual_luminance`. The perceptual lightness is
+// cubed back into the units of luminance, so that the histogram and the average stay in log
+// luminance, and grays meter the same either way.
+fn pixelLuminance(color: vec3<f32>) -> f32 {
+    if params.perceptual_luminance != 0u {
+        let lms = pow(max(RGB_TO_LMS * color, vec3<f32>(0.0)), vec3<f32>(1.0 / 3.0));
+        let lightness = dot(lms, LMS_TO_LIGHTNESS);
+        return lightness * lightness * lightness;
+    }
+    return dot(color, RGB_TO_LUM);
+}
+
+// Writes the weighted average log luminance of a block of the viewport, sampled on a 4x4 grid.
+// The red channel holds the sum of the weighted log luminance, normalized to the metering range,
+// and the green channel the sum of the weights. Pixels darker than the range are left out.
+@fragment
+fn luminance(in: FullscreenVertexOutput) -> @location(0) vec2<f32> {
+    let dim = vec2<f32>(params.viewport.zw);
+    var sum = vec2<f32>(0.0);
+
+    for (var i = 0u; i < 16u; i += 1u) {
+        let tap = (vec2<f32>(f32(i % 4u), f32(i / 4u)) + 0.5) / 4.0;
+        let uv = (floor(in.position.xy) + tap) / f32(#{LUMINANCE_SIZE});
+
+        let color = textureLoad(tex_color, vec2<i32>(params.viewport.xy) + vec2<i32>(uv * dim), 0);
+        let rendered_lum = pixelLuminance(color.rgb);
+        let lum = rendered_lum * exp2(-params.camera_exposure);
+        var mask = meteringMask(uv);
+
+        if params.weight_by_alpha != 0u {
+            mask *= saturate(color.a);
+        }
+
+        mask *= modeWeight(uv, dim.x / dim.y);
+        mask *= select(1.0, 0.0, isExcluded(uv));
+
+        if lum >= exp2(params.min_log_lum) && log2(rendered_lum) <= params.saturation_cutoff {
+            let log_lum = saturate((log2(lum) - params.min_log_lum) * params.inv_log_lum_range);
+            sum += vec2<f32>(log_lum, 1.0) * mask;
+        }
+    }
+
+    return sum / 16.0;
+}
+
+// Averages 2x2 texels of the previous level of the reduction.
+@fragment
+fn reduce(in: FullscreenVertexOutput) -> @location(0) vec2<f32> {
+    let p = vec2<i32>(in.position.xy) * 2;
+    let sum = textureLoad(tex_reduced, p, 0).rg +
+        textureLoad(tex_reduced, p + vec2<i32>(1, 0), 0).rg +
+        textureLoad(tex_reduced, p + vec2<i32>(0, 1), 0).rg +
+        textureLoad(tex_reduced, p + vec2<i32>(1, 1), 0).rg;
+    return sum * 0.25;
+}
+
+// Adapts the exposure of the previous frame towards the average of the reduction.
+// Writes the adapted exposure, the metered average log luminance and the environment exposure,
+// like the compute path's state buffer.
+@fragment
+fn adapt(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let reduced = textureLoad(tex_reduced, vec2<i32>(0), 0).rg;
+    let previous = textureLoad(tex_state, vec2<i32>(0), 0);
+    let state = previous.rg;
+    let exposure = state.x;
+
+    // Nothing was metered, for example because the metering mask is black. Hold the exposure
+    // instead of adapting to an arbitrary target.
+    if reduced.y <= 0.0 {
+        return withEnvironment(state, previous.a);
+    }
+    let metered_frames = select(previous.a + 1.0, 1.0, previous.a >= 65535.0);
+
+    // The compute path maps its bins back to the log luminance they were binned at, so the
+    // average is mapped to the metering range directly.
+    let avg_lum = reduced.x / reduced.y * params.log_lum_range + params.min_log_lum;
+
+    // Expose scenes darker than the floor as if they were at it.
+    let target_lum = max(avg_lum, params.dark_floor);
+    let compensation = compensationAt(target_lum);
+    let exposed_lum = select(target_lum, params.target_log_lum, params.absolute_target != 0u);
+    let target_exposure = min(
+        select(compensation - exposed_lum, compensation, params.compensation_override != 0u) + params.correction,
+        params.max_gain,
+    );
+
+    if params.reset != 0u {
+        return withEnvironment(vec2<f32>(target_exposure, avg_lum), metered_frames);
+    }
+
+    let delta = target_exposure - exposure;
+    if abs(delta) <= params.deadband {
+        return withEnvironment(vec2<f32>(exposure, avg_lum), metered_frames);
+    }
+    let speed = select(params.speed_up, params.speed_down, delta > 0.0);
+    let step = delta * (1.0 - exp(-speed * params.delta_time));
+    return withEnvironment(vec2<f32>(exposure + clamp(step, -params.max_step, params.max_step), avg_lum), metered_frames);
+}
+
+// Samples the compensation curve at a log luminance, in EV. The curve is baked over the metering
+// range into texels 1 to 255, and texel 0 repeats the first one. Neighboring texels are
+// interpolated, so a wide range doesn't step the exposure.
+fn compensationAt(log_lum: f32) -> f32 {
+    let x = saturate((log_lum - params.min_log_lum) * params.inv_log_lum_range) * 254.0 + 1.0;
+    let texel = min(u32(x), 254u);
+    let low = textureLoad(tex_compensation, texel, 0).r;
+    let high = textureLoad(tex_compensation, texel + 1u, 0).r;
+    return mix(low, high, x - f32(texel));
+}
+
+// Appends the environment exposure, derived from the adapted exposure in `state.x`, and the
+// number of metered frames, like the compute path's state buffer.
+fn withEnvironment(state: vec2<f32>, metered_frames: f32) -> vec4<f32> {
+    let environment = state.x * params.environment_scale + params.environment_correction;
+    return vec4<f32>(state, environment, metered_frames);
+}
@@ -0,0 +1,122 @@
+use bevy::{
+    ecs::{query::QueryItem, system::lifetimeless::Read},
+    prelude::*,
+    render::{
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        render_resource::*,
+        renderer::RenderDevice,
+        Render, RenderApp, RenderSet,
+    },
+    utils::HashMap,
+};
+
+use crate::{
+    pipeline::{HistogramBins, ViewAutoExposurePipeline},
+    AutoExposure,
+};
+
+/// Component that shares the metering histogram of an [`AutoExposure`] camera with custom render
+/// nodes, like a local tone mapping pass, through a [`ViewHistogramBuffer`] on its view in the
+/// render world.
+///
+/// The histogram that views are metered into is cleared once it's averaged, so the metering
+/// node copies it into a buffer of the view before that. The copy is only made by the compute
+/// path, so the buffer stays zero on devices without compute shaders. Frames that aren't
+/// metered leave it alone, so it holds the histogram of the last metered frame.
+#[derive(Component, Clone, Copy, Debug, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct ShareHistogram;
+
+impl ExtractComponent for ShareHistogram {
+    type QueryData = Read<Self>;
+    type QueryFilter = (With<Camera>, With<AutoExposure>);
+    type Out = Self;
+
+    fn extract_component(_: QueryItem<'_, Self::QueryData>) -> Option<Self> {
+        Some(Self)
+    }
+}
+
+/// Render world component that holds the metering histogram of an [`AutoExposure`] view with
+/// [`ShareHistogram`], for custom render nodes. Nodes that read it should run after the
+/// [`AutoExposureLabel`](crate::AutoExposureLabel) node, which updates it.
+///
+/// The buffer is an `array<u32>` of `bins` weighted pixel counts, like
+/// `HistogramReadbackEvent::bins` with the `debug` feature: every metered pixel
+/// adds up to 8 to its bin, depending on the metering mask and mode. The first bin holds the
+/// pixels darker than `min`. Bin `i` of the others holds the log2 luminances from
+/// `min + pow((i - 1) / (bins - 2), 1 / bin_gamma) * (max - min)` up to where the next bin
+/// starts, in the units of the rendered view without the adapted exposure. It can be bound as a
+/// read only storage buffer, or copied from.
+///
+/// WGSL shaders can map bins to luminances with `binToLogLum`, from
+/// `#import bevy_mod_auto_exposure::bins`, with the `HISTOGRAM_BINS` shader def set to `bins`.
+#[derive(Component, Clone)]
+pub struct ViewHistogramBuffer {
+    pub buffer: Buffer,
+    /// The number of bins, the `BINS` of the
+    /// [`BinnedAutoExposurePlugin`](crate::BinnedAutoExposurePlugin).
+    pub bins: u32,
+    /// The metering range, in log2 luminance, see [`AutoExposure::min`] and
+    /// [`AutoExposure::max`].
+    pub min: f32,
+    pub max: f32,
+    /// The spacing of the bins, see [`BinDistribution`](crate::BinDistribution).
+    pub bin_gamma: f32,
+}
+
+#[derive(Resource, Default)]
+struct ViewHistogramBuffers {
+    buffers: HashMap<Entity, Buffer>,
+}
+
+/// Extracts [`ShareHistogram`], and prepares the buffers that the histograms are copied into.
+pub(crate) struct HistogramPlugin;
+
+impl Plugin for HistogramPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<ShareHistogram>()
+            .add_plugins(ExtractComponentPlugin::<ShareHistogram>::default());
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<ViewHistogramBuffers>()
+            .add_systems(
+                Render,
+                prepare_view_histogram_buffers.in_set(RenderSet::Prepare),
+            );
+    }
+}
+
+fn prepare_view_histogram_buffers(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    bins: Res<HistogramBins>,
+    mut buffers: ResMut<ViewHistogramBuffers>,
+    views: Query<(Entity, &ViewAutoExposurePipeline), With<ShareHistogram>>,
+) {
+    buffers.buffers.retain(|entity, _| views.contains(*entity));
+
+    for (entity, auto_exposure) in views.iter() {
+        let buffer = buffers.buffers.entry(entity).or_insert_with(|| {
+            device.create_buffer(&BufferDescriptor {
+                label: Some("auto exposure view histogram buffer"),
+                size: bins.buffer_size.get(),
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        });
+
+        let params = &auto_exposure.params;
+        commands.entity(entity).insert(ViewHistogramBuffer {
+            buffer: buffer.clone(),
+            bins: bins.bins,
+            min: params.min_log_lum,
+            max: params.min_log_lum + params.log_lum_range,
+            bin_gamma: params.bin_gamma,
+        });
+    }
+}
@@ -1,56 +1,987 @@
 use bevy::{
     asset::load_internal_asset,
-    core_pipeline::core_3d::graph::{Core3d, Node3d},
-    ecs::{query::QueryItem, system::lifetimeless::Read},
+    core_pipeline::{
+        core_3d::graph::{Core3d, Node3d},
+        prepass::DepthPrepass,
+    },
+    ecs::{
+        query::QueryItem,
+        system::{lifetimeless::Read, EntityCommands, SystemParam},
+    },
     math::vec2,
     prelude::*,
     render::{
+        camera::{ExtractedCamera, SortedCameras},
         extract_component::{ExtractComponent, ExtractComponentPlugin},
-        render_graph::RenderGraphApp,
+        render_asset::{RenderAssetUsages, RenderAssets},
+        render_graph::{RenderGraph, RenderGraphApp},
         render_resource::{
-            Buffer, BufferDescriptor, BufferUsages, Extent3d, PipelineCache,
-            SpecializedComputePipelines, TextureDescriptor, TextureDimension, TextureFormat,
-            TextureUsages, TextureView, TextureViewDescriptor,
+            Buffer, BufferDescriptor, BufferInitDescriptor, BufferUsages, Extent3d,
+            ImageCopyTexture, ImageDataLayout, Origin3d, PipelineCache, Source,
+            SpecializedComputePipelines, Texture, TextureAspect, TextureDescriptor,
+            TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureView,
+            TextureViewDescriptor,
         },
         renderer::{RenderDevice, RenderQueue},
+        texture::TextureCache,
+        view::{ExtractedView, VIEW_TYPE_HANDLE},
         Extract, Render, RenderApp, RenderSet,
     },
-    utils::HashMap,
+    utils::{HashMap, HashSet},
+};
+use pipeline::{
+    AutoExposurePipeline, ComputePipelines, DepthParams, HistogramBins, HistogramWorkgroupSize,
+    MaskFrame, MeteringPipelines, Pass, ViewAutoExposurePipeline, ViewAutoExposureTexture,
+    ViewGroup, ViewMaskFrames, DEFAULT_HISTOGRAM_BINS, DEFAULT_HISTOGRAM_WORKGROUP_SIZE,
+    DOWNSAMPLE_FORMAT, STATE_TRANSITION_PROGRESS_OFFSET,
 };
-use pipeline::{AutoExposureParams, AutoExposurePipeline, Pass, ViewAutoExposurePipeline};
 
-use crate::{node::AutoExposureNode, pipeline::METERING_SHADER_HANDLE};
+use crate::{
+    histogram::HistogramPlugin,
+    node::InvalidViewUniformLayout,
+    pipeline::{BINS_SHADER_HANDLE, DOWNSAMPLE_SHADER_HANDLE, METERING_SHADER_HANDLE},
+    readback::{ReadbackPlugin, ViewCameraExposure},
+    sensor::{SensorPlugin, ViewSensedLuminance},
+};
 
+mod curve;
+#[cfg(feature = "debug")]
+mod debug;
+mod diagnostics;
+mod error;
+#[cfg(feature = "fragment_fallback")]
+mod fallback;
+mod histogram;
+mod material;
 mod node;
 mod pipeline;
+mod readback;
+mod sensor;
+#[cfg(feature = "serde")]
+mod serialize;
+pub mod units;
+
+pub use curve::CompensationCurve;
+#[cfg(feature = "serde")]
+pub use curve::{CompensationCurveLoader, CompensationCurveLoaderError};
+#[cfg(feature = "debug-gizmos")]
+pub use debug::AutoExposureGizmos;
+#[cfg(feature = "debug")]
+pub use debug::{
+    AutoExposureCurveOverlay, AutoExposureDebugOverlay, HistogramReadback, HistogramReadbackEvent,
+};
+#[cfg(feature = "debug-text")]
+pub use debug::{AutoExposureDebugText, DebugTextCorner};
+pub use diagnostics::{
+    AutoExposureDiagnosticsPlugin, AutoExposureMeteringDiagnosticsPlugin, MeteringDiagnosticsSlot,
+};
+pub use error::AutoExposureError;
+pub use histogram::{ShareHistogram, ViewHistogramBuffer};
+pub use material::AutoExposureGpu;
+pub use node::{AutoExposureLabel, AutoExposureNode};
+pub use pipeline::{AutoExposureParams, MAX_EXCLUSION_RECTS};
+pub use readback::{
+    AutoExposureSet, CaptureReady, CurrentAutoExposureParams, ExposureCapturedEvent,
+    ExposureSettled, MeteredLuminance,
+};
+pub use sensor::{LightSensor, SensedLuminance};
+#[cfg(feature = "serde")]
+pub use serialize::with_asset_server;
+
+/// Plugin for the auto exposure feature, which meters with a histogram of 256 bins.
+///
+/// Metering uses compute shaders. On devices without them, like WebGL2, auto exposure is
+/// disabled with an error, unless the `fragment_fallback` feature is enabled. That feature
+/// meters with fragment shaders instead, which is less accurate; see the readme for details.
+pub type AutoExposurePlugin = BinnedAutoExposurePlugin<DEFAULT_HISTOGRAM_BINS>;
+
+/// [`AutoExposurePlugin`] with a metering histogram of `BINS` bins.
+///
+/// More bins resolve the metered luminance more finely, at the cost of workgroup memory and of
+/// the time it takes to average them. The bin count must be a power of two between 16 and 4096,
+/// which is checked at compile time:
+///
+/// ```compile_fail
+/// # use bevy_mod_auto_exposure::BinnedAutoExposurePlugin;
+/// let plugin = BinnedAutoExposurePlugin::<100>::default();
+/// # bevy::prelude::App::new().add_plugins(plugin);
+/// ```
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_auto_exposure::BinnedAutoExposurePlugin;
+/// fn build(app: &mut App) {
+///     app.add_plugins(BinnedAutoExposurePlugin::<64>::default());
+/// }
+/// ```
+pub struct BinnedAutoExposurePlugin<const BINS: usize> {
+    /// Where in the render graph the view is metered.
+    pub metering_tap: MeteringTap,
+    /// The size of the workgroups that build the metering histogram, in pixels. Defaults to
+    /// 16x16.
+    ///
+    /// Which size is fastest depends on the GPU, so this can be tuned by profiling the
+    /// [`AutoExposureLabel`] node, or by comparing a few sizes with
+    /// `cargo bench --bench histogram_workgroup_size`. Sizes that the device doesn't support are
+    /// replaced by the default with a warning.
+    pub histogram_workgroup_size: UVec2,
+    /// When set, metering pauses while none of the app's windows has focus, for example when
+    /// they are minimized, to save power. The exposure of every camera is frozen at its last
+    /// value while paused, and adapts on from there once a window regains focus.
+    pub pause_when_unfocused: bool,
+    /// Whether the [`AutoExposureLabel`] node is wired into the `Core3d` graph, between the main
+    /// pass and tone mapping, or around bloom as the `metering_tap` sets. Defaults to `true`.
+    ///
+    /// Without the edges, the node is still added to the graph, but it's up to the app to order
+    /// it: after the main pass that it meters, and before the nodes that read the exposure, like
+    /// tone mapping. See [`without_graph_edges`](Self::without_graph_edges).
+    pub graph_edges: bool,
+}
+
+impl<const BINS: usize> Default for BinnedAutoExposurePlugin<BINS> {
+    fn default() -> Self {
+        Self {
+            metering_tap: MeteringTap::default(),
+            histogram_workgroup_size: DEFAULT_HISTOGRAM_WORKGROUP_SIZE,
+            pause_when_unfocused: false,
+            graph_edges: true,
+        }
+    }
+}
+
+impl<const BINS: usize> BinnedAutoExposurePlugin<BINS> {
+    /// The default plugin, without the edges of the [`AutoExposureLabel`] node, for apps with a
+    /// customized render graph that order the node themselves.
+    ///
+    /// ```
+    /// # use bevy::{core_pipeline::core_3d::graph::{Core3d, Node3d}, prelude::*};
+    /// # use bevy::render::{render_graph::RenderGraphApp, RenderApp};
+    /// # use bevy_mod_auto_exposure::{AutoExposureLabel, AutoExposurePlugin};
+    /// fn build(app: &mut App) {
+    ///     app.add_plugins(AutoExposurePlugin::without_graph_edges());
+    ///     app.sub_app_mut(RenderApp).add_render_graph_edges(
+    ///         Core3d,
+    ///         (Node3d::EndMainPass, AutoExposureLabel, Node3d::Tonemapping),
+    ///     );
+    /// }
+    /// ```
+    pub fn without_graph_edges() -> Self {
+        Self {
+            graph_edges: false,
+            ..default()
+        }
+    }
+}
 
-/// Plugin for the auto exposure feature.
-pub struct AutoExposurePlugin;
+/// Where in the render graph the view is metered, relative to bloom.
+/// When the render graph has no bloom node, the view is always metered right after the main pass.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MeteringTap {
+    /// Meter the view before bloom is applied. This is usually the most correct, since bloom
+    /// adds energy to the image: metering after it darkens the view around bright lights more
+    /// than the lights alone would.
+    #[default]
+    PreBloom,
+    /// Meter the view after bloom is applied, so bright lights with a lot of bloom pull down
+    /// the exposure more.
+    PostBloom,
+}
 
 /// Component that enables auto exposure for a camera.
+///
+/// With the `serde` feature, this can be (de)serialized. Asset handles are stored as their
+/// asset path, see `with_asset_server` for deserializing them.
 #[derive(Component, Clone, Reflect)]
-#[reflect(Component)]
+#[reflect(Component, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct AutoExposure {
-    /// The minimum exposure value for the camera.
+    /// Whether auto exposure is applied. When disabled, no metering work is done and the
+    /// camera's own exposure is used. Re-enabling resumes from the last adapted exposure.
+    pub enabled: bool,
+    /// The minimum exposure value for the camera, in EV. See [`units`] for what that means.
     pub min: f32,
-    /// The maximum exposure value for the camera.
+    /// The maximum exposure value for the camera, in EV.
+    ///
+    /// `min` and `max` can be changed at runtime, for example between areas with different
+    /// lighting. The adapted exposure is kept in absolute EV, so it doesn't jump when the range
+    /// changes, and adapts to the new range at the configured speed. An eased transition that
+    /// is in progress starts over from the current exposure, towards the target of the new range.
+    ///
+    /// Pixels brighter than `max` are counted in the top bin, so scenes with very bright
+    /// highlights, like the specular highlights of an HDR render, need a wide range to keep
+    /// them from skewing the percentiles. Ranges up to `-24..24` EV are well within the
+    /// precision of the metering. Each bin covers `(max - min) / (bins - 2)` EV though, so for
+    /// ranges that wide use [`PercentileMode::Interpolated`], more bins with
+    /// [`BinnedAutoExposurePlugin`], or a [`BinDistribution`] that spends them where it matters.
     pub max: f32,
     /// The percentage of darkest pixels to ignore when metering.
+    /// Must be less than `high_percent`; out of range values are clamped.
     pub low_percent: u32,
     /// The percentage of brightest pixels to ignore when metering.
+    /// Must be in `1..=100`; out of range values are clamped.
     pub high_percent: u32,
+    /// How the bins that `low_percent` and `high_percent` cut through are averaged.
+    pub percentile_mode: PercentileMode,
+    /// The exposure to start adapting from, when the camera is spawned. Setting this close to
+    /// the expected exposure avoids a visible adaptation when a level loads.
+    /// When `None`, adaptation starts at an exposure of zero.
+    pub initial_ev: Option<f32>,
     /// The speed at which the exposure adapts from dark to bright scenes.
+    ///
+    /// The exposure approaches its target exponentially, closing `1 - exp(-speed_up * dt)` of
+    /// the remaining difference every frame. A speed of 3 covers 95% of the difference in a
+    /// second, regardless of the frame rate.
     pub speed_up: f32,
     /// The speed at which the exposure adapts from bright to dark scenes.
+    /// See [`AutoExposure::speed_up`].
     pub speed_down: f32,
+    /// A curve that shapes the way of the exposure from where it started adapting to its
+    /// target, as points that map the linear progress, from zero to one, to the eased progress.
+    /// `None`, the default, approaches the target exponentially.
+    ///
+    /// The linear progress approaches one at `speed_up` or `speed_down`, like the exposure does
+    /// without a curve, and the exposure covers the eased share of the way. So the speeds still
+    /// set how long a transition takes, and a linear curve adapts like no curve while the target
+    /// holds still. An ease-in curve like `[(0, 0), (0.5, 0.2), (1, 1)]` starts large transitions
+    /// gently, instead of at their fastest.
+    ///
+    /// A transition starts when the exposure starts adapting, and starts over when the target
+    /// turns around, or moves further than the exposure has left to go. `max_ev_per_second`
+    /// still limits the exposure on its way. Ignored by the `fragment_fallback` metering.
+    pub easing_curve: Option<Vec<Vec2>>,
+    /// The exposure holds still while the metered target is within this many EV of it, and
+    /// only starts adapting once the difference exceeds it.
+    ///
+    /// This stops the exposure from drifting constantly in nearly static scenes, at the cost
+    /// of responsiveness: small changes in the scene brightness are never corrected.
+    pub deadband_ev: f32,
+    /// The most the exposure may change in a second, in EV, on top of `speed_up` and
+    /// `speed_down`. `None` doesn't limit it.
+    ///
+    /// This is a hard ceiling regardless of the adaptation speed, for example to keep large
+    /// jumps in brightness comfortable for players sensitive to motion. Resets still snap to
+    /// the target.
+    pub max_ev_per_second: Option<f32>,
+    /// When set, the exposure adapts on the real time, ignoring the relative speed and pauses
+    /// of the virtual [`Time`]. This keeps the exposure adapting while the game is paused or
+    /// in slow motion.
+    pub use_real_time: bool,
+    /// When set, every frame adapts the exposure by this many seconds, instead of the time
+    /// that the frame took. This overrides `use_real_time`.
+    ///
+    /// Given the same frames, the exposure then evolves the same way on every run, whatever
+    /// the frame rate, for deterministic replays and fixed timestep games. Use the timestep of
+    /// [`Time<Fixed>`](bevy::time::Fixed) to adapt in step with the fixed update. Frames skipped
+    /// by `metering_interval` count the same delta each.
+    pub fixed_delta_time: Option<f32>,
+    /// How much of the previous frames' histogram is blended into the histogram of this frame
+    /// before it's averaged, from zero to below one. Zero meters every frame on its own.
+    ///
+    /// This smooths the metering itself: a flash that lasts a frame only shifts a share of the
+    /// histogram, so it barely moves the metered average, and a lasting change fades in over
+    /// a few frames. `speed_up` and `speed_down` instead smooth the exposure after the average,
+    /// so a flash still sets the target, and the exposure starts towards it. The blend is
+    /// applied every frame, so the window it covers shortens at higher frame rates. Resets
+    /// don't blend, and changing `min`, `max` or `bin_distribution` starts the blend over.
+    /// Ignored by the `fragment_fallback` metering.
+    pub temporal_blend: f32,
     /// The mask to apply when metering. Bright spots on the mask will contribute more to the
-    /// metering, and dark spots will contribute less.
+    /// metering, and dark spots will contribute less. While the mask is black everywhere, and
+    /// `mask_bias` doesn't lift it, nothing is metered and the exposure is held.
+    #[cfg_attr(feature = "serde", serde(with = "serialize::handle"))]
     pub metering_mask: Handle<Image>,
+    /// A second metering mask to cross-fade to, and how far to fade to it in `0..=1`.
+    /// At zero only `metering_mask` is used, and at one only this mask. This can transition
+    /// between masks smoothly, for example when the player aims down sights.
+    /// While either mask isn't loaded, it weighs the whole view evenly, see `wait_for_mask`.
+    #[cfg_attr(feature = "serde", serde(with = "serialize::mask_blend"))]
+    pub metering_mask_blend: Option<(Handle<Image>, f32)>,
+    /// A mask that is layered onto the cross-faded metering masks, and how the two are
+    /// combined, for example a center weighted mask multiplied by a mask of a region of
+    /// interest. The combined weights are then transformed by `mask_gain` and `mask_bias`.
+    ///
+    /// The layer is stretched across the whole view, regardless of `mask_region`, and plays
+    /// along with `mask_frame` if it's a flip book. While it isn't loaded, it weighs the whole
+    /// view evenly, see `wait_for_mask`.
+    #[cfg_attr(feature = "serde", serde(with = "serialize::mask_layer"))]
+    pub metering_mask_layer: Option<(Handle<Image>, MaskCombine)>,
+    /// When set, metering waits for the metering masks to load, and holds the exposure until
+    /// they have. Without it, a mask that is still loading weighs the whole view evenly, so
+    /// the exposure can pop once it has loaded. Defaults to false.
+    ///
+    /// The default `metering_mask` handle, which weighs the whole view evenly, is never waited
+    /// for. A mask that fails to load holds the exposure for good.
+    pub wait_for_mask: bool,
+    /// The region of the metering masks that is stretched across the view, in normalized
+    /// texture coordinates (`0..=1`, top left is zero). Defaults to the whole mask.
+    ///
+    /// This allows packing several masks into one atlas, and switching between them by
+    /// changing the region, for example to weigh the screen thirds of an ultrawide display
+    /// differently. The region applies to both `metering_mask` and `metering_mask_blend`.
+    pub mask_region: Rect,
+    /// Scales the weights of the metering masks, after they are cross-faded and combined with
+    /// the `metering_mask_layer`. Every weight is transformed to
+    /// `clamp(weight * mask_gain + mask_bias, 0, 1)`, so one mask asset can be shared by
+    /// cameras that emphasize it differently. Defaults to one.
+    ///
+    /// A gain below one with a positive bias softens the mask, for example a gain of 0.5 and
+    /// a bias of 0.5 still meters the dark parts of the mask at half weight. A gain above one
+    /// with a negative bias sharpens it.
+    pub mask_gain: f32,
+    /// Offsets the weights of the metering masks, see `mask_gain`. Defaults to zero. With a
+    /// positive bias, even a black mask meters the whole view.
+    pub mask_bias: f32,
+    /// The layer of the metering masks to meter with, for masks that are animated as a flip
+    /// book. Update it every frame to play the animation. Defaults to zero.
+    ///
+    /// An animated mask is a 2D texture array with a frame per layer, for example an image
+    /// loaded as a vertical strip of frames and turned into an array with
+    /// [`Image::reinterpret_stacked_2d_as_array`]. Only the red channel of the frames is used,
+    /// like with other masks. The frame is copied out of the array before metering, since not
+    /// every backend can bind a single layer, so the array needs
+    /// [`TextureUsages::COPY_SRC`](bevy::render::render_resource::TextureUsages::COPY_SRC) in
+    /// its `texture_descriptor.usage`, and must not be compressed. An array without it weighs
+    /// the whole view evenly, and a warning is logged.
+    ///
+    /// The frame is clamped to the last layer of each mask, so a mask that isn't an array
+    /// always meters with its only layer, and a flip book can be cross-faded with a still
+    /// `metering_mask_blend`.
+    pub mask_frame: u32,
+    /// Rectangles of the view that aren't metered, in normalized screen coordinates (`0..=1`,
+    /// top left is zero). Empty by default, which meters the whole view.
+    ///
+    /// This keeps overlays that are drawn before the metering tap, like a large bright HUD of
+    /// an immediate mode UI, from pulling the exposure of the scene down. UI that is drawn
+    /// after tonemapping, like `bevy_ui`, isn't metered anyway. Only the first
+    /// [`MAX_EXCLUSION_RECTS`] rects are used.
+    pub exclusion_rects: Vec<Rect>,
     /// Exposure compensation curve to apply after metering.
     /// The X axis corresponds to the measured exposure, and the Y axis corresponds to the
-    /// exposure compensation to apply.
-    /// Note that the compensation values are clamped between -8 and +8.
+    /// exposure compensation to apply, or the exposure itself, depending on
+    /// `compensation_mode`.
+    /// Note that in [`CompensationMode::Additive`], the compensation values are clamped between
+    /// -8 and +8.
     pub compensation_curve: Vec<Vec2>,
+    /// A [`CompensationCurve`] asset to use instead of `compensation_curve`. The curve is
+    /// re-baked whenever the asset changes. While the asset is loading, `compensation_curve`
+    /// is used.
+    #[cfg_attr(feature = "serde", serde(with = "serialize::optional_handle"))]
+    pub compensation_curve_asset: Option<Handle<CompensationCurve>>,
+    /// How the output of the compensation curve is applied.
+    pub compensation_mode: CompensationMode,
+    /// A correction, in EV, that is added to the target exposure after the compensation curve,
+    /// in either [`CompensationMode`]. Positive values brighten the image.
+    pub correction: f32,
+    /// When set, the target exposure follows the key of the scene, so high-key scenes, which
+    /// are mostly bright, stay bright, and low-key scenes, which are mostly dark, stay dark,
+    /// instead of both being exposed as mid gray. Defaults to off.
+    ///
+    /// The key is where the metered average lies within the spread of the metered pixels,
+    /// which spans from the log luminance at `low_percent` to the one at `high_percent`. The
+    /// target exposure is offset by
+    ///
+    /// ```text
+    /// key_strength * (average - (low + high) / 2)
+    /// ```
+    ///
+    /// in EV, so a scene whose average is in the middle of its spread is exposed as usual,
+    /// and the offset grows with how far the average leans towards either end, and with the
+    /// contrast of the scene. The offset applies after the compensation curve, like
+    /// `correction`. Ignored by the `fragment_fallback` metering, which doesn't meter the spread.
+    pub adaptive_key: bool,
+    /// How strongly [`adaptive_key`](Self::adaptive_key) follows the key of the scene, from
+    /// zero, which ignores it, upwards. At one, a scene whose average lies at the top of its
+    /// spread is exposed half its contrast brighter. Defaults to 0.5.
+    pub key_strength: f32,
+    /// How strongly the shadows and the highlights of the scene count towards the metered
+    /// average, for a filmic response that protects the highlights or lifts the shadows. The
+    /// default weighs both evenly, which meters like a single histogram.
+    pub tone_weights: ToneWeights,
+    /// How the exposure for the environment, like the skybox, is derived from the adapted
+    /// exposure, so it can be dimmed less aggressively than the rest of the scene.
+    pub environment_exposure: EnvironmentExposure,
+    /// The display luminance, in nits, to show the metered scene at, for HDR displays. `None`
+    /// keeps the SDR behavior, which exposes the metered scene as mid gray relative to the
+    /// white of the display.
+    ///
+    /// This plugin only outputs an exposure, in EV, and doesn't know how the tonemapper and
+    /// the display map the exposed image to nits. So the exposure is offset by
+    /// `log2(target_nits / paper_white_nits)`, assuming that an exposed value of one is shown
+    /// at `paper_white_nits`. Tonemapping compresses highlights, so bright targets end up
+    /// dimmer on screen than requested. The offset applies after the compensation curve, like
+    /// `correction`.
+    pub target_nits: Option<f32>,
+    /// The display luminance, in nits, that the downstream tonemapping and HDR output show an
+    /// exposed value of one at. Only used with `target_nits`. Defaults to 80 nits, the white of
+    /// an sRGB display.
+    pub paper_white_nits: f32,
+    /// The scene luminance, in cd/m² (nits), to expose as mid gray, for scenes that are
+    /// calibrated to real world units. `None`, the default, exposes the metered average as mid
+    /// gray instead.
+    ///
+    /// When set, the exposure no longer follows the brightness of the scene: a surface of this
+    /// luminance is always shown as mid gray, like with a light meter that was set by hand. The
+    /// metered average still drives the compensation curve and `adaptive_key`, so they can
+    /// brighten or darken the image by how bright the scene is, and `correction`, `max_gain_ev`
+    /// and the adaptation speeds apply as usual.
+    ///
+    /// The luminance is converted to the units of the metered image with the camera's
+    /// [`Exposure`](bevy::render::camera::Exposure), like bevy does when it renders physically
+    /// based lights: an unlit value of one is `1 / exposure` cd/m², where `exposure` is
+    /// `2^-ev100 / 1.2`. So this only matches real world units when the lights and emissive
+    /// materials of the scene use physically based intensities, and a
+    /// [`MeteringSource::Image`] is assumed to be rendered with the camera's exposure too. See
+    /// the [`units`] module for the calibration of the readbacks.
+    pub mid_gray_luminance: Option<f32>,
+    /// The metered log luminance, in EV, below which a scene counts as black. Darker scenes are
+    /// exposed as if they were at this luminance, so staring into a pitch black corner doesn't
+    /// push the exposure up to amplify noise.
+    ///
+    /// Unlike `min` and `max`, which limit the exposure, this limits the scene luminance that
+    /// the exposure adapts to. Defaults to negative infinity, which never limits it.
+    pub dark_floor_ev: f32,
+    /// The most gain, in EV, that the exposure may apply on top of the camera's own exposure.
+    /// Scenes that would need more are held at this gain and stay dark, instead of being
+    /// boosted until their noise shows, like a camera at its highest ISO.
+    ///
+    /// This caps the target exposure after the compensation curve and `correction`, so it
+    /// holds whatever the curve asks for. `min` and `max` instead bound the metered luminance,
+    /// which only indirectly bounds the exposure to `-max..-min` before compensation, and
+    /// `dark_floor_ev` raises dark scenes before the curve is applied. The tightest of these
+    /// wins. Defaults to infinity, which never limits it.
+    pub max_gain_ev: f32,
+    /// When set, pixels brighter than this log luminance, in EV, aren't metered at all. This
+    /// keeps specular highlights and emissive UI from darkening the scene.
+    ///
+    /// Unlike `high_percent`, which ignores a share of the brightest pixels, this is an absolute
+    /// cap. Pixels above `max` are otherwise counted as if they were at `max`.
+    pub saturation_cutoff: Option<f32>,
+    /// When set, every pixel is weighed by the alpha channel of the metering source, so partly
+    /// covered pixels contribute less.
+    ///
+    /// This only makes sense for sources whose alpha holds coverage, like an offscreen target
+    /// that is composited later. The main texture of a view usually holds an alpha of one, or
+    /// garbage, so this is off by default.
+    pub weight_by_alpha: bool,
+    /// When set, pixels at or beyond this distance from the camera, in world units, aren't
+    /// metered at all. This keeps a bright sky from darkening everything else in outdoor scenes,
+    /// as the sky is infinitely far away.
+    ///
+    /// This reads the depth of the depth prepass, so the camera needs a [`DepthPrepass`]. Without
+    /// one, a warning is logged and every pixel is metered. It is also ignored when metering a
+    /// [`MeteringSource::Image`], and on devices without compute shaders.
+    pub exclude_depth: Option<f32>,
+    /// When set, pixels are weighed by how close they are to this distance from the camera, in
+    /// world units, like the focus distance of a depth of field effect. A character that the
+    /// camera focuses on is then exposed for, even while a bright background fills most of
+    /// the view, the way a cinematographer exposes for the subject in focus. Update it along
+    /// with the depth of field to rack the exposure with the focus.
+    ///
+    /// The distance is measured along the view direction, like the depth of field of bevy.
+    /// The weights multiply those of the metering mask and mode, so this can be combined with
+    /// spot metering. Like `exclude_depth`, this needs a [`DepthPrepass`], without which a warning
+    /// is logged and every pixel is weighed evenly, and it is ignored when metering a
+    /// [`MeteringSource::Image`], and on devices without compute shaders.
+    pub focus_distance: Option<f32>,
+    /// How quickly the weight of pixels falls off away from `focus_distance`: it halves every
+    /// `focus_falloff` world units in front of or behind the focus plane, so pixels more than
+    /// three falloffs away aren't metered. Defaults to one.
+    pub focus_falloff: f32,
+    /// How the bins of the metering histogram are spaced over the `min..max` range.
+    pub bin_distribution: BinDistribution,
+    /// A 3D LUT that transforms the color of every pixel before its luminance is binned, to
+    /// meter in a custom color space, for example one with a film-like response.
+    ///
+    /// The LUT is indexed by the log2 of every channel, normalized from `min..max` to `0..=1`,
+    /// the way LUTs for HDR colors are usually shaped, and sampled with the image's own sampler.
+    /// It outputs a linear color. The percentiles then operate on the log luminance of the
+    /// output instead of the scene, so a LUT that compresses highlights also keeps them from
+    /// dominating `high_percent`. Other settings, like `saturation_cutoff`, still apply to the
+    /// scene luminance.
+    ///
+    /// Without a LUT, or while it isn't loaded, the scene luminance is metered. The image must
+    /// be 3D, with a filterable format, or a warning is logged and it is ignored. The LUT is
+    /// also ignored on devices without compute shaders.
+    #[cfg_attr(feature = "serde", serde(with = "serialize::optional_handle"))]
+    pub metering_lut: Option<Handle<Image>>,
+    /// How the luminance of every metered pixel is computed from its color.
+    pub luminance_model: LuminanceModel,
+    /// When set, the view is first downsampled to this resolution before metering.
+    /// Metering tolerates a low resolution well, so this saves a lot of work on large render
+    /// targets at the cost of some accuracy. The resolution is clamped to the viewport size.
+    pub metering_resolution: Option<UVec2>,
+    /// The mip level of the metering source to meter. Every level halves the resolution, which
+    /// saves bandwidth much like `metering_resolution`, but without the extra downsample pass.
+    ///
+    /// The mips are only as good as whatever generated them, while the downsample pass always
+    /// averages every pixel of the view. Both can be combined, in which case the mip is
+    /// downsampled further. The main texture of a view has no mips, so this is mostly useful
+    /// with a [`MeteringSource::Image`] that has them. When the source lacks the mip level, a
+    /// warning is logged and the full resolution is metered instead.
+    pub metering_mip: u32,
+    /// Only every this many pixels are metered, in each dimension, so a stride of 4 meters a
+    /// 16th of the pixels. Defaults to 1, which meters every pixel.
+    ///
+    /// This is a cheap way to cut the cost of metering large render targets, for example on
+    /// integrated GPUs, as it needs no extra pass. Unlike `metering_resolution`, skipped pixels
+    /// aren't averaged into the metered ones, so high strides make the metering noisy: small
+    /// bright spots flicker in and out as the view moves, and the exposure follows. The counts
+    /// of a `HistogramReadbackEvent` shrink with the stride. Zero is treated as one. Ignored on
+    /// devices without compute shaders.
+    pub sample_stride: u32,
+    /// Only every this many frames are metered, to cut the cost of metering further. Defaults
+    /// to 1, which meters every frame, and zero is treated as one.
+    ///
+    /// The exposure is held between metered frames, and a metered frame adapts for the time of
+    /// the frames it skipped as well, so the speed of adaptation doesn't change, but it moves
+    /// in coarser steps. The first frame after the camera is spawned, and frames that reset
+    /// the exposure, are always metered. `temporal_blend` blends metered frames only, so it
+    /// covers a longer window at higher intervals.
+    pub metering_interval: u32,
+    /// Which part of the view to meter, on top of the metering mask.
+    /// This is overridden by a [`MeteringFocus`] on the same camera.
+    pub metering_mode: MeteringMode,
+    /// The time, in seconds, over which the weighting of the view cross-fades to a new metering
+    /// mode, when it changes at runtime. Defaults to 0, which switches instantly.
+    ///
+    /// Changing the kind of `metering_mode`, or a [`MeteringFocus`] that moves on or off screen,
+    /// starts a fade from the weighting of the previous mode. A spot that moves, by its settings
+    /// or by a [`MeteringFocus`], is followed without fading. A change during a fade starts from
+    /// the weighting that was blended so far.
+    pub metering_mode_fade: f32,
+    /// The image to meter. Defaults to the view itself.
+    pub metering_source: MeteringSource,
+    /// Where the adapted exposure is written to.
+    pub output: ExposureOutput,
+}
+
+/// How the compensation curve of an [`AutoExposure`] camera is applied.
+///
+/// In both modes, [`AutoExposure::correction`] is added on top of the result.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CompensationMode {
+    /// The curve outputs a compensation, in EV, that is added to the exposure that would
+    /// expose the metered luminance as mid gray.
+    #[default]
+    Additive,
+    /// The curve outputs the target exposure itself, in EV, ignoring what the metered
+    /// luminance would call for. This can author a fully custom response, like keeping dark
+    /// scenes dark for mood. The exposure still adapts to the target at the configured speed.
+    Override,
+}
+
+/// How the metered average of an [`AutoExposure`] camera weighs its shadows against its
+/// highlights, see [`AutoExposure::tone_weights`].
+///
+/// The metered pixels are split at a log luminance into shadows and highlights, as if they were
+/// two histograms, and both are averaged together with their weights. A larger
+/// `highlights` weight pulls the average up when the scene has bright areas, which exposes it
+/// darker and protects the highlights, and a larger `shadows` weight lifts dark areas instead.
+/// Weighing one side at zero meters only the other, and the exposure is held while that side
+/// is empty.
+///
+/// The split applies after `low_percent` and `high_percent` have trimmed the histogram, and
+/// only to the average, not to the spread that `adaptive_key` follows. Ignored by the
+/// `fragment_fallback` metering, which doesn't meter a histogram.
+#[derive(Clone, Copy, Debug, PartialEq, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ToneWeights {
+    /// The log luminance, in EV, that divides the shadows from the highlights. Defaults to 0.
+    pub split: f32,
+    /// The weight of every metered pixel darker than `split`. Defaults to 1.
+    pub shadows: f32,
+    /// The weight of every metered pixel at or above `split`. Defaults to 1.
+    pub highlights: f32,
+}
+
+impl Default for ToneWeights {
+    fn default() -> Self {
+        Self {
+            split: 0.0,
+            shadows: 1.0,
+            highlights: 1.0,
+        }
+    }
+}
+
+/// How the environment exposure of an [`AutoExposure`] camera is derived from its adapted
+/// exposure, see [`AutoExposure::environment_exposure`].
+///
+/// The environment exposure is `exposure * scale + correction`, in EV. It is computed from the
+/// same metering every frame, and kept next to the adapted exposure in the
+/// [`AutoExposureGpu`] and [`ViewExposureBuffer`] buffers, for environment materials to read.
+/// It isn't applied to anything by itself: the view uniforms only get the adapted exposure.
+///
+/// As the view's exposure is applied to the whole image, including the environment, a
+/// material that draws the environment should scale its color by
+/// `exp2(environment - exposure)` to end up at the environment exposure.
+#[derive(Clone, Copy, Debug, PartialEq, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EnvironmentExposure {
+    /// How much of the adapted exposure the environment follows. At one it follows fully, and
+    /// at zero it stays at `correction`, whatever the scene.
+    pub scale: f32,
+    /// Added to the environment exposure, in EV.
+    pub correction: f32,
+}
+
+impl Default for EnvironmentExposure {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            correction: 0.0,
+        }
+    }
+}
+
+/// Where the adapted exposure of an [`AutoExposure`] camera is written to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExposureOutput {
+    /// Add the exposure to the camera's own exposure, by writing it into the view uniforms.
+    ///
+    /// This depends on the layout of bevy's view uniform, which is checked at startup. When it
+    /// doesn't match, an error is logged and the exposure isn't written, like with
+    /// [`ExposureOutput::Buffer`].
+    #[default]
+    ViewUniform,
+    /// Leave the view uniforms alone, and only keep the exposure in the [`ViewExposureBuffer`]
+    /// of the view, for a custom render node to apply.
+    Buffer,
+    /// Write the exposure into the camera's [`Exposure`](bevy::render::camera::Exposure)
+    /// component, so it composes with other systems that read or change it.
+    ///
+    /// The exposure is read back from the GPU, so it's applied at least a frame late, usually a
+    /// few, which shows as a short lag after sudden changes. Use [`ExposureOutput::ViewUniform`]
+    /// where that matters. The camera's `Exposure` when this output is selected is kept as the
+    /// base that the adapted exposure is subtracted from, in EV100, and restored when another
+    /// output is selected, or auto exposure is disabled. An [`ExposureOverride`] replaces the
+    /// adapted exposure here too.
+    ///
+    /// The metered view is rendered with the adapted exposure, which is undone before metering.
+    /// That assumes everything in the view follows the camera's `Exposure`, like lit materials
+    /// do, so clear colors and unlit materials are metered as if they got darker as the
+    /// exposure rises.
+    ///
+    /// The [`ViewExposureBuffer`] and the [`AutoExposureGpu`] buffer still hold the adapted
+    /// exposure, which the camera already applies.
+    CameraExposure,
+}
+
+/// Render world component that holds the adapted exposure of an [`AutoExposure`] view, for
+/// custom render nodes. This is present regardless of the [`ExposureOutput`].
+///
+/// The buffer starts with the exposure as an `f32`, in EV, followed by the metered average log
+/// luminance and the [`EnvironmentExposure`], in EV, at a byte offset of 8. It can be bound as
+/// a read only storage buffer, or copied from. The exposure is updated by the
+/// [`AutoExposureLabel`] node.
+#[derive(Component, Clone)]
+pub struct ViewExposureBuffer {
+    pub buffer: Buffer,
+}
+
+/// The image that is metered by an [`AutoExposure`] camera.
+#[derive(Clone, Debug, Default, PartialEq, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MeteringSource {
+    /// Meter the main texture of the view, after the main pass.
+    ///
+    /// Only the [`Viewport`](bevy::render::camera::Viewport) of the camera is metered, so
+    /// cameras that share a render target, like split screen players or a picture in picture
+    /// inset, each meter what they show. Cameras on the same target also share its main
+    /// texture, so a camera with [`ClearColorConfig::None`](bevy::render::camera::ClearColorConfig)
+    /// meters the tone mapped image of the camera before it wherever it draws nothing itself.
+    #[default]
+    View,
+    /// Meter an image supplied by the user. Metering is skipped while the image isn't loaded.
+    ///
+    /// This can be used to meter only a subset of the scene: render the objects of interest
+    /// with a second camera, using [`RenderLayers`](bevy::render::view::RenderLayers) and an
+    /// image render target, and pass that image here. This is considerably heavier than
+    /// metering the view, as the objects are rendered twice.
+    ///
+    /// It can also drive the exposure of one camera by what another one sees, like a security
+    /// camera or a mirror that renders to an image: meter that image from the camera that shows
+    /// it. Give the camera that renders the image a lower [`Camera::order`], so the image is
+    /// rendered before it's metered, instead of a frame later.
+    Image(#[cfg_attr(feature = "serde", serde(with = "serialize::handle"))] Handle<Image>),
+    /// Meter the light at a [`LightSensor`] entity instead of an image. The lights are added
+    /// up on the CPU, so nothing is rendered or metered on the GPU besides the average. See
+    /// [`LightSensor`] for what it leaves out.
+    ///
+    /// Metering is skipped while the entity has no sensor, and on devices without compute
+    /// shaders, which hold the exposure instead.
+    LightSensor(Entity),
+}
+
+/// How the bins of the metering histogram are spaced over the metering range.
+///
+/// The histogram has a fixed number of bins, so the spacing decides where the metering is most
+/// precise. For scenes with an extreme dynamic range, a wide `min..max` range spreads the bins
+/// thin, and spending more of them on the luminances that matter keeps the average precise
+/// there. The average is always taken in log luminance, whatever the spacing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BinDistribution {
+    /// Space the bins evenly in log2 luminance, so every bin covers the same number of EV.
+    #[default]
+    Log2,
+    /// Space the bins along a power curve of the log2 luminance, normalized to the metering
+    /// range.
+    Perceptual {
+        /// The exponent of the curve. Below one, more bins cover the darker part of the range,
+        /// and above one the brighter part. Must be positive.
+        gamma: f32,
+    },
+}
+
+impl BinDistribution {
+    /// The exponent that the normalized log luminance is raised to, to find its bin.
+    fn gamma(self) -> f32 {
+        match self {
+            BinDistribution::Log2 => 1.0,
+            BinDistribution::Perceptual { gamma } => gamma.max(f32::EPSILON),
+        }
+    }
+}
+
+/// How the percentiles of an [`AutoExposure`] camera cut off the metering histogram.
+///
+/// A percentile usually falls somewhere within a bin. The pixels of that bin that are kept are
+/// counted either way, but without knowing where in the bin they are, they are averaged at the
+/// bin itself, or interpolated within it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PercentileMode {
+    /// Average the kept pixels of the cut off bin at that bin.
+    #[default]
+    Nearest,
+    /// Assume that the pixels of the cut off bin are spread evenly over it, and average the
+    /// kept ones at the middle of the part of the bin that is kept.
+    ///
+    /// This keeps the average from stepping by whole bins as the percentiles cut through a
+    /// bin, which helps when each bin covers a lot of EV, like with a wide `min..max` range or
+    /// a [`BinDistribution`] that spends few bins on the metered luminance, and in scenes with
+    /// few distinct luminances, where most pixels fall into a handful of bins.
+    Interpolated,
+}
+
+/// How an [`AutoExposure`] camera computes the luminance of a pixel from its color.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LuminanceModel {
+    /// The linear Rec. 709 luma of the color.
+    #[default]
+    Linear,
+    /// The perceptual lightness of the color, as the `L` of OKLab, cubed back into the units of
+    /// luminance.
+    ///
+    /// Grays meter the same either way, but saturated colors, whose luma is low for how bright
+    /// they look, like a blue sky or colored lights, meter brighter. The histogram, the
+    /// percentiles and [`MeteredLuminance`] stay in log luminance, of the cubed lightness. The
+    /// readings of a [`LightSensor`] are always linear.
+    Perceptual,
+}
+
+/// How [`AutoExposure::metering_mask_layer`] is combined with the metering masks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MaskCombine {
+    /// Multiply the weights, so only pixels that both masks weigh are metered.
+    #[default]
+    Multiply,
+    /// Add the weights, clamped to one, so pixels that either mask weighs are metered.
+    Add,
+    /// Take the larger weight, which meters pixels that either mask weighs like `Add`, without
+    /// weighing the overlap more.
+    Max,
+}
+
+/// Selects the region of the view that contributes to metering.
+#[derive(Clone, Debug, Default, PartialEq, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MeteringMode {
+    /// Meter the whole view.
+    #[default]
+    Average,
+    /// Only meter a circle on the view.
+    Spot {
+        /// The center of the circle, in normalized screen coordinates (`0..=1`, top left is zero).
+        center: Vec2,
+        /// The radius of the circle, as a fraction of the view height.
+        radius: f32,
+    },
+    /// Only meter a rectangle on the view, like the metering box of a camera.
+    SpotRect {
+        /// The rectangle, in normalized screen coordinates (`0..=1`, top left is zero). It is
+        /// clamped to the view, and meters the whole view when nothing of it is left.
+        rect: Rect,
+    },
+    /// Weigh the view by the distance from its center, falling off towards the edges.
+    /// This is a shortcut for a [`MeteringMode::Radial`] curve of `(1 - distance)^falloff`.
+    CenterWeighted {
+        /// How fast the weight falls off. At zero the whole view is weighed evenly, at one the
+        /// weight falls off linearly, and higher values concentrate it on the center.
+        falloff: f32,
+    },
+    /// Weigh the view by a curve of the distance from its center. This can build donut shaped
+    /// or edge emphasizing masks without authoring a metering mask image.
+    Radial {
+        /// The curve, which maps the distance from the center to a weight in `0..=1`. The
+        /// distance is normalized so that the center is at zero and the corners are at one.
+        /// The curve is linearly interpolated between its points, which must be sorted by
+        /// distance.
+        curve: Vec<Vec2>,
+    },
+}
+
+impl MeteringMode {
+    /// The curve of the radial weight of this mode, if it weighs the view radially.
+    pub(crate) fn radial_curve(&self) -> Option<Vec<Vec2>> {
+        match self {
+            MeteringMode::Average | MeteringMode::Spot { .. } | MeteringMode::SpotRect { .. } => {
+                None
+            }
+            MeteringMode::CenterWeighted { falloff } => Some(
+                (0..=16)
+                    .map(|i| {
+                        let distance = i as f32 / 16.0;
+                        vec2(distance, (1.0 - distance).powf(falloff.max(0.0)))
+                    })
+                    .collect(),
+            ),
+            MeteringMode::Radial { curve } => Some(curve.clone()),
+        }
+    }
+}
+
+/// Component that focuses the metering of an [`AutoExposure`] camera on a point in the world,
+/// for example the player character in a third person game.
+///
+/// The point is projected onto the view every frame and spot metered. When the point is off
+/// screen, the camera falls back to metering the whole view.
+#[derive(Component, Clone, Copy, Debug, Reflect)]
+#[reflect(Component)]
+pub struct MeteringFocus {
+    /// The world space position to focus on.
+    pub world_pos: Vec3,
+    /// The world space radius around `world_pos` to meter.
+    pub radius: f32,
+}
+
+/// Component that biases the target exposure of an [`AutoExposure`] camera, in EV. Positive
+/// values brighten the image.
+///
+/// This is added on top of [`AutoExposure::correction`], so gameplay systems, like weather or
+/// time of day, can drive it every frame without fighting over the authored settings of the
+/// camera. The exposure adapts to the biased target at the configured speed.
+#[derive(Component, Clone, Copy, Debug, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct ExposureBias(pub f32);
+
+/// Component that overrides the exposure that an [`AutoExposure`] camera applies, in EV.
+///
+/// The camera keeps metering and adapting as usual, but the view uniforms and the
+/// [`AutoExposureGpu`] buffer get this exposure instead. This allows post-processing the
+/// adapted exposure on the CPU, for example to blend it with a hand-authored day and night
+/// curve. Systems that do so should run in [`AutoExposureSet::PostProcess`], which runs after
+/// the adapted exposure is read back into [`ExposureSettled`]:
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_auto_exposure::{AutoExposureSet, ExposureOverride, ExposureSettled};
+/// fn limit_exposure_at_night(mut cameras: Query<(&ExposureSettled, &mut ExposureOverride)>) {
+///     for (settled, mut exposure) in cameras.iter_mut() {
+///         if let Some(adapted) = settled.exposure() {
+///             exposure.0 = adapted.min(2.0);
+///         }
+///     }
+/// }
+///
+/// # let mut app = App::new();
+/// app.add_systems(
+///     First,
+///     limit_exposure_at_night.in_set(AutoExposureSet::PostProcess),
+/// );
+/// ```
+///
+/// The override is applied in the frame that it is set, but the adapted exposure it is based
+/// on is read back from the GPU, so it lags at least one frame behind. The
+/// [`ViewExposureBuffer`], and everything that is read back, still hold the adapted exposure.
+#[derive(Component, Clone, Copy, Debug, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct ExposureOverride(pub f32);
+
+/// Component that meters several [`AutoExposure`] cameras as one, by the id of their group.
+/// For example the six faces of an environment probe, which should all be exposed alike.
+///
+/// The members of a group accumulate their pixels into one histogram, and share one adapted
+/// exposure. The first member in render order, see
+/// [`Camera::order`](bevy::render::camera::Camera::order), averages the histogram that the
+/// group accumulated in the previous frame, before it adds its own pixels. So every member
+/// applies the same exposure in a frame, which is metered one frame behind.
+///
+/// The settings that apply to the whole histogram, like the percentiles and the adaptation
+/// speeds, are taken from the first member. The members must share:
+/// - `min`, `max` and `bin_distribution`, which map luminances to the bins of the histogram;
+/// - `metering_interval`, so they all add to the histogram in the frames that it is averaged.
+///
+/// The settings that apply to every pixel, like the metering mask, mode and source, can
+/// differ between members. The histogram of the group is only available to
+/// `HistogramReadback` and the debug overlay on the first member, with the `debug` feature. Ignored by the
+/// `fragment_fallback` metering, which meters every member on its own.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect)]
+#[reflect(Component)]
+pub struct AutoExposureGroup(pub u32);
+
+/// Bundle of a 3D camera with auto exposure, which defaults to an HDR camera.
+///
+/// Metering an HDR camera is the most accurate, so this saves setting
+/// [`Camera::hdr`](bevy::render::camera::Camera::hdr) by hand. Nothing else is set up for
+/// you: features that need more components, like [`AutoExposure::exclude_depth`], which needs
+/// a [`DepthPrepass`], warn when they are missing. Spawning the components yourself works just
+/// as well.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_auto_exposure::{AutoExposure, AutoExposureBundle};
+/// fn setup(mut commands: Commands) {
+///     commands.spawn(AutoExposureBundle {
+///         auto_exposure: AutoExposure {
+///             min: -16.0,
+///             max: 16.0,
+///             ..default()
+///         },
+///         ..default()
+///     });
+/// }
+/// ```
+#[derive(Bundle)]
+pub struct AutoExposureBundle {
+    /// The camera, with HDR enabled by default.
+    pub camera: Camera3dBundle,
+    /// The auto exposure settings of the camera.
+    pub auto_exposure: AutoExposure,
+}
+
+impl Default for AutoExposureBundle {
+    fn default() -> Self {
+        Self {
+            camera: Camera3dBundle {
+                camera: Camera {
+                    hdr: true,
+                    ..default()
+                },
+                ..default()
+            },
+            auto_exposure: default(),
+        }
+    }
 }
 
 #[derive(Resource)]
@@ -59,9 +990,14 @@ struct AutoExposureResources {
 }
 
 struct ExtractedAutoExposureBuffer {
+    initial_ev: Option<f32>,
     min: f32,
     max: f32,
+    bin_gamma: f32,
     compensation_curve: Vec<Vec2>,
+    compensation_mode: CompensationMode,
+    radial_curve: Option<Vec<Vec2>>,
+    easing_curve: Option<Vec<Vec2>>,
 }
 
 #[derive(Resource)]
@@ -72,29 +1008,355 @@ struct ExtractedAutoExposureBuffers {
 
 struct AutoExposureBuffer {
     exposure: Buffer,
+    /// The blended histogram of the previous frames, see [`AutoExposure::temporal_blend`].
+    histogram_history: Buffer,
+    /// The `min`, `max` and bin gamma that map the bins of the history to luminances.
+    history_bins: (f32, f32, f32),
+    /// The frames left to skip until the next metered frame, see
+    /// [`AutoExposure::metering_interval`].
+    skip_frames: u32,
+    /// The time of the frames skipped since the last metered frame, in seconds.
+    skipped_time: f32,
     compensation_curve: TextureView,
+    radial_weights: TextureView,
+    easing_curve: TextureView,
+    /// The configured and the metered metering mode of the last frame, which differ with a
+    /// [`MeteringFocus`]. `None` before the first frame.
+    metering_mode: Option<(MeteringMode, MeteringMode)>,
+    /// The fade from the previous metering mode, see [`AutoExposure::metering_mode_fade`].
+    mode_fade: Option<MeteringModeFade>,
+    /// The weights of the view that the metering mode fades from, which are uploaded when a fade
+    /// starts.
+    from_weights: Texture,
+    from_weights_view: TextureView,
 }
 
+#[derive(Clone)]
+struct MeteringModeFade {
+    /// The weights of the view that are faded out, see [`metering_mode_weights`].
+    from_weights: Vec<f32>,
+    /// The time since the fade started, in seconds.
+    elapsed: f32,
+}
+
+/// Whether metering is paused, because `pause_when_unfocused` is set and no window has focus.
+#[derive(Resource, Default)]
+pub(crate) struct MeteringPaused(pub bool);
+
 #[derive(Resource, Default)]
 struct AutoExposureBuffers {
     buffers: HashMap<Entity, AutoExposureBuffer>,
+    /// The entities whose component was removed last frame. Their buffers are freed unless the
+    /// component was added back since.
+    removed: Vec<Entity>,
+}
+
+/// The histograms that the members of every [`AutoExposureGroup`] are metered into.
+#[derive(Resource, Default)]
+struct AutoExposureGroups {
+    histograms: HashMap<AutoExposureGroup, Buffer>,
 }
 
 impl Default for AutoExposure {
     fn default() -> Self {
         Self {
+            enabled: true,
             min: -8.0,
             max: 8.0,
             low_percent: 60,
             high_percent: 95,
+            percentile_mode: PercentileMode::Nearest,
+            initial_ev: None,
             speed_up: 3.0,
             speed_down: 1.0,
+            easing_curve: None,
+            deadband_ev: 0.0,
+            max_ev_per_second: None,
+            use_real_time: false,
+            fixed_delta_time: None,
+            temporal_blend: 0.0,
             metering_mask: default(),
+            metering_mask_blend: None,
+            metering_mask_layer: None,
+            wait_for_mask: false,
+            mask_region: Rect::new(0.0, 0.0, 1.0, 1.0),
+            mask_gain: 1.0,
+            mask_bias: 0.0,
+            mask_frame: 0,
+            exclusion_rects: Vec::new(),
             compensation_curve: vec![vec2(-8.0, 0.0), vec2(8.0, 0.0)],
+            compensation_curve_asset: None,
+            compensation_mode: CompensationMode::Additive,
+            correction: 0.0,
+            adaptive_key: false,
+            key_strength: 0.5,
+            tone_weights: ToneWeights::default(),
+            environment_exposure: EnvironmentExposure::default(),
+            target_nits: None,
+            paper_white_nits: 80.0,
+            mid_gray_luminance: None,
+            dark_floor_ev: f32::NEG_INFINITY,
+            max_gain_ev: f32::INFINITY,
+            saturation_cutoff: None,
+            weight_by_alpha: false,
+            exclude_depth: None,
+            focus_distance: None,
+            focus_falloff: 1.0,
+            bin_distribution: BinDistribution::Log2,
+            metering_lut: None,
+            luminance_model: LuminanceModel::Linear,
+            metering_resolution: None,
+            metering_mode: MeteringMode::Average,
+            metering_mode_fade: 0.0,
+            metering_mip: 0,
+            sample_stride: 1,
+            metering_interval: 1,
+            metering_source: MeteringSource::View,
+            output: ExposureOutput::ViewUniform,
         }
     }
 }
 
+impl AutoExposure {
+    /// Sets `min` and `max` to a range that is `stops` EV wide, centered on `center_ev`.
+    /// This is the same as setting `min` and `max` directly, for those who think of the range
+    /// as how many stops it covers.
+    ///
+    /// ```
+    /// # use bevy_mod_auto_exposure::AutoExposure;
+    /// let auto_exposure = AutoExposure::default().with_range_stops(-2.0, 12.0);
+    /// assert_eq!((auto_exposure.min, auto_exposure.max), (-8.0, 4.0));
+    /// ```
+    ///
+    /// The `compensation_curve` isn't moved along, so a curve that was authored for another
+    /// range is clamped at its ends.
+    pub fn with_range_stops(self, center_ev: f32, stops: f32) -> Self {
+        Self {
+            min: center_ev - stops * 0.5,
+            max: center_ev + stops * 0.5,
+            ..self
+        }
+    }
+}
+
+/// The narrowest `min..max` range that is metered, in EV.
+const MIN_METERING_RANGE: f32 = 0.01;
+
+/// Presets for common lighting scenarios. These are a starting point to tweak from.
+///
+/// All ranges are in EV, measured on the rendered image after the camera's own exposure.
+impl AutoExposure {
+    /// Sunlit outdoor scenes. The range extends far up for sunlit surfaces and the sky, and the
+    /// brightest 10% of the view is ignored so the sky doesn't darken the rest of the scene.
+    pub fn outdoor_daylight() -> Self {
+        Self {
+            min: -4.0,
+            max: 12.0,
+            low_percent: 50,
+            high_percent: 90,
+            speed_up: 3.0,
+            speed_down: 1.5,
+            compensation_curve: vec![vec2(-4.0, 0.0), vec2(12.0, 0.0)],
+            ..default()
+        }
+    }
+
+    /// Artificially lit interiors. The range is centered a bit lower than the default, and
+    /// dim rooms are kept slightly darker than mid gray.
+    pub fn indoor() -> Self {
+        Self {
+            min: -10.0,
+            max: 6.0,
+            low_percent: 40,
+            high_percent: 95,
+            speed_up: 2.0,
+            speed_down: 1.0,
+            compensation_curve: vec![vec2(-10.0, -1.0), vec2(-2.0, 0.0), vec2(6.0, 0.0)],
+            ..default()
+        }
+    }
+
+    /// Night time and dark interiors. The range extends far down, but the compensation curve
+    /// keeps dark scenes looking dark instead of brightening them to mid gray. Adapting to the
+    /// dark is slow, like it is for the human eye.
+    pub fn night() -> Self {
+        Self {
+            min: -16.0,
+            max: 4.0,
+            low_percent: 40,
+            high_percent: 98,
+            speed_up: 3.0,
+            speed_down: 0.3,
+            compensation_curve: vec![vec2(-16.0, -4.0), vec2(-4.0, -1.0), vec2(4.0, 0.0)],
+            ..default()
+        }
+    }
+
+    /// A camera operator riding the exposure by hand: nearly the whole view is metered, and
+    /// the exposure changes slowly and evenly in both directions.
+    pub fn film_like() -> Self {
+        Self {
+            min: -8.0,
+            max: 8.0,
+            low_percent: 10,
+            high_percent: 90,
+            speed_up: 0.7,
+            speed_down: 0.7,
+            compensation_curve: vec![vec2(-8.0, -1.0), vec2(0.0, 0.0), vec2(8.0, 0.0)],
+            ..default()
+        }
+    }
+
+    /// The `min..max` range that is metered, with `max` nudged up to [`MIN_METERING_RANGE`]
+    /// above `min` when the range is narrower, or inverted, which would map the whole histogram
+    /// to infinities.
+    pub(crate) fn metering_range(&self) -> (f32, f32) {
+        if self.max - self.min >= MIN_METERING_RANGE {
+            (self.min, self.max)
+        } else {
+            (self.min, self.min + MIN_METERING_RANGE)
+        }
+    }
+}
+
+impl AutoExposure {
+    /// Creates a metering mask that weighs the center of the view fully, and falls off
+    /// smoothly towards the edges, and adds it to `images`. Use it as the `metering_mask`, so no
+    /// mask image has to be authored.
+    ///
+    /// The distance from the center is normalized so that the middle of every edge is at one,
+    /// and the corners are at about 1.41. Pixels closer than `inner` weigh fully, and pixels
+    /// beyond `outer` not at all. The mask is stretched across the view, so the falloff is
+    /// elliptical on views that aren't square.
+    ///
+    /// [`MeteringMode::CenterWeighted`] and [`MeteringMode::Radial`] weigh the view similarly
+    /// without a mask. A mask can still be combined with those, or cross-faded to with
+    /// `metering_mask_blend`.
+    pub fn radial_mask(images: &mut Assets<Image>, inner: f32, outer: f32) -> Handle<Image> {
+        const SIZE: u32 = 256;
+
+        let data = (0..SIZE * SIZE)
+            .map(|i| {
+                let uv = (UVec2::new(i % SIZE, i / SIZE).as_vec2() + 0.5) / SIZE as f32;
+                let distance = ((uv - 0.5) * 2.0).length();
+                let t = ((distance - inner) / (outer - inner).max(f32::EPSILON)).clamp(0.0, 1.0);
+                // Smoothstep from one down to zero.
+                ((1.0 - t * t * (3.0 - 2.0 * t)) * 255.0).round() as u8
+            })
+            .collect();
+
+        images.add(Image::new(
+            Extent3d {
+                width: SIZE,
+                height: SIZE,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::R8Unorm,
+            RenderAssetUsages::default(),
+        ))
+    }
+}
+
+/// Extension trait for [`EntityCommands`] to control the adaptation of an [`AutoExposure`]
+/// camera.
+pub trait AutoExposureCommandsExt {
+    /// Snaps the exposure of the camera to the metered value of the next frame, instead of
+    /// adapting to it over time. Use this when the scene changes abruptly, for example when the
+    /// player teleports or respawns.
+    ///
+    /// Unlike [`AutoExposure::initial_ev`], which starts from a fixed exposure, this snaps to
+    /// whatever the current scene meters. Nothing happens while auto exposure is disabled.
+    fn reset_auto_exposure(&mut self) -> &mut Self;
+
+    /// Sets the adapted exposure of the camera to `ev`, from where it keeps adapting in the
+    /// next frame. Use this to restore an exposure that was saved with
+    /// [`AutoExposureState::save_state`], so loading a save doesn't adapt from scratch.
+    ///
+    /// Unlike [`AutoExposure::initial_ev`], this also applies to cameras that already adapted.
+    /// The exposure of a group is restored on its first member.
+    fn restore_auto_exposure(&mut self, ev: f32) -> &mut Self;
+}
+
+impl AutoExposureCommandsExt for EntityCommands<'_> {
+    fn reset_auto_exposure(&mut self) -> &mut Self {
+        self.insert(ResetAutoExposure)
+    }
+
+    fn restore_auto_exposure(&mut self, ev: f32) -> &mut Self {
+        self.insert(RestoreAutoExposure(ev))
+    }
+}
+
+/// Saves and restores the adapted exposure of [`AutoExposure`] cameras, for example to keep it
+/// in a save game.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_mod_auto_exposure::AutoExposureState;
+/// fn swap_exposures(mut state: AutoExposureState, cameras: Query<Entity, With<Camera>>) {
+///     let [first, second] = [cameras.iter().next().unwrap(), cameras.iter().last().unwrap()];
+///     if let (Some(a), Some(b)) = (state.save_state(first), state.save_state(second)) {
+///         state.load_state(first, b);
+///         state.load_state(second, a);
+///     }
+/// }
+/// # bevy::ecs::system::assert_is_system(swap_exposures);
+/// ```
+#[derive(SystemParam)]
+pub struct AutoExposureState<'w, 's> {
+    commands: Commands<'w, 's>,
+    cameras: Query<'w, 's, &'static ExposureSettled>,
+}
+
+impl AutoExposureState<'_, '_> {
+    /// Returns the adapted exposure of the camera, in EV, or `None` before it was first read
+    /// back.
+    ///
+    /// The exposure is read back from the GPU by the [`ExposureSettled`] of the camera, which
+    /// it needs. The readback doesn't stall the GPU, so the saved exposure lags a few frames
+    /// behind the rendered one. That is usually well within the adaptation, but save after the
+    /// exposure settled to save exactly what is shown.
+    pub fn save_state(&self, entity: Entity) -> Option<f32> {
+        self.cameras.get(entity).ok()?.exposure()
+    }
+
+    /// Sets the adapted exposure of the camera to `ev`, see
+    /// [`AutoExposureCommandsExt::restore_auto_exposure`].
+    pub fn load_state(&mut self, entity: Entity, ev: f32) {
+        self.commands.entity(entity).restore_auto_exposure(ev);
+    }
+}
+
+/// Marks a camera whose exposure snaps to the metered value this frame.
+#[derive(Component, Clone)]
+struct ResetAutoExposure;
+
+impl ExtractComponent for ResetAutoExposure {
+    type QueryData = ();
+    type QueryFilter = (With<Camera>, With<AutoExposure>, With<Self>);
+    type Out = Self;
+
+    fn extract_component(_: QueryItem<'_, Self::QueryData>) -> Option<Self> {
+        Some(Self)
+    }
+}
+
+/// The exposure to restore on a camera this frame, in EV.
+#[derive(Component, Clone)]
+struct RestoreAutoExposure(f32);
+
+impl ExtractComponent for RestoreAutoExposure {
+    type QueryData = Read<Self>;
+    type QueryFilter = (With<Camera>, With<AutoExposure>);
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::QueryData>) -> Option<Self> {
+        Some(item.clone())
+    }
+}
+
 impl ExtractComponent for AutoExposure {
     type QueryData = Read<Self>;
     type QueryFilter = With<Camera>;
@@ -105,7 +1367,68 @@ impl ExtractComponent for AutoExposure {
     }
 }
 
-impl Plugin for AutoExposurePlugin {
+impl ExtractComponent for AutoExposureGroup {
+    type QueryData = Read<Self>;
+    type QueryFilter = (With<Camera>, With<AutoExposure>);
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::QueryData>) -> Option<Self> {
+        Some(*item)
+    }
+}
+
+impl ExtractComponent for MeteringFocus {
+    type QueryData = Read<Self>;
+    type QueryFilter = (With<Camera>, With<AutoExposure>);
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::QueryData>) -> Option<Self> {
+        Some(*item)
+    }
+}
+
+impl ExtractComponent for ExposureBias {
+    type QueryData = Read<Self>;
+    type QueryFilter = (With<Camera>, With<AutoExposure>);
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::QueryData>) -> Option<Self> {
+        Some(*item)
+    }
+}
+
+impl ExtractComponent for ExposureOverride {
+    type QueryData = Read<Self>;
+    type QueryFilter = (With<Camera>, With<AutoExposure>);
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::QueryData>) -> Option<Self> {
+        Some(*item)
+    }
+}
+
+impl MeteringFocus {
+    /// Projects the focus onto a view, returning `None` when it is off screen.
+    fn project(&self, projection: Mat4, transform: &GlobalTransform) -> Option<MeteringMode> {
+        let view_proj = projection * transform.compute_matrix().inverse();
+        let clip = view_proj * self.world_pos.extend(1.0);
+        if clip.w <= 0.0 {
+            return None;
+        }
+
+        let ndc = clip.xyz() / clip.w;
+        if ndc.x.abs() > 1.0 || ndc.y.abs() > 1.0 {
+            return None;
+        }
+
+        Some(MeteringMode::Spot {
+            center: vec2(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5),
+            radius: self.radius * projection.y_axis.y / clip.w * 0.5,
+        })
+    }
+}
+
+impl<const BINS: usize> Plugin for BinnedAutoExposurePlugin<BINS> {
     fn build(&self, app: &mut App) {
         load_internal_asset!(
             app,
@@ -113,72 +1436,459 @@ impl Plugin for AutoExposurePlugin {
             "auto_exposure.wgsl",
             Shader::from_wgsl
         );
+        load_internal_asset!(app, BINS_SHADER_HANDLE, "bins.wgsl", Shader::from_wgsl);
+        load_internal_asset!(
+            app,
+            DOWNSAMPLE_SHADER_HANDLE,
+            "downsample.wgsl",
+            Shader::from_wgsl
+        );
+        #[cfg(feature = "fragment_fallback")]
+        load_internal_asset!(
+            app,
+            fallback::FALLBACK_SHADER_HANDLE,
+            "fallback.wgsl",
+            Shader::from_wgsl
+        );
 
-        app.register_type::<AutoExposure>();
-        app.add_plugins(ExtractComponentPlugin::<AutoExposure>::default());
+        app.init_asset::<CompensationCurve>()
+            .register_type::<AutoExposure>()
+            .register_type::<MeteringFocus>()
+            .register_type::<ExposureBias>()
+            .register_type::<ExposureOverride>()
+            .register_type::<AutoExposureGroup>()
+            .register_type::<BinDistribution>()
+            .register_type::<ToneWeights>()
+            .register_type::<EnvironmentExposure>()
+            .register_type::<PercentileMode>()
+            .register_type::<LuminanceModel>()
+            .register_type::<MaskCombine>()
+            .register_type::<CompensationMode>()
+            .register_type::<MeteringMode>()
+            .register_type::<MeteringSource>()
+            .register_type::<ExposureOutput>()
+            .register_type::<CompensationCurve>()
+            // The generic field types, so that editors like `bevy-inspector-egui` can edit them.
+            .register_type::<Vec<Vec2>>()
+            .register_type::<Vec<Rect>>()
+            .register_type::<Option<f32>>()
+            .register_type::<Option<UVec2>>()
+            .register_type::<Option<Handle<Image>>>()
+            .register_type::<Option<(Handle<Image>, f32)>>()
+            .register_type::<Option<Handle<CompensationCurve>>>()
+            .register_type::<Option<Vec<Vec2>>>();
+        #[cfg(feature = "serde")]
+        app.init_asset_loader::<CompensationCurveLoader>();
+        app.add_plugins((
+            ExtractComponentPlugin::<AutoExposure>::default(),
+            ExtractComponentPlugin::<MeteringFocus>::default(),
+            ExtractComponentPlugin::<ExposureBias>::default(),
+            ExtractComponentPlugin::<ExposureOverride>::default(),
+            ExtractComponentPlugin::<AutoExposureGroup>::default(),
+            ExtractComponentPlugin::<AutoExposureGpu>::default(),
+            ExtractComponentPlugin::<ResetAutoExposure>::default(),
+            ExtractComponentPlugin::<RestoreAutoExposure>::default(),
+            HistogramPlugin,
+            ReadbackPlugin,
+            SensorPlugin,
+        ));
+        app.add_systems(First, clear_auto_exposure_resets)
+            .add_systems(PostUpdate, warn_black_metering_masks);
+        #[cfg(feature = "debug")]
+        app.add_plugins(debug::DebugPlugin);
 
         let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            warn!(
+                "Auto exposure is disabled: {}",
+                AutoExposureError::MissingRenderApp
+            );
             return;
         };
 
         render_app
+            .insert_resource(HistogramBins::new::<BINS>())
             .init_resource::<SpecializedComputePipelines<AutoExposurePipeline>>()
             .init_resource::<AutoExposureBuffers>()
-            .add_systems(ExtractSchedule, extract_auto_exposure_buffers)
+            .init_resource::<AutoExposureGroups>()
+            .init_resource::<MeteringPaused>()
+            .init_resource::<Time<Real>>()
+            .add_systems(
+                ExtractSchedule,
+                (
+                    extract_auto_exposure_buffers.run_if(metering_supported),
+                    extract_real_time,
+                ),
+            )
             .add_systems(
                 Render,
                 (
-                    prepare_auto_exposure_buffers.in_set(RenderSet::Prepare),
-                    queue_view_auto_exposure_pipelines.in_set(RenderSet::Queue),
+                    prepare_auto_exposure_buffers
+                        .in_set(RenderSet::Prepare)
+                        .run_if(metering_supported),
+                    restore_auto_exposure_states
+                        .in_set(RenderSet::Prepare)
+                        .after(prepare_auto_exposure_buffers),
+                    queue_view_auto_exposure_pipelines
+                        .in_set(RenderSet::Queue)
+                        .run_if(metering_supported),
+                    prepare_auto_exposure_textures
+                        .in_set(RenderSet::PrepareResources)
+                        .run_if(resource_exists::<AutoExposurePipeline>),
+                    prepare_mask_frames
+                        .in_set(RenderSet::PrepareResources)
+                        .run_if(metering_supported),
                 ),
             )
-            .add_render_graph_node::<AutoExposureNode>(Core3d, node::AutoExposure)
-            .add_render_graph_edges(
-                Core3d,
-                (Node3d::EndMainPass, node::AutoExposure, Node3d::Tonemapping),
+            .add_render_graph_node::<AutoExposureNode>(Core3d, AutoExposureLabel);
+
+        if self.pause_when_unfocused {
+            render_app.add_systems(ExtractSchedule, extract_metering_paused);
+        }
+
+        #[cfg(feature = "fragment_fallback")]
+        render_app
+            .init_resource::<bevy::render::render_resource::SpecializedRenderPipelines<
+                fallback::FallbackPipeline,
+            >>()
+            .add_systems(
+                Render,
+                fallback::prepare_fallback_textures
+                    .in_set(RenderSet::PrepareResources)
+                    .run_if(resource_exists::<fallback::FallbackPipeline>),
             );
+
+        if !self.graph_edges {
+            return;
+        }
+
+        let has_bloom = render_app
+            .world
+            .resource::<RenderGraph>()
+            .get_sub_graph(Core3d)
+            .is_some_and(|graph| graph.get_node_state(Node3d::Bloom).is_ok());
+
+        match (self.metering_tap, has_bloom) {
+            (MeteringTap::PreBloom, true) => render_app.add_render_graph_edges(
+                Core3d,
+                (
+                    Node3d::EndMainPass,
+                    AutoExposureLabel,
+                    Node3d::Bloom,
+                    Node3d::Tonemapping,
+                ),
+            ),
+            (MeteringTap::PostBloom, true) => render_app.add_render_graph_edges(
+                Core3d,
+                (
+                    Node3d::EndMainPass,
+                    Node3d::Bloom,
+                    AutoExposureLabel,
+                    Node3d::Tonemapping,
+                ),
+            ),
+            (_, false) => render_app.add_render_graph_edges(
+                Core3d,
+                (Node3d::EndMainPass, AutoExposureLabel, Node3d::Tonemapping),
+            ),
+        };
     }
 
     fn finish(&self, app: &mut App) {
+        // The view shader only goes missing without rendering, when nothing is written anyway.
+        let view_uniform_layout = app
+            .world
+            .get_resource::<Assets<Shader>>()
+            .and_then(|shaders| shaders.get(&VIEW_TYPE_HANDLE))
+            .and_then(|shader| match &shader.source {
+                Source::Wgsl(source) => Some(node::validate_view_uniform_layout(source)),
+                _ => None,
+            });
+
         let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
         };
 
-        render_app.init_resource::<AutoExposurePipeline>();
-        render_app.init_resource::<AutoExposureResources>();
+        if let Some(Err(reason)) = view_uniform_layout {
+            error!(
+                "Auto exposure can't write the exposure into the view uniforms: {}. \
+                Use `ExposureOutput::CameraExposure` instead.",
+                AutoExposureError::InvalidViewUniformLayout(reason)
+            );
+            render_app.insert_resource(InvalidViewUniformLayout);
+        }
+
+        match missing_compute_capability(&render_app.world) {
+            None => {
+                let device = render_app.world.resource::<RenderDevice>();
+                let size = self.histogram_workgroup_size;
+                let size = if histogram_workgroup_size_supported(device, size) {
+                    size
+                } else {
+                    warn!(
+                        "The device doesn't support histogram workgroups of {}x{}, using {}x{} \
+                        instead",
+                        size.x,
+                        size.y,
+                        DEFAULT_HISTOGRAM_WORKGROUP_SIZE.x,
+                        DEFAULT_HISTOGRAM_WORKGROUP_SIZE.y,
+                    );
+                    DEFAULT_HISTOGRAM_WORKGROUP_SIZE
+                };
+                render_app.insert_resource(HistogramWorkgroupSize(size));
+                render_app.init_resource::<AutoExposurePipeline>();
+            }
+            #[cfg(feature = "fragment_fallback")]
+            Some(missing) => {
+                info!(
+                    "Auto exposure falls back to fragment shaders: {}",
+                    AutoExposureError::UnsupportedDevice(missing)
+                );
+                render_app.init_resource::<fallback::FallbackPipeline>();
+            }
+            // The render systems don't run without pipelines, so the app keeps running with a
+            // fixed exposure.
+            #[cfg(not(feature = "fragment_fallback"))]
+            Some(missing) => error!(
+                "Auto exposure is disabled: {}. \
+                Enable the `fragment_fallback` feature to meter without compute shaders.",
+                AutoExposureError::UnsupportedDevice(missing)
+            ),
+        }
     }
 }
 
-impl FromWorld for AutoExposureResources {
-    fn from_world(world: &mut World) -> Self {
+impl AutoExposureResources {
+    fn new(device: &RenderDevice, bins: &HistogramBins) -> Self {
         Self {
-            histogram: world
-                .resource::<RenderDevice>()
-                .create_buffer(&BufferDescriptor {
-                    label: Some("histogram buffer"),
-                    size: 256 * 4,
-                    usage: BufferUsages::STORAGE,
-                    mapped_at_creation: false,
-                }),
+            histogram: device.create_buffer(&BufferDescriptor {
+                label: Some("histogram buffer"),
+                size: bins.buffer_size.get(),
+                // Light sensors copy their reading in.
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
         }
     }
 }
 
+/// Pauses metering while none of the windows has focus. Apps without windows are never paused.
+fn extract_metering_paused(mut paused: ResMut<MeteringPaused>, windows: Extract<Query<&Window>>) {
+    paused.0 = !windows.is_empty() && !windows.iter().any(|window| window.focused);
+}
+
+/// The render world only gets the virtual time, which `use_real_time` ignores.
+fn extract_real_time(mut real_time: ResMut<Time<Real>>, time: Extract<Res<Time<Real>>>) {
+    *real_time = **time;
+}
+
+type ResetFilter = Or<(With<ResetAutoExposure>, With<RestoreAutoExposure>)>;
+
+/// Resets and restores only apply to the frame after they were requested.
+fn clear_auto_exposure_resets(
+    mut commands: Commands,
+    resets: Query<Entity, ResetFilter>,
+    mut started: Local<bool>,
+) {
+    // The startup schedules run right before the first frame, which has to extract their
+    // requests.
+    if !std::mem::replace(&mut *started, true) {
+        return;
+    }
+
+    for entity in resets.iter() {
+        commands
+            .entity(entity)
+            .remove::<(ResetAutoExposure, RestoreAutoExposure)>();
+    }
+}
+
+/// Writes restored exposures into the state buffers, once they exist.
+fn restore_auto_exposure_states(
+    queue: Res<RenderQueue>,
+    buffers: Res<AutoExposureBuffers>,
+    views: Query<(Entity, &AutoExposure, &RestoreAutoExposure)>,
+) {
+    for (entity, auto_exposure, restore) in views.iter() {
+        let Some(buffer) = buffers.buffers.get(&entity) else {
+            continue;
+        };
+        let environment = restore.0 * auto_exposure.environment_exposure.scale
+            + auto_exposure.environment_exposure.correction;
+        queue.write_buffer(&buffer.exposure, 0, &restore.0.to_le_bytes());
+        queue.write_buffer(&buffer.exposure, 8, &environment.to_le_bytes());
+        // The restored exposure starts a new eased transition.
+        queue.write_buffer(
+            &buffer.exposure,
+            STATE_TRANSITION_PROGRESS_OFFSET,
+            &1f32.to_le_bytes(),
+        );
+    }
+}
+
+/// Warns once about every metering mask that is black everywhere. Such a mask meters nothing, so
+/// the exposure never adapts.
+fn warn_black_metering_masks(
+    mut events: EventReader<AssetEvent<Image>>,
+    images: Res<Assets<Image>>,
+    cameras: Query<(Entity, &AutoExposure)>,
+    mut checked: Local<HashSet<AssetId<Image>>>,
+) {
+    for event in events.read() {
+        if let AssetEvent::Modified { id } = event {
+            checked.remove(id);
+        }
+    }
+
+    for (entity, auto_exposure) in cameras.iter() {
+        // The bias meters black parts of the masks too.
+        if auto_exposure.mask_bias > 0.0 {
+            continue;
+        }
+
+        // Only a multiplied layer keeps a black mask black.
+        let masks = std::iter::once(&auto_exposure.metering_mask)
+            .chain(
+                auto_exposure
+                    .metering_mask_blend
+                    .as_ref()
+                    .map(|(mask, _)| mask),
+            )
+            .chain(
+                auto_exposure
+                    .metering_mask_layer
+                    .as_ref()
+                    .filter(|(_, combine)| *combine == MaskCombine::Multiply)
+                    .map(|(mask, _)| mask),
+            );
+
+        for mask in masks {
+            let Some(image) = images.get(mask) else {
+                continue;
+            };
+
+            if checked.insert(mask.id()) && is_black(image) {
+                warn!(
+                    "The metering mask {} of AutoExposure on {:?} is black everywhere, \
+                    so the exposure won't adapt while it's used",
+                    mask.path()
+                        .map_or(mask.id().to_string(), ToString::to_string),
+                    entity,
+                );
+            }
+        }
+    }
+}
+
+/// Whether the red channel of an image, which is what a metering mask is sampled from, is zero
+/// everywhere. Only formats with 8 bit channels are checked.
+fn is_black(image: &Image) -> bool {
+    let (red, stride) = match image.texture_descriptor.format {
+        TextureFormat::R8Unorm => (0, 1),
+        TextureFormat::Rg8Unorm => (0, 2),
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => (0, 4),
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => (2, 4),
+        _ => return false,
+    };
+
+    image.data.iter().skip(red).step_by(stride).all(|&r| r == 0)
+}
+
+/// Whether the render device supports the compute shaders and storage buffers that metering
+/// uses.
+#[cfg(feature = "debug")]
+pub(crate) fn compute_supported(render_world: &World) -> bool {
+    missing_compute_capability(render_world).is_none()
+}
+
+/// Describes the first capability that the compute path needs and the render device lacks, if
+/// any.
+fn missing_compute_capability(render_world: &World) -> Option<String> {
+    let limits = render_world.resource::<RenderDevice>().limits();
+    let bins = render_world.resource::<HistogramBins>();
+    // The average pass keeps a prefix, a sum, a count and a weight for every invocation.
+    let workgroup_storage = (bins.bins * 4).max(bins.average_workgroup_size * 16);
+
+    if limits.max_compute_workgroups_per_dimension == 0 {
+        Some("compute shaders".to_string())
+    } else if limits.max_storage_buffers_per_shader_stage < 3 {
+        Some("three storage buffers per shader stage".to_string())
+    } else if limits.max_compute_invocations_per_workgroup < 256 {
+        Some("256 invocations per compute workgroup".to_string())
+    } else if limits.max_compute_workgroup_storage_size < workgroup_storage {
+        Some(format!("{workgroup_storage} bytes of workgroup storage"))
+    } else {
+        None
+    }
+}
+
+/// Whether the device can run histogram workgroups of `size`.
+fn histogram_workgroup_size_supported(device: &RenderDevice, size: UVec2) -> bool {
+    let limits = device.limits();
+    size.cmpge(UVec2::ONE).all()
+        && size.x <= limits.max_compute_workgroup_size_x
+        && size.y <= limits.max_compute_workgroup_size_y
+        && size.x * size.y <= limits.max_compute_invocations_per_workgroup
+}
+
+/// Whether metering pipelines were set up in [`BinnedAutoExposurePlugin::finish`], which they aren't
+/// on devices that support neither the compute path nor the enabled fallback.
+fn metering_supported(
+    pipeline: Option<Res<AutoExposurePipeline>>,
+    #[cfg(feature = "fragment_fallback")] fallback_pipeline: Option<
+        Res<fallback::FallbackPipeline>,
+    >,
+) -> bool {
+    #[cfg(feature = "fragment_fallback")]
+    if fallback_pipeline.is_some() {
+        return true;
+    }
+    pipeline.is_some()
+}
+
 fn extract_auto_exposure_buffers(
     mut commands: Commands,
-    changed: Extract<Query<(Entity, &AutoExposure), Changed<AutoExposure>>>,
+    auto_exposures: Extract<Query<(Entity, Ref<AutoExposure>)>>,
     mut removed: Extract<RemovedComponents<AutoExposure>>,
+    curves: Extract<Res<Assets<CompensationCurve>>>,
+    mut curve_events: Extract<EventReader<AssetEvent<CompensationCurve>>>,
 ) {
+    let changed_curves: HashSet<_> = curve_events
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::Added { id } | AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect();
+
     commands.insert_resource(ExtractedAutoExposureBuffers {
-        changed: changed
+        changed: auto_exposures
             .iter()
+            .filter(|(_, auto_exposure)| {
+                auto_exposure.is_changed()
+                    || auto_exposure
+                        .compensation_curve_asset
+                        .as_ref()
+                        .is_some_and(|curve| changed_curves.contains(&curve.id()))
+            })
             .map(|(entity, auto_exposure)| {
+                let compensation_curve = auto_exposure
+                    .compensation_curve_asset
+                    .as_ref()
+                    .and_then(|curve| curves.get(curve))
+                    .map_or(&auto_exposure.compensation_curve, |curve| &curve.points);
+
+                let (min, max) = auto_exposure.metering_range();
                 (
                     entity,
                     ExtractedAutoExposureBuffer {
-                        min: auto_exposure.min,
-                        max: auto_exposure.max,
-                        compensation_curve: auto_exposure.compensation_curve.clone(),
+                        initial_ev: auto_exposure.initial_ev,
+                        min,
+                        max,
+                        bin_gamma: auto_exposure.bin_distribution.gamma(),
+                        compensation_curve: compensation_curve.clone(),
+                        compensation_mode: auto_exposure.compensation_mode,
+                        radial_curve: auto_exposure.metering_mode.radial_curve(),
+                        easing_curve: auto_exposure.easing_curve.clone(),
                     },
                 )
             })
@@ -187,123 +1897,1015 @@ fn extract_auto_exposure_buffers(
     });
 }
 
+#[allow(clippy::too_many_arguments)]
 fn prepare_auto_exposure_buffers(
+    mut commands: Commands,
     device: Res<RenderDevice>,
     queue: Res<RenderQueue>,
+    bins: Res<HistogramBins>,
+    pipeline: Option<Res<AutoExposurePipeline>>,
+    resources: Option<Res<AutoExposureResources>>,
     mut extracted: ResMut<ExtractedAutoExposureBuffers>,
     mut buffers: ResMut<AutoExposureBuffers>,
 ) {
-    for (entity, buffer) in extracted.changed.drain(..) {
-        let exposure = device.create_buffer(&BufferDescriptor {
-            label: Some("auto exposure state buffer"),
-            size: 4,
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
-            mapped_at_creation: false,
-        });
+    let extracted = &mut *extracted;
 
-        let compensation_curve_desc = TextureDescriptor {
-            label: Some("auto exposure compensation curve"),
-            size: Extent3d {
-                width: 256,
-                height: 1,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: TextureDimension::D1,
-            format: TextureFormat::R8Unorm,
-            usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
-            view_formats: &[TextureFormat::R8Unorm],
-        };
+    // The histogram that every view is metered into is only created once there is a camera to
+    // meter, so apps without one, like asset processing tools, allocate nothing.
+    if pipeline.is_some() && resources.is_none() && !extracted.changed.is_empty() {
+        commands.insert_resource(AutoExposureResources::new(&device, &bins));
+    }
 
-        let mut data = [0u8; 256];
-        let y_to_val = |y: f32| ((y + 8.0) / 16.0 * 255.0).clamp(0.0, 255.0) as u8;
-        for i in 1..256 {
-            let ev = buffer.min + ((i - 1) as f32 / 254.0) * (buffer.max - buffer.min);
+    // A component that is removed and added back, within the same frame or in the next one,
+    // keeps its state, so the adaptation doesn't restart.
+    let buffers = &mut *buffers;
+    let removed = std::mem::take(&mut extracted.removed);
+    let readded = |entity: &Entity| {
+        extracted
+            .changed
+            .iter()
+            .any(|(changed, _)| changed == entity)
+    };
+    for entity in std::mem::take(&mut buffers.removed) {
+        if !readded(&entity) {
+            buffers.buffers.remove(&entity);
+        }
+    }
+    buffers.removed = removed
+        .into_iter()
+        .filter(|entity| !readded(entity))
+        .collect();
 
-            if let Some(j) = buffer
-                .compensation_curve
-                .iter()
-                .enumerate()
-                .find_map(|(i, v)| if v.x >= ev { Some(i) } else { None })
-            {
-                if j == 0 {
-                    data[i] = y_to_val(buffer.compensation_curve[0].y);
-                    continue;
+    for (entity, buffer) in extracted.changed.drain(..) {
+        // Only the compensation curve depends on the settings, so the state buffers of an
+        // existing entity are reused to preserve the current exposure.
+        let history_bins = (buffer.min, buffer.max, buffer.bin_gamma);
+        let (exposure, histogram_history, skip_frames, skipped_time) = match buffers
+            .buffers
+            .get(&entity)
+        {
+            Some(existing) => {
+                // The history holds the luminances of other bins after the range changed, so it
+                // starts over. An empty history only scales the blended histogram down.
+                if existing.history_bins != history_bins {
+                    queue.write_buffer(
+                        &existing.histogram_history,
+                        0,
+                        &vec![0; bins.buffer_size.get() as usize],
+                    );
                 }
-
-                let v0 = buffer.compensation_curve[j - 1];
-                let v1 = buffer.compensation_curve[j];
-                if v0.x == v1.x {
-                    data[i] = y_to_val(v0.y);
-                    continue;
+                // An eased transition may head for a target that the new range can't reach, so
+                // the next one starts from the current exposure instead of overshooting.
+                if existing.history_bins.0 != buffer.min || existing.history_bins.1 != buffer.max {
+                    queue.write_buffer(
+                        &existing.exposure,
+                        STATE_TRANSITION_PROGRESS_OFFSET,
+                        &1f32.to_le_bytes(),
+                    );
                 }
-
-                let t = (ev - v0.x) / (v1.x - v0.x);
-                let y = v0.y + t * (v1.y - v0.y);
-                data[i] = y_to_val(y);
-                continue;
+                (
+                    existing.exposure.clone(),
+                    existing.histogram_history.clone(),
+                    existing.skip_frames,
+                    existing.skipped_time,
+                )
+            }
+            None => {
+                // The environment exposure starts out like the exposure, until it is metered.
+                // No frame was metered yet, the dynamic range is empty until then, which reads
+                // back as zero nits, and no eased transition is in progress.
+                let initial_ev = buffer.initial_ev.unwrap_or(0.0);
+                let exposure = device.create_buffer_with_data(&BufferInitDescriptor {
+                    label: Some("auto exposure state buffer"),
+                    contents: &[
+                        initial_ev,
+                        0.0,
+                        initial_ev,
+                        0.0,
+                        f32::NEG_INFINITY,
+                        f32::NEG_INFINITY,
+                        initial_ev,
+                        initial_ev,
+                        1.0,
+                        0.0,
+                        0.0,
+                        0.0,
+                    ]
+                    .map(f32::to_le_bytes)
+                    .concat(),
+                    usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+                });
+                // Starts out empty, like the histogram of a frame that metered nothing.
+                let histogram_history = device.create_buffer(&BufferDescriptor {
+                    label: Some("auto exposure histogram history buffer"),
+                    size: bins.buffer_size.get(),
+                    usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                // The first frame is always metered.
+                (exposure, histogram_history, 0, 0.0)
             }
+        };
 
-            data[i] = y_to_val(buffer.compensation_curve[buffer.compensation_curve.len() - 1].y);
+        let mut data = [0f32; 256];
+        for i in 1..256 {
+            let ev = buffer.min + ((i - 1) as f32 / 254.0) * (buffer.max - buffer.min);
+            let y = sample_curve(&buffer.compensation_curve, ev);
+            data[i] = match buffer.compensation_mode {
+                CompensationMode::Additive => y.clamp(-8.0, 8.0),
+                CompensationMode::Override => y,
+            };
         }
         data[0] = data[1];
+        let compensation_curve = create_curve_texture(
+            &device,
+            &queue,
+            "auto exposure compensation curve",
+            TextureFormat::R32Float,
+            &data.map(f32::to_le_bytes).concat(),
+        );
 
-        let compensation_curve = device
-            .create_texture_with_data(&queue, &compensation_curve_desc, default(), &data)
-            .create_view(&TextureViewDescriptor {
-                label: Some("auto exposure compensation curve view"),
-                ..default()
-            });
+        let radial_weights = create_radial_weights(&device, &queue, buffer.radial_curve.as_deref());
+        // Without an easing curve, the progress is eased linearly.
+        let data: [f32; 256] = std::array::from_fn(|i| {
+            let progress = i as f32 / 255.0;
+            buffer
+                .easing_curve
+                .as_ref()
+                .map_or(progress, |curve| sample_curve(curve, progress))
+        });
+        let easing_curve = create_curve_texture(
+            &device,
+            &queue,
+            "auto exposure easing curve",
+            TextureFormat::R32Float,
+            &data.map(f32::to_le_bytes).concat(),
+        );
+        // The metering mode is compared against the last frame, so a change of the settings
+        // can start a fade.
+        let (metering_mode, mode_fade, from_weights) = match buffers.buffers.get(&entity) {
+            Some(existing) => (
+                existing.metering_mode.clone(),
+                existing.mode_fade.clone(),
+                existing.from_weights.clone(),
+            ),
+            None => (
+                None,
+                None,
+                device.create_texture(&TextureDescriptor {
+                    label: Some("auto exposure metering mode fade weights"),
+                    size: Extent3d {
+                        width: FADE_WEIGHTS_SIZE,
+                        height: FADE_WEIGHTS_SIZE,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: TextureDimension::D2,
+                    format: TextureFormat::R8Unorm,
+                    usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                }),
+            ),
+        };
+        let from_weights_view = from_weights.create_view(&TextureViewDescriptor::default());
 
         buffers.buffers.insert(
             entity,
             AutoExposureBuffer {
                 exposure,
+                histogram_history,
+                history_bins,
+                skip_frames,
+                skipped_time,
                 compensation_curve,
+                radial_weights,
+                easing_curve,
+                metering_mode,
+                mode_fade,
+                from_weights,
+                from_weights_view,
             },
         );
     }
+}
+
+/// Packs the first [`MAX_EXCLUSION_RECTS`] exclusion rects as (min x, min y, max x, max y).
+fn exclusion_rects(rects: &[Rect]) -> [Vec4; MAX_EXCLUSION_RECTS] {
+    let mut packed = [Vec4::ZERO; MAX_EXCLUSION_RECTS];
+    for (packed, rect) in packed.iter_mut().zip(rects) {
+        *packed = rect.min.extend(rect.max.x).extend(rect.max.y);
+    }
+    packed
+}
 
-    for entity in extracted.removed.drain(..) {
-        buffers.buffers.remove(&entity);
+/// Samples a curve at `x`, interpolating linearly between its points. Outside of the curve,
+/// the first or last point is used.
+pub(crate) fn sample_curve(curve: &[Vec2], x: f32) -> f32 {
+    let Some(j) = curve.iter().position(|v| v.x >= x) else {
+        return curve.last().map_or(0.0, |v| v.y);
+    };
+    if j == 0 {
+        return curve[0].y;
     }
+
+    let v0 = curve[j - 1];
+    let v1 = curve[j];
+    if v0.x == v1.x {
+        return v0.y;
+    }
+
+    let t = (x - v0.x) / (v1.x - v0.x);
+    v0.y + t * (v1.y - v0.y)
+}
+
+/// Bakes the weights of a radial metering curve into a texture. Without a curve, every distance
+/// is weighed fully.
+fn create_radial_weights(
+    device: &RenderDevice,
+    queue: &RenderQueue,
+    curve: Option<&[Vec2]>,
+) -> TextureView {
+    create_curve_texture(
+        device,
+        queue,
+        "auto exposure radial weights",
+        TextureFormat::R8Unorm,
+        &radial_weights(curve),
+    )
+}
+
+/// The weights of a radial metering curve at 256 distances from the center of the view, from
+/// the center to the corners.
+fn radial_weights(curve: Option<&[Vec2]>) -> [u8; 256] {
+    let mut data = [255u8; 256];
+    if let Some(curve) = curve {
+        for (i, weight) in data.iter_mut().enumerate() {
+            let distance = i as f32 / 255.0;
+            *weight = (sample_curve(curve, distance) * 255.0).clamp(0.0, 255.0) as u8;
+        }
+    }
+    data
+}
+
+/// The width and height of the weights that a metering mode fades from, see
+/// [`AutoExposure::metering_mode_fade`].
+const FADE_WEIGHTS_SIZE: u32 = 64;
+
+/// Weighs the view by a metering mode like the metering shaders do, for a view with the aspect
+/// ratio `aspect`. Returns the weights at the centers of `FADE_WEIGHTS_SIZE` squared texels, row
+/// by row from the top left.
+fn metering_mode_weights(metering_mode: &MeteringMode, aspect: f32) -> Vec<f32> {
+    let (spot_center, spot_radius, spot_rect, _) = metering_mode_params(metering_mode);
+    let radial = metering_mode
+        .radial_curve()
+        .map(|curve| radial_weights(Some(&curve)));
+    let scale = Vec2::new(aspect, 1.0);
+    let size = FADE_WEIGHTS_SIZE;
+
+    (0..size * size)
+        .map(|i| {
+            let uv = (Vec2::new((i % size) as f32, (i / size) as f32) + 0.5) / size as f32;
+            let in_spot =
+                spot_radius <= 0.0 || ((uv - spot_center) * scale).length() <= spot_radius;
+            let in_rect = uv.cmpge(spot_rect.xy()).all() && uv.cmple(spot_rect.zw()).all();
+            if !(in_spot && in_rect) {
+                return 0.0;
+            }
+
+            // The distance from the center is normalized so that the corners are at one.
+            radial.map_or(1.0, |radial| {
+                let distance = ((uv - 0.5) * scale).length() / (0.5 * scale.length());
+                f32::from(radial[(distance.clamp(0.0, 1.0) * 255.0) as usize]) / 255.0
+            })
+        })
+        .collect()
+}
+
+/// Returns the spot center and radius, the spot rect as (min x, min y, max x, max y), and
+/// whether the radial weights apply, of a metering mode.
+fn metering_mode_params(metering_mode: &MeteringMode) -> (Vec2, f32, Vec4, bool) {
+    let (spot_center, spot_radius) = match metering_mode {
+        MeteringMode::Spot { center, radius } => (*center, *radius),
+        _ => (Vec2::ZERO, 0.0),
+    };
+    // An empty rect would meter nothing, so it meters the whole view instead.
+    let spot_rect = match metering_mode {
+        MeteringMode::SpotRect { rect } => rect.intersect(Rect::new(0.0, 0.0, 1.0, 1.0)),
+        _ => Rect::new(0.0, 0.0, 1.0, 1.0),
+    };
+    let spot_rect = if spot_rect.width() > 0.0 && spot_rect.height() > 0.0 {
+        spot_rect
+            .min
+            .extend(spot_rect.max.x)
+            .extend(spot_rect.max.y)
+    } else {
+        Vec4::new(0.0, 0.0, 1.0, 1.0)
+    };
+    let radial = matches!(
+        metering_mode,
+        MeteringMode::CenterWeighted { .. } | MeteringMode::Radial { .. }
+    );
+    (spot_center, spot_radius, spot_rect, radial)
+}
+
+/// Creates a 1D texture that a curve was baked into, as 256 values of `format`.
+fn create_curve_texture(
+    device: &RenderDevice,
+    queue: &RenderQueue,
+    label: &'static str,
+    format: TextureFormat,
+    data: &[u8],
+) -> TextureView {
+    let desc = TextureDescriptor {
+        label: Some(label),
+        size: Extent3d {
+            width: 256,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D1,
+        format,
+        usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+        view_formats: &[format],
+    };
+
+    device
+        .create_texture_with_data(queue, &desc, default(), data)
+        .create_view(&TextureViewDescriptor {
+            label: Some(label),
+            ..default()
+        })
 }
 
+type ViewAutoExposureQuery = (
+    Entity,
+    Read<AutoExposure>,
+    Read<ExtractedView>,
+    Read<ExtractedCamera>,
+    Option<Read<MeteringFocus>>,
+    Option<Read<ExposureBias>>,
+    Option<Read<ExposureOverride>>,
+    Option<Read<AutoExposureGroup>>,
+    Option<Read<ViewSensedLuminance>>,
+    Option<Read<ViewCameraExposure>>,
+    Has<ResetAutoExposure>,
+    Has<CaptureReady>,
+    Has<DepthPrepass>,
+);
+
+#[allow(clippy::too_many_arguments)]
 fn queue_view_auto_exposure_pipelines(
     mut commands: Commands,
-    mut pipeline_cache: ResMut<PipelineCache>,
+    pipeline_cache: Res<PipelineCache>,
     mut compute_pipelines: ResMut<SpecializedComputePipelines<AutoExposurePipeline>>,
-    pipeline: Res<AutoExposurePipeline>,
-    time: Res<Time>,
-    buffers: Res<AutoExposureBuffers>,
-    view_targets: Query<(Entity, &AutoExposure)>,
+    pipeline: Option<Res<AutoExposurePipeline>>,
+    #[cfg(feature = "fragment_fallback")]
+    mut fallback_pipelines: fallback::FallbackPipelineSpecializer,
+    (time, real_time): (Res<Time>, Res<Time<Real>>),
+    mut buffers: ResMut<AutoExposureBuffers>,
+    (mut groups, sorted_cameras, bins): (
+        ResMut<AutoExposureGroups>,
+        Res<SortedCameras>,
+        Res<HistogramBins>,
+    ),
+    (device, queue): (Res<RenderDevice>, Res<RenderQueue>),
+    (msaa, images): (Res<Msaa>, Res<RenderAssets<Image>>),
+    view_targets: Query<ViewAutoExposureQuery>,
+    mut warned: Local<HashSet<Entity>>,
+    mut warned_depth: Local<HashSet<Entity>>,
+    mut warned_mip: Local<HashSet<Entity>>,
+    mut warned_ldr: Local<HashSet<Entity>>,
+    (mut warned_lut, mut warned_range): (Local<HashSet<Entity>>, Local<HashSet<Entity>>),
 ) {
-    for (entity, auto_exposure) in view_targets.iter() {
-        let histogram_pipeline =
-            compute_pipelines.specialize(&mut pipeline_cache, &pipeline, Pass::Histogram);
-        let average_pipeline =
-            compute_pipelines.specialize(&mut pipeline_cache, &pipeline, Pass::Average);
+    // Forget the warnings of removed components, so they warn again when added back.
+    for warned in [
+        &mut *warned,
+        &mut *warned_depth,
+        &mut *warned_mip,
+        &mut *warned_ldr,
+        &mut *warned_lut,
+        &mut *warned_range,
+    ] {
+        warned.retain(|entity| buffers.buffers.contains_key(entity));
+    }
 
-        let Some(buffer) = buffers.buffers.get(&entity) else {
+    // The first member of every group in render order averages the histogram of the group, and
+    // its state is shared by the other members.
+    let mut first_members = HashMap::new();
+    for camera in &sorted_cameras.0 {
+        let Ok((entity, auto_exposure, .., Some(group), _, _, _, _, _)) =
+            view_targets.get(camera.entity)
+        else {
+            continue;
+        };
+        if auto_exposure.enabled
+            && !matches!(
+                auto_exposure.metering_source,
+                MeteringSource::LightSensor(_)
+            )
+            && buffers.buffers.contains_key(&entity)
+        {
+            first_members.entry(*group).or_insert(entity);
+        }
+    }
+    groups
+        .histograms
+        .retain(|group, _| first_members.contains_key(group));
+
+    for (
+        entity,
+        auto_exposure,
+        view,
+        camera,
+        focus,
+        bias,
+        exposure_override,
+        group,
+        sensed,
+        camera_exposure,
+        reset,
+        capture,
+        depth_prepass,
+    ) in view_targets.iter()
+    {
+        if !auto_exposure.enabled {
             continue;
+        }
+
+        // Depth is only read when metering the view, from the depth prepass.
+        let reads_depth = (auto_exposure.exclude_depth.is_some()
+            || auto_exposure.focus_distance.is_some())
+            && auto_exposure.metering_source == MeteringSource::View;
+        if reads_depth && !depth_prepass && warned_depth.insert(entity) {
+            warn!(
+                "AutoExposure on {:?} has exclude_depth or focus_distance set, but the camera \
+                has no DepthPrepass; metering every pixel evenly instead",
+                entity,
+            );
+        }
+        let depth = (reads_depth && depth_prepass).then(|| depth_params(auto_exposure, view));
+
+        // The main textures of views are created without mips.
+        let mip_level_count = match &auto_exposure.metering_source {
+            MeteringSource::View | MeteringSource::LightSensor(_) => 1,
+            MeteringSource::Image(image) => {
+                images.get(image).map_or(1, |image| image.mip_level_count)
+            }
         };
+        let metering_mip = if auto_exposure.metering_mip < mip_level_count {
+            auto_exposure.metering_mip
+        } else {
+            if warned_mip.insert(entity) {
+                warn!(
+                    "AutoExposure on {:?} meters mip {}, but its source only has {} mip levels; \
+                    metering mip 0 instead",
+                    entity, auto_exposure.metering_mip, mip_level_count,
+                );
+            }
+            0
+        };
+
+        let (min, max) = auto_exposure.metering_range();
+        if (min, max) != (auto_exposure.min, auto_exposure.max) && warned_range.insert(entity) {
+            warn!(
+                "AutoExposure on {:?} has a min of {} and a max of {}, which leaves no range to \
+                meter; metering up to {} instead",
+                entity, auto_exposure.min, auto_exposure.max, max,
+            );
+        }
+
+        // Without HDR, the main texture holds the tonemapped image, which is clipped at one.
+        let inverse_tonemap = !view.hdr && auto_exposure.metering_source == MeteringSource::View;
+        if inverse_tonemap && warned_ldr.insert(entity) {
+            warn!(
+                "AutoExposure on {:?} meters a camera without HDR; the metered luminance is \
+                approximated by inverse tonemapping, which is less accurate",
+                entity,
+            );
+        }
+
+        // The LUT is sampled as a filterable 3D texture, which other images can't be bound as.
+        let metering_lut = auto_exposure.metering_lut.as_ref().filter(|lut| {
+            let Some(image) = images.get(*lut) else {
+                return false;
+            };
+            let usable = image.texture.dimension() == TextureDimension::D3
+                && image.texture_format.sample_type(None, None)
+                    == Some(TextureSampleType::Float { filterable: true });
+            if !usable && warned_lut.insert(entity) {
+                warn!(
+                    "AutoExposure on {:?} has a metering_lut that isn't a 3D image with a \
+                    filterable format; metering without it",
+                    entity,
+                );
+            }
+            usable
+        });
+
+        let pipelines = match &pipeline {
+            Some(pipeline) => MeteringPipelines::Compute(ComputePipelines {
+                histogram: compute_pipelines.specialize(
+                    &pipeline_cache,
+                    pipeline,
+                    Pass::Histogram {
+                        depth_samples: depth.map(|_| msaa.samples()),
+                        inverse_tonemap,
+                        lut: metering_lut.is_some(),
+                    },
+                ),
+                average: compute_pipelines.specialize(&pipeline_cache, pipeline, Pass::Average),
+                downsample: auto_exposure.metering_resolution.map(|_| {
+                    compute_pipelines.specialize(&pipeline_cache, pipeline, Pass::Downsample)
+                }),
+            }),
+            #[cfg(feature = "fragment_fallback")]
+            None => match fallback_pipelines.specialize(&pipeline_cache) {
+                Some(pipelines) => MeteringPipelines::Fragment(pipelines),
+                None => continue,
+            },
+            #[cfg(not(feature = "fragment_fallback"))]
+            None => continue,
+        };
+
+        // The members of a group are metered into the histogram of the group, with the state of
+        // its first member. Without compute shaders, every member is metered on its own, and so
+        // are light sensors, which have no pixels to add to the histogram.
+        let group = group
+            .filter(|_| matches!(pipelines, MeteringPipelines::Compute(_)))
+            .filter(|_| {
+                !matches!(
+                    auto_exposure.metering_source,
+                    MeteringSource::LightSensor(_)
+                )
+            })
+            .and_then(|group| {
+                let first = *first_members.get(group)?;
+                let shared = buffers.buffers.get(&first)?;
+                let histogram = groups.histograms.entry(*group).or_insert_with(|| {
+                    device.create_buffer(&BufferDescriptor {
+                        label: Some("auto exposure group histogram buffer"),
+                        size: bins.buffer_size.get(),
+                        usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+                        mapped_at_creation: false,
+                    })
+                });
+                let group = ViewGroup {
+                    histogram: histogram.clone(),
+                    first: first == entity,
+                };
+                Some((
+                    group,
+                    shared.exposure.clone(),
+                    shared.histogram_history.clone(),
+                ))
+            });
+
+        let Some(buffer) = buffers.buffers.get_mut(&entity) else {
+            continue;
+        };
+        let (group, state, histogram_history) = match group {
+            Some((group, state, histogram_history)) => (Some(group), state, histogram_history),
+            None => (
+                None,
+                buffer.exposure.clone(),
+                buffer.histogram_history.clone(),
+            ),
+        };
+
+        let (low_percent, high_percent) =
+            clamp_percentiles(auto_exposure.low_percent, auto_exposure.high_percent);
+        if (low_percent, high_percent) != (auto_exposure.low_percent, auto_exposure.high_percent)
+            && warned.insert(entity)
+        {
+            warn!(
+                "AutoExposure on {:?} has invalid percentiles low_percent: {}, high_percent: {}; \
+                using {} and {} instead",
+                entity,
+                auto_exposure.low_percent,
+                auto_exposure.high_percent,
+                low_percent,
+                high_percent,
+            );
+        }
+
+        let metering_mode = match focus {
+            Some(focus) => focus
+                .project(view.projection, &view.transform)
+                .unwrap_or(MeteringMode::Average),
+            None => auto_exposure.metering_mode.clone(),
+        };
+        let (spot_center, spot_radius, spot_rect, radial) = metering_mode_params(&metering_mode);
+
+        let delta_time = match auto_exposure.fixed_delta_time {
+            Some(delta_time) => delta_time.max(0.0),
+            None if auto_exposure.use_real_time => real_time.delta_seconds(),
+            None => time.delta_seconds(),
+        };
+
+        // Only a change of the kind of mode fades. A spot that moves, by its settings or by a
+        // focus, is followed right away.
+        let mode_changed = buffer
+            .metering_mode
+            .as_ref()
+            .is_some_and(|(configured, metered)| {
+                std::mem::discriminant(configured)
+                    != std::mem::discriminant(&auto_exposure.metering_mode)
+                    || std::mem::discriminant(metered) != std::mem::discriminant(&metering_mode)
+            });
+        let fade = auto_exposure.metering_mode_fade;
+        if mode_changed && fade > 0.0 {
+            let aspect = match &auto_exposure.metering_source {
+                MeteringSource::View => view.viewport.z as f32 / view.viewport.w.max(1) as f32,
+                MeteringSource::Image(image) => images
+                    .get(image)
+                    .map_or(1.0, |image| image.size.x / image.size.y.max(1.0)),
+                MeteringSource::LightSensor(_) => 1.0,
+            };
+            let (_, from) = buffer.metering_mode.take().unwrap();
+            let mut from_weights = metering_mode_weights(&from, aspect);
+
+            // A change during a fade fades from the weights that were blended so far.
+            if let Some(old) = &buffer.mode_fade {
+                let blend = (old.elapsed / fade).min(1.0);
+                for (weight, old_weight) in from_weights.iter_mut().zip(&old.from_weights) {
+                    *weight = old_weight.lerp(*weight, blend);
+                }
+            }
+
+            let data = from_weights
+                .iter()
+                .map(|weight| (weight * 255.0).round() as u8)
+                .collect::<Vec<_>>();
+            queue.write_texture(
+                ImageCopyTexture {
+                    texture: &buffer.from_weights,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                },
+                &data,
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(FADE_WEIGHTS_SIZE),
+                    rows_per_image: None,
+                },
+                Extent3d {
+                    width: FADE_WEIGHTS_SIZE,
+                    height: FADE_WEIGHTS_SIZE,
+                    depth_or_array_layers: 1,
+                },
+            );
+            buffer.mode_fade = Some(MeteringModeFade {
+                from_weights,
+                elapsed: 0.0,
+            });
+        } else if let Some(mode_fade) = &mut buffer.mode_fade {
+            mode_fade.elapsed += delta_time;
+        }
+        if buffer
+            .mode_fade
+            .as_ref()
+            .is_some_and(|mode_fade| mode_fade.elapsed >= fade.max(0.0))
+        {
+            buffer.mode_fade = None;
+        }
+        buffer.metering_mode = Some((auto_exposure.metering_mode.clone(), metering_mode));
+        let mode_blend = buffer
+            .mode_fade
+            .as_ref()
+            .map_or(1.0, |mode_fade| mode_fade.elapsed / fade);
+
+        // Skipped frames hold the exposure, and their time is adapted for in the next metered
+        // frame, so the speed of adaptation doesn't depend on the interval.
+        let delta_time = delta_time + buffer.skipped_time;
+        let meter = buffer.skip_frames == 0 || reset || capture;
+        if meter {
+            buffer.skip_frames = auto_exposure.metering_interval.max(1) - 1;
+            buffer.skipped_time = 0.0;
+        } else {
+            buffer.skip_frames -= 1;
+            buffer.skipped_time = delta_time;
+        }
+
+        // The time spent waiting for the masks isn't adapted for once they have loaded.
+        let loading_mask = auto_exposure.wait_for_mask
+            && std::iter::once(&auto_exposure.metering_mask)
+                .chain(
+                    auto_exposure
+                        .metering_mask_blend
+                        .as_ref()
+                        .map(|(mask, _)| mask),
+                )
+                .chain(
+                    auto_exposure
+                        .metering_mask_layer
+                        .as_ref()
+                        .map(|(mask, _)| mask),
+                )
+                .any(|mask| *mask != Handle::default() && images.get(mask).is_none());
+        let meter = meter && !loading_mask;
 
         commands.entity(entity).insert(ViewAutoExposurePipeline {
-            histogram_pipeline,
-            mean_luminance_pipeline: average_pipeline,
-            state: buffer.exposure.clone(),
+            pipelines,
+            meter,
+            state: state.clone(),
+            histogram_history,
+            group,
             compensation_curve: buffer.compensation_curve.clone(),
+            radial_weights: buffer.radial_weights.clone(),
+            from_weights: buffer.from_weights_view.clone(),
+            easing_curve: buffer.easing_curve.clone(),
             params: AutoExposureParams {
-                min_log_lum: auto_exposure.min,
-                inv_log_lum_range: 1.0 / (auto_exposure.max - auto_exposure.min),
-                log_lum_range: auto_exposure.max - auto_exposure.min,
-                low_percent: auto_exposure.low_percent,
-                high_percent: auto_exposure.high_percent,
-                speed_up: auto_exposure.speed_up * time.delta_seconds(),
-                speed_down: auto_exposure.speed_down * time.delta_seconds(),
+                min_log_lum: min,
+                inv_log_lum_range: 1.0 / (max - min),
+                log_lum_range: max - min,
+                low_percent,
+                high_percent,
+                speed_up: auto_exposure.speed_up,
+                speed_down: auto_exposure.speed_down,
+                delta_time,
+                spot_center,
+                spot_radius,
+                spot_rect,
+                mask_blend: auto_exposure
+                    .metering_mask_blend
+                    .as_ref()
+                    .map_or(0.0, |(_, blend)| blend.clamp(0.0, 1.0)),
+                saturation_cutoff: auto_exposure.saturation_cutoff.unwrap_or(f32::MAX),
+                weight_by_alpha: auto_exposure.weight_by_alpha as u32,
+                reset: (reset || capture) as u32,
+                deadband: auto_exposure.deadband_ev.max(0.0),
+                max_step: auto_exposure
+                    .max_ev_per_second
+                    .map_or(f32::MAX, |max| max.max(0.0) * delta_time),
+                dark_floor: auto_exposure.dark_floor_ev,
+                max_gain: auto_exposure.max_gain_ev,
+                bin_gamma: auto_exposure.bin_distribution.gamma(),
+                interpolate_percentiles: (auto_exposure.percentile_mode
+                    == PercentileMode::Interpolated)
+                    as u32,
+                compensation_override: (auto_exposure.compensation_mode
+                    == CompensationMode::Override) as u32,
+                correction: auto_exposure.correction
+                    + bias.map_or(0.0, |bias| bias.0)
+                    + auto_exposure.target_nits.map_or(0.0, |nits| {
+                        (nits / auto_exposure.paper_white_nits)
+                            .max(f32::MIN_POSITIVE)
+                            .log2()
+                    }),
+                environment_scale: auto_exposure.environment_exposure.scale,
+                environment_correction: auto_exposure.environment_exposure.correction,
+                temporal_blend: auto_exposure.temporal_blend.clamp(0.0, 0.99),
+                key_strength: if auto_exposure.adaptive_key {
+                    auto_exposure.key_strength.max(0.0)
+                } else {
+                    0.0
+                },
+                mask_region: auto_exposure
+                    .mask_region
+                    .min
+                    .extend(auto_exposure.mask_region.width())
+                    .extend(auto_exposure.mask_region.height()),
+                mask_gain: auto_exposure.mask_gain,
+                mask_bias: auto_exposure.mask_bias,
+                radial: radial as u32,
+                sample_stride: auto_exposure.sample_stride.max(1),
+                // Only the view is rendered with the exposure of the camera.
+                camera_exposure: camera_exposure
+                    .filter(|_| auto_exposure.metering_source == MeteringSource::View)
+                    .map_or(0.0, |exposure| exposure.0),
+                viewport: UVec4::ZERO,
+                exclusion_count: auto_exposure.exclusion_rects.len().min(MAX_EXCLUSION_RECTS)
+                    as u32,
+                exclusion_rects: exclusion_rects(&auto_exposure.exclusion_rects),
+                mode_blend,
+                easing: auto_exposure.easing_curve.is_some() as u32,
+                perceptual_luminance: (auto_exposure.luminance_model == LuminanceModel::Perceptual)
+                    as u32,
+                mask_combine: match auto_exposure.metering_mask_layer {
+                    None => 0,
+                    Some((_, MaskCombine::Multiply)) => 1,
+                    Some((_, MaskCombine::Add)) => 2,
+                    Some((_, MaskCombine::Max)) => 3,
+                },
+                absolute_target: auto_exposure.mid_gray_luminance.is_some() as u32,
+                target_log_lum: auto_exposure.mid_gray_luminance.map_or(0.0, |nits| {
+                    // The camera's own exposure, without the adapted exposure that it applies.
+                    nits.max(f32::MIN_POSITIVE).log2() + camera.exposure.log2()
+                        - camera_exposure.map_or(0.0, |exposure| exposure.0)
+                }),
+                tone_split: auto_exposure.tone_weights.split,
+                shadow_weight: auto_exposure.tone_weights.shadows.max(0.0),
+                highlight_weight: auto_exposure.tone_weights.highlights.max(0.0),
             },
             metering_mask: auto_exposure.metering_mask.clone(),
+            metering_mask_blend: auto_exposure
+                .metering_mask_blend
+                .as_ref()
+                .map(|(mask, _)| mask.clone()),
+            metering_mask_layer: auto_exposure
+                .metering_mask_layer
+                .as_ref()
+                .map(|(mask, _)| mask.clone()),
+            metering_source: auto_exposure.metering_source.clone(),
+            sensed_bin: sensed.map(|sensed| sensor::sensed_bin(sensed.0, auto_exposure, &bins)),
+            output: auto_exposure.output,
+            depth,
+            metering_mip,
+            metering_lut: metering_lut.cloned(),
+            exposure_override: exposure_override.map(|exposure| exposure.0),
         });
+        commands
+            .entity(entity)
+            .insert(ViewExposureBuffer { buffer: state });
+    }
+}
+
+fn prepare_auto_exposure_textures(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    mut texture_cache: ResMut<TextureCache>,
+    images: Res<RenderAssets<Image>>,
+    views: Query<(
+        Entity,
+        &ExtractedView,
+        &AutoExposure,
+        &ViewAutoExposurePipeline,
+    )>,
+) {
+    for (entity, view, auto_exposure, pipeline) in views.iter() {
+        // Only views that run the downsample pass need the texture, which excludes disabled
+        // cameras and the fragment fallback, which ignores `metering_resolution`.
+        let MeteringPipelines::Compute(ComputePipelines {
+            downsample: Some(_),
+            ..
+        }) = &pipeline.pipelines
+        else {
+            continue;
+        };
+        let Some(resolution) = auto_exposure.metering_resolution else {
+            continue;
+        };
+
+        let source_size = match &auto_exposure.metering_source {
+            MeteringSource::View => view.viewport.zw(),
+            MeteringSource::Image(image) => match images.get(image) {
+                Some(image) => image.size.as_uvec2(),
+                None => continue,
+            },
+            MeteringSource::LightSensor(_) => continue,
+        };
+
+        let size = resolution.min(source_size).max(UVec2::ONE);
+        let downsampled = texture_cache.get(
+            &device,
+            TextureDescriptor {
+                label: Some("auto exposure downsampled view"),
+                size: Extent3d {
+                    width: size.x,
+                    height: size.y,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: DOWNSAMPLE_FORMAT,
+                usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+        );
+
+        commands
+            .entity(entity)
+            .insert(ViewAutoExposureTexture { downsampled });
+    }
+}
+
+/// Allocates the textures that the current frame of flip book metering masks is copied to.
+fn prepare_mask_frames(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    mut texture_cache: ResMut<TextureCache>,
+    images: Res<RenderAssets<Image>>,
+    views: Query<(Entity, &AutoExposure)>,
+    mut warned: Local<HashSet<AssetId<Image>>>,
+) {
+    for (entity, auto_exposure) in views.iter() {
+        let mut mask_frame = |mask: &Handle<Image>| {
+            let image = images.get(mask)?;
+            let layers = image.texture.depth_or_array_layers();
+            if layers == 1 {
+                return None;
+            }
+            if !image.texture.usage().contains(TextureUsages::COPY_SRC) {
+                if warned.insert(mask.id()) {
+                    warn!(
+                        "The metering mask {} of AutoExposure on {:?} is a texture array \
+                        without COPY_SRC usage, so its frames can't be metered",
+                        mask.path()
+                            .map_or(mask.id().to_string(), ToString::to_string),
+                        entity,
+                    );
+                }
+                return None;
+            }
+
+            let texture = texture_cache.get(
+                &device,
+                TextureDescriptor {
+                    label: Some("auto exposure metering mask frame"),
+                    size: Extent3d {
+                        width: image.texture.width(),
+                        height: image.texture.height(),
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: TextureDimension::D2,
+                    format: image.texture_format,
+                    usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                    view_formats: &[],
+                },
+            );
+            Some(MaskFrame {
+                layer: auto_exposure.mask_frame.min(layers - 1),
+                texture,
+            })
+        };
+
+        let frames = ViewMaskFrames {
+            mask: mask_frame(&auto_exposure.metering_mask),
+            mask_blend: auto_exposure
+                .metering_mask_blend
+                .as_ref()
+                .and_then(|(mask, _)| mask_frame(mask)),
+            mask_layer: auto_exposure
+                .metering_mask_layer
+                .as_ref()
+                .and_then(|(mask, _)| mask_frame(mask)),
+        };
+        if frames.mask.is_some() || frames.mask_blend.is_some() || frames.mask_layer.is_some() {
+            commands.entity(entity).insert(frames);
+        }
+    }
+}
+
+/// The [`DepthParams`] of a view that reads depth, without its viewport, which the node fills in.
+fn depth_params(auto_exposure: &AutoExposure, view: &ExtractedView) -> DepthParams {
+    // Reverse the distance to the depth that the prepass writes for it. Without a distance, the
+    // threshold is below any depth, so nothing is excluded.
+    let exclude_depth = auto_exposure.exclude_depth.map_or(-1.0, |distance| {
+        let clip = view.projection * Vec4::new(0.0, 0.0, -distance, 1.0);
+        clip.z / clip.w
+    });
+
+    // The distance along the view direction is the negated view z that the inverse projection
+    // maps a depth back to, as a ratio of the z and w rows.
+    let inverse = view.projection.inverse();
+    DepthParams {
+        viewport: UVec4::ZERO,
+        exclude_depth,
+        focus_distance: auto_exposure.focus_distance.unwrap_or(0.0),
+        focus_falloff: match auto_exposure.focus_distance {
+            Some(_) => auto_exposure.focus_falloff.max(f32::EPSILON),
+            None => 0.0,
+        },
+        depth_to_distance: Vec4::new(
+            -inverse.z_axis.z,
+            -inverse.w_axis.z,
+            inverse.z_axis.w,
+            inverse.w_axis.w,
+        ),
+    }
+}
+
+/// Coerces the metering percentiles into `0..=100`, with `low < high`.
+fn clamp_percentiles(low_percent: u32, high_percent: u32) -> (u32, u32) {
+    let high_percent = high_percent.clamp(1, 100);
+    let low_percent = low_percent.min(high_percent - 1);
+    (low_percent, high_percent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clamps_percentiles() {
+        // Valid percentiles are kept.
+        assert_eq!(clamp_percentiles(60, 95), (60, 95));
+        assert_eq!(clamp_percentiles(0, 100), (0, 100));
+        // A low percentile at or above the high one is moved just below it.
+        assert_eq!(clamp_percentiles(95, 60), (59, 60));
+        assert_eq!(clamp_percentiles(50, 50), (49, 50));
+        // Values over 100 are capped.
+        assert_eq!(clamp_percentiles(60, 150), (60, 100));
+        assert_eq!(clamp_percentiles(200, 300), (99, 100));
+        // A high percentile of zero leaves room for the low one.
+        assert_eq!(clamp_percentiles(0, 0), (0, 1));
+        assert_eq!(clamp_percentiles(10, 0), (0, 1));
     }
 }
@@ -0,0 +1,82 @@
+use bevy::{
+    ecs::{query::QueryItem, system::lifetimeless::Read},
+    prelude::*,
+    render::{
+        extract_component::ExtractComponent,
+        render_resource::{Buffer, BufferDescriptor, BufferUsages},
+        renderer::RenderDevice,
+    },
+};
+
+use crate::AutoExposure;
+
+/// Component that mirrors the adapted exposure of an [`AutoExposure`] camera into a buffer that
+/// custom materials can bind, for example materials that do their own tonemapping.
+///
+/// The buffer holds the exposure and the environment exposure (see
+/// [`EnvironmentExposure`](crate::EnvironmentExposure)) as `f32`s, in EV, and is updated every
+/// frame after the [`AutoExposureLabel`](crate::AutoExposureLabel) node, regardless of the
+/// [`ExposureOutput`](crate::ExposureOutput). Without this component, no copy is made.
+///
+/// Bind it as a read only storage buffer in a material:
+///
+/// ```
+/// # use bevy::{prelude::*, render::render_resource::{AsBindGroup, Buffer}};
+/// #[derive(Asset, TypePath, AsBindGroup, Clone)]
+/// struct ExposedMaterial {
+///     // A clone of `AutoExposureGpu::buffer`.
+///     #[storage(0, read_only, buffer)]
+///     exposure: Buffer,
+/// }
+/// ```
+///
+/// ```wgsl
+/// struct Exposure {
+///     exposure: f32,
+///     environment: f32,
+/// }
+///
+/// @group(2) @binding(0) var<storage, read> exposure: Exposure;
+///
+/// fn expose(color: vec3<f32>) -> vec3<f32> {
+///     return color * exp2(exposure.exposure);
+/// }
+///
+/// // For a skybox that is drawn with the camera's exposure already applied.
+/// fn expose_environment(color: vec3<f32>) -> vec3<f32> {
+///     return color * exp2(exposure.environment - exposure.exposure);
+/// }
+/// ```
+#[derive(Component, Clone)]
+pub struct AutoExposureGpu {
+    buffer: Buffer,
+}
+
+impl AutoExposureGpu {
+    /// Creates the buffer of the exposures, which start at zero until the camera is metered.
+    pub fn new(device: &RenderDevice) -> Self {
+        Self {
+            buffer: device.create_buffer(&BufferDescriptor {
+                label: Some("auto exposure material buffer"),
+                size: 8,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+        }
+    }
+
+    /// The buffer that holds the exposures, to clone into materials.
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+}
+
+impl ExtractComponent for AutoExposureGpu {
+    type QueryData = Read<Self>;
+    type QueryFilter = (With<Camera>, With<AutoExposure>);
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::QueryData>) -> Option<Self> {
+        Some(item.clone())
+    }
+}
@@ -1,40 +1,153 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use bevy::{
+    asset::Handle,
+    core_pipeline::prepass::ViewPrepassTextures,
     ecs::{
+        entity::Entity,
         query::QueryState,
-        system::lifetimeless::Read,
+        system::{lifetimeless::Read, Resource},
         world::{FromWorld, World},
     },
+    log::warn,
+    math::UVec4,
     render::{
         render_asset::RenderAssets,
         render_graph::*,
         render_resource::*,
         renderer::RenderContext,
         texture::{FallbackImage, Image},
-        view::{ExtractedView, ViewTarget, ViewUniformOffset, ViewUniforms},
+        view::{
+            ColorGrading, ExtractedView, ViewTarget, ViewUniform, ViewUniformOffset, ViewUniforms,
+        },
     },
 };
 
+#[cfg(feature = "debug")]
+use crate::debug::{histogram_copies, DebugHistogramCopies};
 use crate::{
-    pipeline::{AutoExposurePipeline, ViewAutoExposurePipeline},
-    AutoExposureResources,
+    diagnostics::MeteringTimers,
+    histogram::ViewHistogramBuffer,
+    pipeline::{
+        AutoExposureParams, AutoExposurePipeline, ComputePipelines, DepthParams, MaskFrame,
+        MeteringPipelines, ViewAutoExposurePipeline, ViewAutoExposureTexture, ViewMaskFrames,
+        STATE_SIZE,
+    },
+    readback::{ViewCaptureReadbackBuffer, ViewStateReadbackBuffer},
+    AutoExposureError, AutoExposureGpu, AutoExposureResources, ExposureOutput, MeteringPaused,
+    MeteringSource,
 };
 
+/// Without the `debug` feature, the histogram isn't copied for debugging.
+#[cfg(not(feature = "debug"))]
+type DebugHistogramCopies = ();
+
+#[cfg(not(feature = "debug"))]
+fn histogram_copies<'w>(_: ()) -> std::iter::Empty<&'w Buffer> {
+    std::iter::empty()
+}
+
+/// Index of the `color_grading` field in [`ViewUniform`].
+const VIEW_COLOR_GRADING_FIELD: usize = 11;
+
+/// Byte offset of `color_grading.exposure` in [`ViewUniform`], which the exposure is copied to.
+/// It is derived from the shader layout of the struct, so it follows bevy's layout on every
+/// backend.
+const VIEW_EXPOSURE_OFFSET: u64 =
+    ViewUniform::METADATA.offset(VIEW_COLOR_GRADING_FIELD) + ColorGrading::METADATA.offset(0);
+
+// Check that `VIEW_COLOR_GRADING_FIELD` still points at a field of the size of `ColorGrading`,
+// so a change to `ViewUniform` fails to compile instead of writing into the wrong field.
+const _: () = assert!(
+    ViewUniform::METADATA.offset(VIEW_COLOR_GRADING_FIELD + 1)
+        - ViewUniform::METADATA.offset(VIEW_COLOR_GRADING_FIELD)
+        >= ColorGrading::METADATA.min_size().get()
+);
+
+/// Render world resource that is present when [`validate_view_uniform_layout`] failed, which
+/// stops the node from writing the exposure into the view uniforms.
+#[derive(Resource)]
+pub(crate) struct InvalidViewUniformLayout;
+
+/// Checks that `VIEW_EXPOSURE_OFFSET` points at `color_grading.exposure`, by comparing it to the
+/// `View` struct of bevy's view shader, whose source is `view_shader`. The compile time check
+/// above only catches fields that change size, not fields that are reordered.
+pub(crate) fn validate_view_uniform_layout(view_shader: &str) -> Result<(), String> {
+    let size = ViewUniform::min_size().get();
+    if VIEW_EXPOSURE_OFFSET + 4 > size {
+        return Err(format!(
+            "the exposure offset {VIEW_EXPOSURE_OFFSET} lies beyond the {size} bytes of `View`"
+        ));
+    }
+
+    let view_fields =
+        wgsl_struct_fields(view_shader, "View").ok_or("the view shader doesn't declare `View`")?;
+    match view_fields.get(VIEW_COLOR_GRADING_FIELD) {
+        Some(&"color_grading") => {}
+        field => {
+            return Err(format!(
+                "field {VIEW_COLOR_GRADING_FIELD} of `View` is {field:?} instead of `color_grading`"
+            ))
+        }
+    }
+
+    let grading_fields = wgsl_struct_fields(view_shader, "ColorGrading")
+        .ok_or("the view shader doesn't declare `ColorGrading`")?;
+    match grading_fields.first() {
+        Some(&"exposure") => Ok(()),
+        field => Err(format!(
+            "the first field of `ColorGrading` is {field:?} instead of `exposure`"
+        )),
+    }
+}
+
+/// Returns the field names of the WGSL struct `name` in `source`, in order.
+fn wgsl_struct_fields<'a>(source: &'a str, name: &str) -> Option<Vec<&'a str>> {
+    let start = source.find(&format!("struct {name} {{"))?;
+    let body = &source[start..];
+    let body = &body[body.find('{')? + 1..body.find('}')?];
+    let fields = body
+        .lines()
+        .filter_map(|line| line.split("//").next()?.split_once(':'))
+        .map(|(field, _)| field.trim())
+        .collect();
+    Some(fields)
+}
+
+/// Render graph label of the node that meters the views and writes their exposure.
+/// Nodes that read the [`ViewExposureBuffer`](crate::ViewExposureBuffer) of a view should run
+/// after it.
 #[derive(RenderLabel, Debug, Clone, Hash, PartialEq, Eq)]
-pub struct AutoExposure;
+pub struct AutoExposureLabel;
+
+/// The pixel count of the histogram bin of a light sensor reading, enough for the percentiles to
+/// cut it finely.
+const SENSED_COUNT: u32 = 1024;
 
+/// Render graph node that meters the views and writes their exposure, which the plugin adds to
+/// the `Core3d` graph as [`AutoExposureLabel`].
 pub struct AutoExposureNode {
     query: QueryState<(
         Read<ViewUniformOffset>,
         Read<ViewTarget>,
         Read<ViewAutoExposurePipeline>,
         Read<ExtractedView>,
+        Option<Read<ViewAutoExposureTexture>>,
+        DebugHistogramCopies,
+        Option<Read<ViewHistogramBuffer>>,
+        Option<Read<ViewStateReadbackBuffer>>,
+        Option<Read<ViewCaptureReadbackBuffer>>,
+        Option<Read<AutoExposureGpu>>,
     )>,
+    /// Whether the missing view uniforms were already warned about.
+    warned_view_uniforms: AtomicBool,
 }
 
 impl FromWorld for AutoExposureNode {
     fn from_world(world: &mut World) -> Self {
         Self {
             query: QueryState::new(world),
+            warned_view_uniforms: AtomicBool::new(false),
         }
     }
 }
@@ -51,109 +164,611 @@ impl Node for AutoExposureNode {
         world: &World,
     ) -> Result<(), NodeRunError> {
         let view_entity = graph.view_entity();
-        let pipeline_cache = world.resource::<PipelineCache>();
-        let pipeline = world.resource::<AutoExposurePipeline>();
-        let resources = world.resource::<AutoExposureResources>();
-
-        let (view_uniform_offset, view_target, auto_exposure, view) =
-            match self.query.get_manual(world, view_entity) {
-                Ok(result) => result,
-                Err(_) => return Ok(()),
-            };
 
-        let histogram_pipeline = pipeline_cache
-            .get_compute_pipeline(auto_exposure.histogram_pipeline)
-            .unwrap();
-        let average_pipeline = pipeline_cache
-            .get_compute_pipeline(auto_exposure.mean_luminance_pipeline)
-            .unwrap();
-
-        let source = view_target.main_texture_view();
-
-        let fallback = world.resource::<FallbackImage>();
-        let mask = world
-            .resource::<RenderAssets<Image>>()
-            .get(&auto_exposure.metering_mask);
-        let mask = mask
-            .map(|i| &i.texture_view)
-            .unwrap_or(&fallback.d2.texture_view);
-
-        let mut settings = encase::UniformBuffer::new(Vec::new());
-        settings.write(&auto_exposure.params).unwrap();
-        let settings =
+        let (
+            view_uniform_offset,
+            view_target,
+            auto_exposure,
+            view,
+            texture,
+            debug_histograms,
+            shared_histogram,
+            state_readback,
+            capture_readback,
+            gpu_exposure,
+        ) = match self.query.get_manual(world, view_entity) {
+            Ok(result) => result,
+            Err(_) => return Ok(()),
+        };
+
+        let histogram_copies: Vec<&Buffer> = histogram_copies(debug_histograms)
+            .chain(shared_histogram.map(|histogram| &histogram.buffer))
+            .collect();
+
+        let timers = world.get_resource::<MeteringTimers>();
+        let timer = timers.map(|timers| timers.start(render_context));
+
+        // While paused, or on frames between metered frames, the state buffer is left alone, so
+        // the exposure is held below.
+        match &auto_exposure.pipelines {
+            _ if !auto_exposure.meter || world.resource::<MeteringPaused>().0 => {}
+            MeteringPipelines::Compute(pipelines) => meter(
+                render_context,
+                world,
+                view_entity,
+                view_target,
+                auto_exposure,
+                pipelines,
+                view,
+                texture,
+                &histogram_copies,
+            ),
+            #[cfg(feature = "fragment_fallback")]
+            MeteringPipelines::Fragment(pipelines) => crate::fallback::meter(
+                render_context,
+                world,
+                view_entity,
+                view_target,
+                auto_exposure,
+                pipelines,
+                view,
+            ),
+        }
+
+        if let (Some(timers), Some(timer)) = (timers, timer) {
+            timers.stop(render_context, timer);
+        }
+
+        let state_copies = state_readback
+            .map(|readback| &readback.buffer)
+            .into_iter()
+            .chain(capture_readback.map(|readback| &readback.buffer));
+        for state_copy in state_copies {
+            render_context.command_encoder().copy_buffer_to_buffer(
+                &auto_exposure.state,
+                0,
+                state_copy,
+                0,
+                STATE_SIZE,
+            );
+        }
+
+        // The exposure to apply is the first value of either buffer.
+        let exposure_override = auto_exposure.exposure_override.map(|exposure| {
             render_context
                 .render_device()
                 .create_buffer_with_data(&BufferInitDescriptor {
-                    label: None,
-                    contents: settings.as_ref(),
-                    usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-                });
+                    label: Some("auto exposure override"),
+                    contents: &exposure.to_le_bytes(),
+                    usage: BufferUsages::COPY_SRC,
+                })
+        });
+        let exposure = exposure_override.as_ref().unwrap_or(&auto_exposure.state);
 
-        let compute_bind_group = render_context.render_device().create_bind_group(
-            None,
-            &pipeline.histogram_layout,
-            &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: settings.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::TextureView(source),
-                },
-                BindGroupEntry {
-                    binding: 2,
-                    resource: BindingResource::TextureView(mask),
-                },
-                BindGroupEntry {
-                    binding: 3,
-                    resource: BindingResource::TextureView(&auto_exposure.compensation_curve),
-                },
-                BindGroupEntry {
-                    binding: 4,
-                    resource: resources.histogram.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 5,
-                    resource: auto_exposure.state.as_entire_binding(),
-                },
-            ],
+        // Copy the exposure and the environment exposure for materials, also when metering was
+        // skipped.
+        if let Some(gpu_exposure) = gpu_exposure {
+            render_context.command_encoder().copy_buffer_to_buffer(
+                exposure,
+                0,
+                gpu_exposure.buffer(),
+                0,
+                4,
+            );
+            render_context.command_encoder().copy_buffer_to_buffer(
+                &auto_exposure.state,
+                8,
+                gpu_exposure.buffer(),
+                4,
+                4,
+            );
+        }
+
+        if auto_exposure.output != ExposureOutput::ViewUniform
+            || world.contains_resource::<InvalidViewUniformLayout>()
+        {
+            return Ok(());
+        }
+
+        // Copy the computed exposure value to the view uniforms.
+        // If this wasn't a plugin, we could just add the STORAGE access modifier to the view uniforms buffer
+        // and write directly to it. But since this is a plugin, we have to resort to this hack.
+        // This also happens when metering was skipped, so the last exposure is held.
+        // A missing buffer is logged rather than returned, see `AutoExposureError`.
+        let Some(view_uniforms_buffer) = world.resource::<ViewUniforms>().uniforms.buffer() else {
+            if !self.warned_view_uniforms.swap(true, Ordering::Relaxed) {
+                warn!(
+                    "Auto exposure can't write the exposure of {:?}: {}",
+                    view_entity,
+                    AutoExposureError::MissingViewUniforms,
+                );
+            }
+            return Ok(());
+        };
+        render_context.command_encoder().copy_buffer_to_buffer(
+            exposure,
+            0,
+            view_uniforms_buffer,
+            view_uniform_offset.offset as u64 + VIEW_EXPOSURE_OFFSET,
+            4,
         );
 
-        let mut compute_pass =
+        Ok(())
+    }
+}
+
+/// Returns the texture to meter, and the rect of it to meter as (x, y, width, height).
+/// The view may only cover part of its main texture, for example with split screen.
+/// When a mip is metered, the texture only holds that mip, and the rect is scaled to it.
+/// Returns `None` when the source image isn't available yet, or a light sensor has no reading.
+pub(crate) fn metering_source(
+    world: &World,
+    view_target: &ViewTarget,
+    auto_exposure: &ViewAutoExposurePipeline,
+    view: &ExtractedView,
+) -> Option<(TextureView, UVec4)> {
+    let (texture, texture_view, viewport) = match &auto_exposure.metering_source {
+        MeteringSource::View => (
+            view_target.main_texture(),
+            view_target.main_texture_view(),
+            view.viewport,
+        ),
+        MeteringSource::Image(image) => {
+            let image = world.resource::<RenderAssets<Image>>().get(image)?;
+            let size = image.size.as_uvec2();
+            (
+                &image.texture,
+                &image.texture_view,
+                UVec4::new(0, 0, size.x, size.y),
+            )
+        }
+        // A light sensor meters no texture, the fallback image only fills the binding.
+        MeteringSource::LightSensor(_) => {
+            auto_exposure.sensed_bin?;
+            let image = &world.resource::<FallbackImage>().d2;
+            (&image.texture, &image.texture_view, UVec4::new(0, 0, 1, 1))
+        }
+    };
+
+    let mip = auto_exposure.metering_mip;
+    if mip == 0 {
+        return Some((texture_view.clone(), viewport));
+    }
+
+    let texture_view = texture.create_view(&TextureViewDescriptor {
+        label: Some("auto exposure metered mip"),
+        base_mip_level: mip,
+        mip_level_count: Some(1),
+        ..Default::default()
+    });
+    let viewport = UVec4::new(
+        viewport.x >> mip,
+        viewport.y >> mip,
+        (viewport.z >> mip).max(1),
+        (viewport.w >> mip).max(1),
+    );
+    Some((texture_view, viewport))
+}
+
+/// Returns the views of the metering mask and the blend mask of a view. The blend mask defaults
+/// to the metering mask.
+pub(crate) fn mask_views<'w>(
+    render_context: &mut RenderContext,
+    world: &'w World,
+    view_entity: Entity,
+    auto_exposure: &ViewAutoExposurePipeline,
+) -> (&'w TextureView, &'w TextureView, &'w TextureView) {
+    let frames = world.get::<ViewMaskFrames>(view_entity);
+    let mask = mask_view(
+        render_context,
+        world,
+        &auto_exposure.metering_mask,
+        frames.and_then(|frames| frames.mask.as_ref()),
+    );
+    let mask_blend = match &auto_exposure.metering_mask_blend {
+        Some(mask_blend) => mask_view(
+            render_context,
+            world,
+            mask_blend,
+            frames.and_then(|frames| frames.mask_blend.as_ref()),
+        ),
+        None => mask,
+    };
+    // Without a layer, the shader doesn't combine it, and any texture fills the binding.
+    let mask_layer = match &auto_exposure.metering_mask_layer {
+        Some(mask_layer) => mask_view(
+            render_context,
+            world,
+            mask_layer,
+            frames.and_then(|frames| frames.mask_layer.as_ref()),
+        ),
+        None => &world.resource::<FallbackImage>().d2.texture_view,
+    };
+    (mask, mask_blend, mask_layer)
+}
+
+/// Returns the view of a metering mask, or of a white image while the mask isn't loaded, which
+/// weighs the whole view evenly. Of a flip book, the current frame is copied out first.
+fn mask_view<'w>(
+    render_context: &mut RenderContext,
+    world: &'w World,
+    mask: &Handle<Image>,
+    frame: Option<&'w MaskFrame>,
+) -> &'w TextureView {
+    let white = &world.resource::<FallbackImage>().d2.texture_view;
+    let Some(image) = world.resource::<RenderAssets<Image>>().get(mask) else {
+        return white;
+    };
+    if image.texture.depth_or_array_layers() == 1 {
+        return &image.texture_view;
+    }
+    // Flip books that can't be copied have no frame.
+    let Some(frame) = frame else {
+        return white;
+    };
+
+    render_context.command_encoder().copy_texture_to_texture(
+        ImageCopyTexture {
+            texture: &image.texture,
+            mip_level: 0,
+            origin: Origin3d {
+                x: 0,
+                y: 0,
+                z: frame.layer,
+            },
+            aspect: TextureAspect::All,
+        },
+        frame.texture.texture.as_image_copy(),
+        frame.texture.texture.size(),
+    );
+    &frame.texture.default_view
+}
+
+/// Records the metering passes, which update the exposure in the state buffer.
+/// Metering is skipped while the pipelines are compiling, or the source image isn't available yet.
+#[allow(clippy::too_many_arguments)]
+fn meter(
+    render_context: &mut RenderContext,
+    world: &World,
+    view_entity: Entity,
+    view_target: &ViewTarget,
+    auto_exposure: &ViewAutoExposurePipeline,
+    pipelines: &ComputePipelines,
+    view: &ExtractedView,
+    texture: Option<&ViewAutoExposureTexture>,
+    histogram_copies: &[&Buffer],
+) {
+    let pipeline_cache = world.resource::<PipelineCache>();
+    let pipeline = world.resource::<AutoExposurePipeline>();
+    let Some(resources) = world.get_resource::<AutoExposureResources>() else {
+        return;
+    };
+    // The members of a group are metered into its histogram, which its first member averages
+    // before it adds its own pixels, so every member applies the same exposure this frame.
+    let histogram = auto_exposure
+        .group
+        .as_ref()
+        .map_or(&resources.histogram, |group| &group.histogram);
+    let average_first = auto_exposure
+        .group
+        .as_ref()
+        .is_some_and(|group| group.first);
+    let average_last = auto_exposure.group.is_none();
+
+    // The pipelines take a few frames to compile, skip metering until they are ready.
+    let (Some(histogram_pipeline), Some(average_pipeline)) = (
+        pipeline_cache.get_compute_pipeline(pipelines.histogram),
+        pipeline_cache.get_compute_pipeline(pipelines.average),
+    ) else {
+        return;
+    };
+
+    let Some((source, source_viewport)) = metering_source(world, view_target, auto_exposure, view)
+    else {
+        return;
+    };
+
+    let downsample = pipelines
+        .downsample
+        .and_then(|id| pipeline_cache.get_compute_pipeline(id))
+        .zip(texture);
+
+    let (metered, viewport) = match downsample {
+        Some((_, texture)) => (
+            &texture.downsampled.default_view,
+            UVec4::new(
+                0,
+                0,
+                texture.downsampled.texture.width(),
+                texture.downsampled.texture.height(),
+            ),
+        ),
+        None => (&source, source_viewport),
+    };
+
+    let (mask, mask_blend, mask_layer) =
+        mask_views(render_context, world, view_entity, auto_exposure);
+
+    // The layout always has a LUT, which the shader only samples when the view has one.
+    let fallback_lut = &world.resource::<FallbackImage>().d3;
+    let lut = auto_exposure
+        .metering_lut
+        .as_ref()
+        .and_then(|lut| world.resource::<RenderAssets<Image>>().get(lut))
+        .unwrap_or(fallback_lut);
+
+    // The histogram dispatch is derived from these same params, so the metered rect and the
+    // invocations that cover it can't disagree in the frame that the viewport is resized.
+    let params = AutoExposureParams {
+        viewport,
+        ..auto_exposure.params
+    };
+    let mut settings = encase::UniformBuffer::new(Vec::new());
+    settings.write(&params).unwrap();
+    let settings = render_context
+        .render_device()
+        .create_buffer_with_data(&BufferInitDescriptor {
+            label: None,
+            contents: settings.as_ref(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+    let compute_bind_group = render_context.render_device().create_bind_group(
+        None,
+        &pipeline.histogram_layout,
+        &[
+            BindGroupEntry {
+                binding: 0,
+                resource: settings.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(metered),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::TextureView(mask),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: BindingResource::TextureView(&auto_exposure.compensation_curve),
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: histogram.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 5,
+                resource: auto_exposure.state.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 6,
+                resource: BindingResource::TextureView(mask_blend),
+            },
+            BindGroupEntry {
+                binding: 7,
+                resource: BindingResource::TextureView(&auto_exposure.radial_weights),
+            },
+            BindGroupEntry {
+                binding: 8,
+                resource: BindingResource::TextureView(&lut.texture_view),
+            },
+            BindGroupEntry {
+                binding: 9,
+                resource: BindingResource::Sampler(&lut.sampler),
+            },
+            BindGroupEntry {
+                binding: 10,
+                resource: auto_exposure.histogram_history.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 11,
+                resource: BindingResource::TextureView(&auto_exposure.from_weights),
+            },
+            BindGroupEntry {
+                binding: 12,
+                resource: BindingResource::TextureView(&auto_exposure.easing_curve),
+            },
+            BindGroupEntry {
+                binding: 13,
+                resource: BindingResource::TextureView(mask_layer),
+            },
+        ],
+    );
+
+    // A light sensor fills the histogram with its reading, as if every pixel had the same
+    // luminance, and only averages it.
+    if let Some(bin) = auto_exposure.sensed_bin {
+        let mut counts = vec![0; pipeline.histogram_bins.bins as usize];
+        counts[bin as usize] = SENSED_COUNT;
+        let sensed =
             render_context
-                .command_encoder()
-                .begin_compute_pass(&ComputePassDescriptor {
-                    label: Some("auto_exposure_pass"),
-                    timestamp_writes: None,
+                .render_device()
+                .create_buffer_with_data(&BufferInitDescriptor {
+                    label: Some("auto exposure sensed histogram"),
+                    contents: &counts
+                        .iter()
+                        .flat_map(|count: &u32| count.to_le_bytes())
+                        .collect::<Vec<_>>(),
+                    usage: BufferUsages::COPY_SRC,
                 });
+        render_context.command_encoder().copy_buffer_to_buffer(
+            &sensed,
+            0,
+            histogram,
+            0,
+            pipeline.histogram_bins.buffer_size.get(),
+        );
+        average(
+            render_context,
+            histogram,
+            histogram_copies,
+            pipeline,
+            average_pipeline,
+            &compute_bind_group,
+        );
+        return;
+    }
 
-        compute_pass.set_bind_group(0, &compute_bind_group, &[]);
-        compute_pass.set_pipeline(histogram_pipeline);
-        compute_pass.dispatch_workgroups(
-            (view.viewport.z + 15) / 16,
-            (view.viewport.w + 15) / 16,
-            1,
+    let downsample_bind_group = downsample.map(|(downsample_pipeline, texture)| {
+        let mut source_viewport_uniform = encase::UniformBuffer::new(Vec::new());
+        source_viewport_uniform.write(&source_viewport).unwrap();
+        let source_viewport_uniform =
+            render_context
+                .render_device()
+                .create_buffer_with_data(&BufferInitDescriptor {
+                    label: None,
+                    contents: source_viewport_uniform.as_ref(),
+                    usage: BufferUsages::UNIFORM,
+                });
+
+        let bind_group = render_context.render_device().create_bind_group(
+            None,
+            &pipeline.downsample_layout,
+            &BindGroupEntries::sequential((
+                &source,
+                &texture.downsampled.default_view,
+                source_viewport_uniform.as_entire_binding(),
+            )),
         );
-        compute_pass.set_pipeline(average_pipeline);
-        compute_pass.dispatch_workgroups(1, 1, 1);
+        (downsample_pipeline, bind_group)
+    });
 
-        drop(compute_pass);
+    let depth_bind_group = match auto_exposure.depth {
+        Some(params) => {
+            let Some(depth) = world
+                .get::<ViewPrepassTextures>(view_entity)
+                .and_then(|prepass| prepass.depth.as_ref())
+            else {
+                return;
+            };
 
-        // Copy the computed exposure value to the view uniforms.
-        // If this wasn't a plugin, we could just add the STORAGE access modifier to the view uniforms buffer
-        // and write directly to it. But since this is a plugin, we have to resort to this hack.
-        if let Some(view_uniforms_buffer) = world.resource::<ViewUniforms>().uniforms.buffer() {
-            let exposure_offset = view_uniform_offset.offset + 576;
-            render_context.command_encoder().copy_buffer_to_buffer(
-                &auto_exposure.state,
-                0,
-                &view_uniforms_buffer,
-                exposure_offset as u64,
-                4,
-            );
+            let mut depth_params = encase::UniformBuffer::new(Vec::new());
+            depth_params
+                .write(&DepthParams {
+                    // Depth is only read when metering the view, at the full resolution of the
+                    // depth prepass.
+                    viewport: view.viewport,
+                    ..params
+                })
+                .unwrap();
+            let depth_params =
+                render_context
+                    .render_device()
+                    .create_buffer_with_data(&BufferInitDescriptor {
+                        label: None,
+                        contents: depth_params.as_ref(),
+                        usage: BufferUsages::UNIFORM,
+                    });
+
+            let layout = match depth.texture.texture.sample_count() {
+                1 => &pipeline.depth_layout,
+                _ => &pipeline.depth_layout_multisampled,
+            };
+            Some(render_context.render_device().create_bind_group(
+                None,
+                layout,
+                &BindGroupEntries::sequential((
+                    &depth.texture.default_view,
+                    depth_params.as_entire_binding(),
+                )),
+            ))
         }
+        None => None,
+    };
 
-        Ok(())
+    if average_first {
+        average(
+            render_context,
+            histogram,
+            histogram_copies,
+            pipeline,
+            average_pipeline,
+            &compute_bind_group,
+        );
     }
+
+    let mut compute_pass =
+        render_context
+            .command_encoder()
+            .begin_compute_pass(&ComputePassDescriptor {
+                label: Some("auto_exposure_pass"),
+                timestamp_writes: None,
+            });
+
+    if let Some((downsample_pipeline, downsample_bind_group)) = &downsample_bind_group {
+        compute_pass.set_bind_group(0, downsample_bind_group, &[]);
+        compute_pass.set_pipeline(downsample_pipeline);
+        compute_pass.dispatch_workgroups(viewport.z.div_ceil(8), viewport.w.div_ceil(8), 1);
+    }
+
+    compute_pass.set_bind_group(0, &compute_bind_group, &[]);
+    if let Some(depth_bind_group) = &depth_bind_group {
+        compute_pass.set_bind_group(1, depth_bind_group, &[]);
+    }
+    compute_pass.set_pipeline(histogram_pipeline);
+    // Every invocation meters one pixel out of every `sample_stride` pixels.
+    let workgroup_size = pipeline.histogram_workgroup_size;
+    compute_pass.dispatch_workgroups(
+        params
+            .viewport
+            .z
+            .div_ceil(params.sample_stride)
+            .div_ceil(workgroup_size.x),
+        params
+            .viewport
+            .w
+            .div_ceil(params.sample_stride)
+            .div_ceil(workgroup_size.y),
+        1,
+    );
+
+    drop(compute_pass);
+
+    if average_last {
+        average(
+            render_context,
+            histogram,
+            histogram_copies,
+            pipeline,
+            average_pipeline,
+            &compute_bind_group,
+        );
+    }
+}
+
+/// Records the average pass, which updates the exposure in the state buffer from the histogram
+/// and clears it.
+fn average(
+    render_context: &mut RenderContext,
+    histogram: &Buffer,
+    histogram_copies: &[&Buffer],
+    pipeline: &AutoExposurePipeline,
+    average_pipeline: &ComputePipeline,
+    compute_bind_group: &BindGroup,
+) {
+    // The average pass clears the histogram, so copy it out before that happens.
+    for histogram_copy in histogram_copies {
+        render_context.command_encoder().copy_buffer_to_buffer(
+            histogram,
+            0,
+            histogram_copy,
+            0,
+            pipeline.histogram_bins.buffer_size.get(),
+        );
+    }
+
+    let mut compute_pass =
+        render_context
+            .command_encoder()
+            .begin_compute_pass(&ComputePassDescriptor {
+                label: Some("auto_exposure_average_pass"),
+                timestamp_writes: None,
+            });
+
+    compute_pass.set_bind_group(0, compute_bind_group, &[]);
+    compute_pass.set_pipeline(average_pipeline);
+    // The average needs prefix sums over every bin, so a single workgroup reduces the whole
+    // histogram, whatever its size. See `HistogramBins::average_workgroup_size`.
+    compute_pass.dispatch_workgroups(1, 1, 1);
 }
@@ -2,49 +2,325 @@ use std::num::NonZeroU64;
 
 use bevy::{
     prelude::*,
-    render::{render_resource::*, renderer::RenderDevice},
+    render::{render_resource::*, renderer::RenderDevice, texture::CachedTexture},
 };
 
+#[cfg(feature = "fragment_fallback")]
+use crate::fallback::FallbackPipelines;
+use crate::{ExposureOutput, MeteringSource};
+
 #[derive(Resource)]
 pub struct AutoExposurePipeline {
     pub histogram_layout: BindGroupLayout,
     pub histogram_shader: Handle<Shader>,
+    /// The size of the histogram workgroups, in pixels.
+    pub histogram_workgroup_size: UVec2,
+    pub histogram_bins: HistogramBins,
+    /// Layouts of the depth texture that `exclude_depth` reads, without and with MSAA.
+    pub depth_layout: BindGroupLayout,
+    pub depth_layout_multisampled: BindGroupLayout,
+    pub downsample_layout: BindGroupLayout,
+    pub downsample_shader: Handle<Shader>,
 }
 
 #[derive(Component)]
 pub struct ViewAutoExposurePipeline {
-    pub histogram_pipeline: CachedComputePipelineId,
-    pub mean_luminance_pipeline: CachedComputePipelineId,
+    pub pipelines: MeteringPipelines,
+    /// Whether the view is metered this frame, see
+    /// [`AutoExposure::metering_interval`](crate::AutoExposure::metering_interval).
+    pub meter: bool,
     pub state: Buffer,
+    pub histogram_history: Buffer,
+    /// The group that the view is metered with, see
+    /// [`AutoExposureGroup`](crate::AutoExposureGroup).
+    pub group: Option<ViewGroup>,
     pub compensation_curve: TextureView,
+    pub radial_weights: TextureView,
+    /// The weights of the view that the metering mode fades from, see
+    /// [`AutoExposure::metering_mode_fade`](crate::AutoExposure::metering_mode_fade).
+    pub from_weights: TextureView,
+    /// The easing curve, see [`AutoExposure::easing_curve`](crate::AutoExposure::easing_curve).
+    pub easing_curve: TextureView,
     pub params: AutoExposureParams,
     pub metering_mask: Handle<Image>,
+    pub metering_mask_blend: Option<Handle<Image>>,
+    pub metering_mask_layer: Option<Handle<Image>>,
+    pub metering_source: MeteringSource,
+    /// The histogram bin of the reading of a [`MeteringSource::LightSensor`], which is metered
+    /// instead of a texture. `None` while there is no reading.
+    pub sensed_bin: Option<u32>,
+    pub output: ExposureOutput,
+    /// How pixels are excluded and weighed by the depth of the depth prepass, with a viewport
+    /// of zero that the node fills in. `None` when depth isn't read.
+    pub depth: Option<DepthParams>,
+    /// The mip level of the metering source to meter, which the source is known to have.
+    pub metering_mip: u32,
+    /// The 3D LUT that transforms colors before metering, which is known to be loaded.
+    pub metering_lut: Option<Handle<Image>>,
+    /// The exposure to apply instead of the adapted exposure, in EV.
+    pub exposure_override: Option<f32>,
 }
 
-#[derive(ShaderType, Clone, Copy)]
+/// The [`AutoExposureGroup`](crate::AutoExposureGroup) that a view is metered with.
+pub struct ViewGroup {
+    /// The histogram that the members of the group are metered into.
+    pub histogram: Buffer,
+    /// Whether the view is the first member in render order, which averages the histogram.
+    pub first: bool,
+}
+
+/// The pipelines that meter a view, depending on whether the device supports compute shaders.
+pub enum MeteringPipelines {
+    Compute(ComputePipelines),
+    #[cfg(feature = "fragment_fallback")]
+    Fragment(FallbackPipelines),
+}
+
+pub struct ComputePipelines {
+    pub histogram: CachedComputePipelineId,
+    pub average: CachedComputePipelineId,
+    pub downsample: Option<CachedComputePipelineId>,
+}
+
+#[derive(Component)]
+pub struct ViewAutoExposureTexture {
+    pub downsampled: CachedTexture,
+}
+
+/// The textures that the current frame of flip book metering masks is copied to, since the GL
+/// backend can't bind a layer of a texture array as a 2D texture. `None` for masks that aren't
+/// arrays.
+#[derive(Component)]
+pub struct ViewMaskFrames {
+    pub mask: Option<MaskFrame>,
+    pub mask_blend: Option<MaskFrame>,
+    pub mask_layer: Option<MaskFrame>,
+}
+
+pub struct MaskFrame {
+    /// The layer of the mask to copy, which the mask is known to have.
+    pub layer: u32,
+    pub texture: CachedTexture,
+}
+
+/// The parameters that the metering shaders run with, derived from an [`AutoExposure`]
+/// after clamping and resolving its settings. See
+/// [`CurrentAutoExposureParams`](crate::CurrentAutoExposureParams) to read them.
+///
+/// Flags are stored as `u32`, where anything but zero is set. The fields are public to be
+/// read; new ones may be added in any release, so this can't be constructed outside of the crate.
+///
+/// [`AutoExposure`]: crate::AutoExposure
+#[derive(ShaderType, Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
 pub struct AutoExposureParams {
+    /// The lower end of the metering range, in EV.
     pub min_log_lum: f32,
+    /// One over `log_lum_range`.
     pub inv_log_lum_range: f32,
+    /// The size of the metering range, in EV.
     pub log_lum_range: f32,
     pub low_percent: u32,
     pub high_percent: u32,
     pub speed_up: f32,
     pub speed_down: f32,
+    /// The duration of the frame, in seconds.
+    pub delta_time: f32,
+    /// The center of the spot to meter, in uv. Unused while `spot_radius` is zero.
+    pub spot_center: Vec2,
+    /// The radius of the spot to meter, relative to the view height. Zero meters everything.
+    pub spot_radius: f32,
+    /// The rect of the view to meter, in uv, as (min x, min y, max x, max y).
+    pub spot_rect: Vec4,
+    /// How far to cross-fade from the metering mask to the blend mask.
+    pub mask_blend: f32,
+    /// Pixels with a log luminance above this aren't metered.
+    pub saturation_cutoff: f32,
+    pub weight_by_alpha: u32,
+    /// When set, the exposure snaps to its target instead of adapting to it.
+    pub reset: u32,
+    pub deadband: f32,
+    /// The most the exposure may change this frame, in EV.
+    pub max_step: f32,
+    pub dark_floor: f32,
+    /// The target exposure is capped at this, in EV.
+    pub max_gain: f32,
+    /// The gamma of the bin distribution, one for evenly spaced bins.
+    pub bin_gamma: f32,
+    /// When set, bins that the percentiles cut off partially are interpolated.
+    pub interpolate_percentiles: u32,
+    /// When set, the compensation curve holds the target exposure.
+    pub compensation_override: u32,
+    pub correction: f32,
+    /// The environment exposure is the adapted exposure times the scale, plus the correction.
+    pub environment_scale: f32,
+    pub environment_correction: f32,
+    /// The share of the histogram history that is blended into the histogram.
+    pub temporal_blend: f32,
+    /// How far the target exposure follows the key of the scene, zero to ignore it.
+    pub key_strength: f32,
+    /// The region of the metering masks to meter with, as (x, y, width, height).
+    pub mask_region: Vec4,
+    /// The mask weights are scaled by the gain and offset by the bias.
+    pub mask_gain: f32,
+    pub mask_bias: f32,
+    /// When set, pixels are weighed by the radial metering curve.
+    pub radial: u32,
+    /// Only every this many pixels are metered, in each dimension.
+    pub sample_stride: u32,
+    /// The adapted exposure that the camera was already rendered with, in EV, which is undone
+    /// before metering. Zero unless the output is
+    /// [`ExposureOutput::CameraExposure`](crate::ExposureOutput::CameraExposure).
+    pub camera_exposure: f32,
+    /// The rect of the metered texture to meter, as (x, y, width, height). Set by the node,
+    /// and zero before that.
+    pub viewport: UVec4,
+    /// The number of `exclusion_rects` in use.
+    pub exclusion_count: u32,
+    /// Pixels within these rects of the view aren't metered, as (min x, min y, max x, max y).
+    pub exclusion_rects: [Vec4; MAX_EXCLUSION_RECTS],
+    /// How far the metering mode has faded in from the weights that it fades from. One when no
+    /// fade is in progress.
+    pub mode_blend: f32,
+    /// When set, the exposure follows the easing curve from where it started adapting.
+    pub easing: u32,
+    /// When set, pixels are metered by their perceptual lightness instead of their luma.
+    pub perceptual_luminance: u32,
+    /// How the mask layer is combined with the metering masks: 1 multiplies, 2 adds and 3 takes
+    /// the larger weight. Zero without a layer.
+    pub mask_combine: u32,
+    /// When set, `target_log_lum` is exposed as mid gray instead of the metered average.
+    pub absolute_target: u32,
+    /// The calibrated luminance to expose as mid gray, as a log luminance of the metered source.
+    pub target_log_lum: f32,
+    /// The metered pixels below and above this log luminance, in EV, are weighed by
+    /// `shadow_weight` and `highlight_weight` when they are averaged.
+    pub tone_split: f32,
+    pub shadow_weight: f32,
+    pub highlight_weight: f32,
+}
+
+/// Parameters of the depth exclusion and focus weighting, bound next to the depth texture.
+#[derive(ShaderType, Clone, Copy)]
+pub struct DepthParams {
+    /// The rect of the depth texture that covers the view, as (x, y, width, height).
+    pub viewport: UVec4,
+    /// Pixels at or below this reversed depth aren't metered. Below zero to meter every pixel.
+    pub exclude_depth: f32,
+    /// The distance that pixels are weighed around, and the distance over which their weight
+    /// halves. A falloff of zero weighs every pixel evenly.
+    pub focus_distance: f32,
+    pub focus_falloff: f32,
+    /// Maps a depth to the distance along the view direction, as
+    /// `(x * depth + y) / (z * depth + w)`.
+    pub depth_to_distance: Vec4,
 }
 
 #[derive(PartialEq, Eq, Hash, Clone)]
 pub enum Pass {
-    Histogram,
+    Downsample,
+    /// Builds the histogram. With `depth_samples`, pixels are excluded and weighed by depth,
+    /// reading a depth texture with that many samples. With `inverse_tonemap`, the metered texture is already
+    /// tonemapped, and the tonemapping is approximately undone. With `lut`, colors are
+    /// transformed by the metering LUT before their luminance is binned.
+    Histogram {
+        depth_samples: Option<u32>,
+        inverse_tonemap: bool,
+        lut: bool,
+    },
     Average,
 }
 
 pub const METERING_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(12987620402995522466);
+pub const DOWNSAMPLE_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(4539021786553217091);
+/// The bin mapping shared by the metering and debug overlay shaders.
+pub const BINS_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(10422731187360516917);
+
+/// Format of the intermediate texture used when metering at a reduced resolution.
+pub const DOWNSAMPLE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// Size of the state buffer, which holds the adapted exposure, the metered average log luminance,
+/// the environment exposure and the number of metered frames, then from byte 16 on the metered
+/// log luminance at both ends of the dynamic range and the start, target and progress of the
+/// eased transition, as `f32`s, padded to 16 bytes.
+pub const STATE_SIZE: u64 = 48;
+
+/// Byte offset of the progress of the eased transition in the state buffer.
+pub const STATE_TRANSITION_PROGRESS_OFFSET: u64 = 32;
+
+/// Byte offset of the number of metered frames in the state buffer. It counts up to 65535 and
+/// starts over at one, so it only changes on frames that metered anything.
+pub const STATE_METERED_FRAMES_OFFSET: u64 = 12;
+
+/// The most [`AutoExposure::exclusion_rects`](crate::AutoExposure::exclusion_rects) that are
+/// left out of metering. Further rects are ignored.
+pub const MAX_EXCLUSION_RECTS: usize = 8;
+
+/// Number of bins of the metering histogram of [`AutoExposurePlugin`](crate::AutoExposurePlugin).
+pub const DEFAULT_HISTOGRAM_BINS: usize = 256;
+
+/// The sizes that follow from the bin count of the metering histogram, which is the `BINS` of
+/// the [`BinnedAutoExposurePlugin`](crate::BinnedAutoExposurePlugin).
+#[derive(Resource, Clone, Copy)]
+pub struct HistogramBins {
+    pub bins: u32,
+    /// Size of the histogram buffer, in bytes.
+    pub buffer_size: NonZeroU64,
+    /// Size of the single workgroup that averages the histogram: one invocation per bin, capped
+    /// at the 256 invocations per workgroup that every device supports. Divides `bins`.
+    pub average_workgroup_size: u32,
+}
+
+/// Fails to compile for bin counts that can't be metered, when `VALID` is used.
+struct AssertBins<const BINS: usize>;
+
+impl<const BINS: usize> AssertBins<BINS> {
+    const VALID: () = assert!(
+        BINS.is_power_of_two() && BINS >= 16 && BINS <= 4096,
+        "the histogram needs a power of two between 16 and 4096 bins"
+    );
+}
+
+impl HistogramBins {
+    /// The sizes of a histogram with `BINS` bins, or a compile error if it can't be metered.
+    pub const fn new<const BINS: usize>() -> Self {
+        let () = AssertBins::<BINS>::VALID;
+        let bins = BINS as u32;
+
+        Self {
+            bins,
+            buffer_size: match NonZeroU64::new(bins as u64 * 4) {
+                Some(size) => size,
+                None => unreachable!(),
+            },
+            // Powers of two of 256 or more are multiples of 256.
+            average_workgroup_size: if bins < 256 { bins } else { 256 },
+        }
+    }
+
+    /// The shader defs of the bin count, for shaders that import the bins module.
+    pub fn shader_defs(&self) -> Vec<ShaderDefVal> {
+        vec![
+            ShaderDefVal::UInt("HISTOGRAM_BINS".into(), self.bins),
+            ShaderDefVal::UInt("AVERAGE_WORKGROUP_SIZE".into(), self.average_workgroup_size),
+        ]
+    }
+}
+
+pub const DEFAULT_HISTOGRAM_WORKGROUP_SIZE: UVec2 = UVec2::new(16, 16);
+
+/// The size of the histogram workgroups that the plugin was configured with, and the device
+/// supports.
+#[derive(Resource)]
+pub struct HistogramWorkgroupSize(pub UVec2);
 
 impl FromWorld for AutoExposurePipeline {
     fn from_world(world: &mut World) -> Self {
         let render_device = world.resource::<RenderDevice>();
 
         Self {
+            histogram_workgroup_size: world.resource::<HistogramWorkgroupSize>().0,
+            histogram_bins: *world.resource::<HistogramBins>(),
             histogram_layout: render_device.create_bind_group_layout(
                 "compute histogram bind group",
                 &[
@@ -94,7 +370,7 @@ impl FromWorld for AutoExposurePipeline {
                         ty: BindingType::Buffer {
                             ty: BufferBindingType::Storage { read_only: false },
                             has_dynamic_offset: false,
-                            min_binding_size: NonZeroU64::new(256 * 4),
+                            min_binding_size: Some(world.resource::<HistogramBins>().buffer_size),
                         },
                         count: None,
                     },
@@ -104,13 +380,127 @@ impl FromWorld for AutoExposurePipeline {
                         ty: BindingType::Buffer {
                             ty: BufferBindingType::Storage { read_only: false },
                             has_dynamic_offset: false,
-                            min_binding_size: NonZeroU64::new(4),
+                            min_binding_size: NonZeroU64::new(STATE_SIZE),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D1,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: true },
+                            view_dimension: TextureViewDimension::D3,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 9,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 10,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: Some(world.resource::<HistogramBins>().buffer_size),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 11,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 12,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D1,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 13,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
                         },
                         count: None,
                     },
                 ],
             ),
             histogram_shader: METERING_SHADER_HANDLE.clone(),
+            depth_layout: depth_layout(render_device, false),
+            depth_layout_multisampled: depth_layout(render_device, true),
+            downsample_layout: render_device.create_bind_group_layout(
+                "compute downsample bind group",
+                &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::WriteOnly,
+                            format: DOWNSAMPLE_FORMAT,
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: Some(UVec4::min_size()),
+                        },
+                        count: None,
+                    },
+                ],
+            ),
+            downsample_shader: DOWNSAMPLE_SHADER_HANDLE.clone(),
         }
     }
 }
@@ -119,16 +509,100 @@ impl SpecializedComputePipeline for AutoExposurePipeline {
     type Key = Pass;
 
     fn specialize(&self, pass: Pass) -> ComputePipelineDescriptor {
+        let (mut layout, shader) = match pass {
+            Pass::Downsample => (
+                vec![self.downsample_layout.clone()],
+                &self.downsample_shader,
+            ),
+            Pass::Histogram { .. } | Pass::Average => {
+                (vec![self.histogram_layout.clone()], &self.histogram_shader)
+            }
+        };
+
+        let mut shader_defs = match pass {
+            Pass::Downsample => vec![],
+            Pass::Histogram { .. } | Pass::Average => {
+                let mut shader_defs = self.histogram_bins.shader_defs();
+                shader_defs.extend([
+                    ShaderDefVal::UInt(
+                        "HISTOGRAM_WORKGROUP_SIZE_X".into(),
+                        self.histogram_workgroup_size.x,
+                    ),
+                    ShaderDefVal::UInt(
+                        "HISTOGRAM_WORKGROUP_SIZE_Y".into(),
+                        self.histogram_workgroup_size.y,
+                    ),
+                ]);
+                shader_defs
+            }
+        };
+
+        if let Pass::Histogram {
+            inverse_tonemap: true,
+            ..
+        } = pass
+        {
+            shader_defs.push("INVERSE_TONEMAP".into());
+        }
+
+        if let Pass::Histogram { lut: true, .. } = pass {
+            shader_defs.push("METERING_LUT".into());
+        }
+
+        if let Pass::Histogram {
+            depth_samples: Some(samples),
+            ..
+        } = pass
+        {
+            shader_defs.push("DEPTH_PREPASS".into());
+            if samples > 1 {
+                shader_defs.push("DEPTH_MULTISAMPLED".into());
+                layout.push(self.depth_layout_multisampled.clone());
+            } else {
+                layout.push(self.depth_layout.clone());
+            }
+        }
+
         ComputePipelineDescriptor {
             label: Some("luminance compute pipeline".into()),
-            layout: vec![self.histogram_layout.clone()],
-            shader: self.histogram_shader.clone(),
-            shader_defs: vec![],
+            layout,
+            shader: shader.clone(),
+            shader_defs,
             entry_point: match pass {
-                Pass::Histogram => "computeHistogram".into(),
+                Pass::Downsample => "downsample".into(),
+                Pass::Histogram { .. } => "computeHistogram".into(),
                 Pass::Average => "computeAverage".into(),
             },
             push_constant_ranges: vec![],
         }
     }
 }
+
+/// Creates the layout of the depth texture of the depth prepass, and its [`DepthParams`].
+fn depth_layout(render_device: &RenderDevice, multisampled: bool) -> BindGroupLayout {
+    render_device.create_bind_group_layout(
+        "auto exposure depth bind group",
+        &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(DepthParams::min_size()),
+                },
+                count: None,
+            },
+        ],
+    )
+}
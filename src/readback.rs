@@ -0,0 +1,696 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{channel, Receiver, Sender},
+    Arc, Mutex,
+};
+
+use bevy::{
+    ecs::{query::QueryItem, system::lifetimeless::Read},
+    prelude::*,
+    render::{
+        camera::Exposure,
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        render_resource::{
+            Buffer, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Maintain, MapMode,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        Render, RenderApp, RenderSet,
+    },
+    utils::HashMap,
+};
+
+use crate::{
+    pipeline::{
+        AutoExposureParams, ViewAutoExposurePipeline, STATE_METERED_FRAMES_OFFSET, STATE_SIZE,
+    },
+    units::{ev_to_multiplier, nits_to_ev100},
+    AutoExposure, ExposureOutput, ExposureOverride,
+};
+
+/// Component that reads the metered brightness of an [`AutoExposure`] camera back to the CPU
+/// every frame, for gameplay logic like stealth detection.
+///
+/// The reading is the average that the camera meters, so it follows the metering mask, mode and
+/// percentiles. It is read back without stalling the GPU, so it lags a few frames behind.
+/// Until the first reading arrives, both values are zero.
+///
+/// The metered average is converted to luminance by undoing the camera's [`Exposure`], and to
+/// EV100 like a reflected light meter does, with the common calibration constant of 12.5:
+/// `ev100 = log2(nits * 100 / 12.5)`. This only matches real world units when the lights in the
+/// scene use physically based intensities.
+///
+/// The dynamic range of the scene is read back along with the average, as the luminance at the
+/// 1st and 99th percentile of the metered pixels. Unlike the average, it isn't trimmed by the
+/// metering percentiles. Both ends are quantized to the histogram bins and clamped to the
+/// metering range, and they stay zero on devices without compute shaders, which don't meter a
+/// histogram.
+#[derive(Component, Clone, Copy, Debug, Default, Reflect)]
+#[reflect(Component)]
+pub struct MeteredLuminance {
+    /// The metered brightness, as the exposure value at ISO 100 that would expose it as mid
+    /// gray.
+    pub ev100: f32,
+    /// The metered luminance, in nits (cd/m²).
+    pub nits: f32,
+    /// The luminance at the dark end of the dynamic range, in nits (cd/m²).
+    pub min_nits: f32,
+    /// The luminance at the bright end of the dynamic range, in nits (cd/m²).
+    pub max_nits: f32,
+}
+
+impl MeteredLuminance {
+    /// The dynamic range of the scene, from `min_nits` to `max_nits`, in stops. `None` until
+    /// the range was metered.
+    pub fn dynamic_range(&self) -> Option<f32> {
+        (self.min_nits > 0.0).then(|| (self.max_nits / self.min_nits).log2())
+    }
+}
+
+impl ExtractComponent for MeteredLuminance {
+    type QueryData = ();
+    type QueryFilter = (With<Camera>, With<AutoExposure>, With<Self>);
+    type Out = ViewStateReadback;
+
+    fn extract_component(_: QueryItem<'_, Self::QueryData>) -> Option<Self::Out> {
+        Some(ViewStateReadback)
+    }
+}
+
+/// Component that tracks whether the exposure of an [`AutoExposure`] camera has settled, for
+/// example to wait for it to converge before starting a cutscene.
+///
+/// The exposure is read back every frame, without stalling the GPU, so this lags a few frames
+/// behind. It counts as settled once it changed by no more than `epsilon` for `frames`
+/// consecutive metered frames, and stops being settled as soon as it changes by more again.
+/// Frames that don't meter anything are left out, like those while the pipelines are still
+/// compiling, while metering is paused or between the frames of a `metering_interval`.
+#[derive(Component, Clone, Debug, Reflect)]
+#[reflect(Component)]
+pub struct ExposureSettled {
+    /// The largest change of the exposure between two frames, in EV, that counts as settled.
+    pub epsilon: f32,
+    /// The number of consecutive frames that the exposure must stay within `epsilon`.
+    pub frames: u32,
+    stable_frames: u32,
+    last_exposure: Option<f32>,
+    metered_frames: f32,
+}
+
+impl Default for ExposureSettled {
+    fn default() -> Self {
+        Self {
+            epsilon: 0.01,
+            frames: 10,
+            stable_frames: 0,
+            last_exposure: None,
+            metered_frames: 0.0,
+        }
+    }
+}
+
+impl ExposureSettled {
+    /// Whether the exposure has settled.
+    pub fn is_settled(&self) -> bool {
+        self.last_exposure.is_some() && self.stable_frames >= self.frames
+    }
+
+    /// The exposure of the last metered frame that was read back, in EV, or `None` before the
+    /// first one.
+    pub fn exposure(&self) -> Option<f32> {
+        self.last_exposure
+    }
+
+    /// Tracks the exposure of the next frame that was read back, if that frame metered anything,
+    /// which changed the number of metered frames.
+    fn track(&mut self, exposure: f32, metered_frames: f32) {
+        if metered_frames == self.metered_frames {
+            return;
+        }
+        self.metered_frames = metered_frames;
+
+        match self.last_exposure {
+            Some(last) if (exposure - last).abs() <= self.epsilon => {
+                self.stable_frames = self.stable_frames.saturating_add(1);
+            }
+            _ => self.stable_frames = 0,
+        }
+        self.last_exposure = Some(exposure);
+    }
+}
+
+impl ExtractComponent for ExposureSettled {
+    type QueryData = ();
+    type QueryFilter = (With<Camera>, With<AutoExposure>, With<Self>);
+    type Out = ViewStateReadback;
+
+    fn extract_component(_: QueryItem<'_, Self::QueryData>) -> Option<Self::Out> {
+        Some(ViewStateReadback)
+    }
+}
+
+/// The base exposure of an [`ExposureOutput::CameraExposure`] camera, and the adapted exposure
+/// that was last read back for it.
+#[derive(Component)]
+pub(crate) struct CameraExposureOutput {
+    /// The camera's [`Exposure`] when the output was selected.
+    pub base: Exposure,
+    exposure: Option<f32>,
+}
+
+impl ExtractComponent for CameraExposureOutput {
+    type QueryData = (Read<Self>, Option<Read<Exposure>>);
+    type QueryFilter = (With<Camera>, With<AutoExposure>);
+    type Out = (ViewCameraExposure, ViewStateReadback);
+
+    fn extract_component((output, exposure): QueryItem<'_, Self::QueryData>) -> Option<Self::Out> {
+        let ev100 = exposure.copied().unwrap_or_default().ev100;
+        Some((
+            ViewCameraExposure(output.base.ev100 - ev100),
+            ViewStateReadback,
+        ))
+    }
+}
+
+/// The adapted exposure that an [`ExposureOutput::CameraExposure`] view is rendered with, in
+/// EV, which is undone before metering.
+#[derive(Component)]
+pub(crate) struct ViewCameraExposure(pub f32);
+
+/// Marks a view that reads its state buffer back every frame.
+#[derive(Component, Clone)]
+pub struct ViewStateReadback;
+
+/// A mappable copy of the state buffer of a view, made by the metering node.
+#[derive(Component)]
+pub(crate) struct ViewStateReadbackBuffer {
+    pub buffer: Buffer,
+}
+
+/// Component that snaps the exposure of an [`AutoExposure`] camera to the metered value on the
+/// frame it is added, and reports when that is done with an [`ExposureCapturedEvent`].
+///
+/// This makes the exposure deterministic for automated rendering tests and screenshot tools:
+/// add this, wait for the event, and take the screenshot. Screenshots taken once the event was
+/// received use the snapped exposure. It works on both metering paths, and in headless apps
+/// that render to an image.
+///
+/// Frames that don't meter anything, like those while the pipelines are still compiling, can't
+/// snap the exposure, so it snaps on every frame until one does. The component is removed when
+/// the event is sent.
+#[derive(Component, Clone, Copy, Debug, Default, Reflect)]
+#[reflect(Component)]
+pub struct CaptureReady;
+
+impl ExtractComponent for CaptureReady {
+    type QueryData = ();
+    type QueryFilter = (With<Camera>, With<AutoExposure>, With<Self>);
+    type Out = Self;
+
+    fn extract_component(_: QueryItem<'_, Self::QueryData>) -> Option<Self> {
+        Some(Self)
+    }
+}
+
+/// Event sent when the exposure of a camera was snapped after adding [`CaptureReady`].
+#[derive(Event, Clone, Debug)]
+pub struct ExposureCapturedEvent {
+    /// The camera entity that the exposure belongs to.
+    pub entity: Entity,
+    /// The snapped exposure, in EV.
+    pub exposure: f32,
+}
+
+/// A mappable copy of the state buffer of a view, made by the metering node on the frames its
+/// exposure is snapped for [`CaptureReady`], followed by the number of metered frames before
+/// metering.
+#[derive(Component)]
+pub(crate) struct ViewCaptureReadbackBuffer {
+    pub buffer: Buffer,
+}
+
+/// The state buffer of a view that was read back.
+struct StateReadback {
+    entity: Entity,
+    exposure: f32,
+    average: f32,
+    metered_frames: f32,
+    range_low: f32,
+    range_high: f32,
+}
+
+#[derive(Resource)]
+struct StateReadbackSender(Sender<StateReadback>);
+
+#[derive(Resource)]
+struct StateReadbackReceiver(Mutex<Receiver<StateReadback>>);
+
+#[derive(Resource)]
+struct ExposureCapturedSender(Sender<ExposureCapturedEvent>);
+
+#[derive(Resource)]
+struct ExposureCapturedReceiver(Mutex<Receiver<ExposureCapturedEvent>>);
+
+#[derive(Resource, Default)]
+struct PendingCaptureReadbacks {
+    readbacks: Vec<(Entity, Buffer)>,
+}
+
+/// A readback buffer, and whether it's in use by a readback that hasn't been received yet.
+struct StateReadbackBuffer {
+    buffer: Buffer,
+    in_flight: Arc<AtomicBool>,
+}
+
+/// The state readback buffers of every view, which are reused once their readback arrived.
+#[derive(Resource, Default)]
+struct StateReadbackBuffers {
+    buffers: HashMap<Entity, Vec<StateReadbackBuffer>>,
+    pending: Vec<(Entity, Buffer, Arc<AtomicBool>)>,
+}
+
+/// Resource that holds the [`AutoExposureParams`] that every enabled [`AutoExposure`] camera
+/// was last metered with, as the render world computed them. This shows what the settings of
+/// a camera resolve to, after clamping the percentiles, applying its compensation mode and so
+/// on.
+///
+/// The parameters are mirrored back from the render world, so they lag a frame behind.
+#[derive(Resource, Default)]
+pub struct CurrentAutoExposureParams {
+    params: HashMap<Entity, AutoExposureParams>,
+}
+
+impl CurrentAutoExposureParams {
+    /// Returns the parameters that `entity` was last metered with, or `None` when it isn't an
+    /// enabled [`AutoExposure`] camera, or hasn't been rendered yet.
+    pub fn current_params(&self, entity: Entity) -> Option<AutoExposureParams> {
+        self.params.get(&entity).copied()
+    }
+}
+
+#[derive(Resource)]
+struct ParamsSender(Sender<HashMap<Entity, AutoExposureParams>>);
+
+#[derive(Resource)]
+struct ParamsReceiver(Mutex<Receiver<HashMap<Entity, AutoExposureParams>>>);
+
+/// System sets of the auto exposure plugin in the main world.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AutoExposureSet {
+    /// Receives the results that were read back from the GPU: it updates [`MeteredLuminance`],
+    /// [`ExposureSettled`] and [`CurrentAutoExposureParams`], and sends
+    /// [`ExposureCapturedEvent`]s, and `HistogramReadbackEvent`s with the `debug` feature.
+    ///
+    /// This runs in the [`First`] schedule, so systems in later schedules always see the latest
+    /// results. Systems in [`First`] that use them should run after this set:
+    ///
+    /// ```
+    /// # use bevy::prelude::*;
+    /// # use bevy_mod_auto_exposure::{AutoExposureSet, MeteredLuminance};
+    /// #[derive(Component)]
+    /// struct BrightnessLabel;
+    ///
+    /// fn update_brightness_label(
+    ///     cameras: Query<&MeteredLuminance>,
+    ///     mut labels: Query<&mut Text, With<BrightnessLabel>>,
+    /// ) {
+    ///     let Ok(luminance) = cameras.get_single() else {
+    ///         return;
+    ///     };
+    ///     for mut text in labels.iter_mut() {
+    ///         text.sections[0].value = format!("{:.1} EV", luminance.ev100);
+    ///     }
+    /// }
+    ///
+    /// # let mut app = App::new();
+    /// app.add_systems(
+    ///     First,
+    ///     update_brightness_label.after(AutoExposureSet::Readback),
+    /// );
+    /// ```
+    Readback,
+    /// Runs right after [`AutoExposureSet::Readback`] in the [`First`] schedule, for systems
+    /// that post-process the adapted exposure into an
+    /// [`ExposureOverride`](crate::ExposureOverride).
+    PostProcess,
+}
+
+/// Reads back GPU results to the main world.
+pub(crate) struct ReadbackPlugin;
+
+impl Plugin for ReadbackPlugin {
+    fn build(&self, app: &mut App) {
+        let (state_sender, state_receiver) = channel();
+        let (capture_sender, capture_receiver) = channel();
+        let (params_sender, params_receiver) = channel();
+
+        app.register_type::<MeteredLuminance>()
+            .register_type::<ExposureSettled>()
+            .register_type::<CaptureReady>()
+            .add_event::<ExposureCapturedEvent>()
+            .insert_resource(StateReadbackReceiver(Mutex::new(state_receiver)))
+            .insert_resource(ExposureCapturedReceiver(Mutex::new(capture_receiver)))
+            .insert_resource(ParamsReceiver(Mutex::new(params_receiver)))
+            .init_resource::<CurrentAutoExposureParams>()
+            .add_plugins((
+                ExtractComponentPlugin::<MeteredLuminance>::default(),
+                ExtractComponentPlugin::<ExposureSettled>::default(),
+                ExtractComponentPlugin::<CaptureReady>::default(),
+                ExtractComponentPlugin::<CameraExposureOutput>::default(),
+            ))
+            .configure_sets(
+                First,
+                (AutoExposureSet::Readback, AutoExposureSet::PostProcess).chain(),
+            )
+            .add_systems(
+                First,
+                (
+                    (receive_state_readbacks, receive_captures, receive_params)
+                        .in_set(AutoExposureSet::Readback),
+                    apply_camera_exposure.after(AutoExposureSet::PostProcess),
+                ),
+            );
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .insert_resource(StateReadbackSender(state_sender))
+            .insert_resource(ExposureCapturedSender(capture_sender))
+            .insert_resource(ParamsSender(params_sender))
+            .init_resource::<StateReadbackBuffers>()
+            .init_resource::<PendingCaptureReadbacks>()
+            .add_systems(
+                Render,
+                (
+                    prepare_state_readbacks.in_set(RenderSet::Prepare),
+                    prepare_capture_readbacks.in_set(RenderSet::Prepare),
+                    send_params.in_set(RenderSet::Prepare),
+                    map_state_readbacks.in_set(RenderSet::Cleanup),
+                    map_capture_readbacks.in_set(RenderSet::Cleanup),
+                ),
+            );
+    }
+}
+
+type StateReadbackQuery = (
+    Option<&'static mut MeteredLuminance>,
+    Option<&'static mut ExposureSettled>,
+    Option<&'static mut CameraExposureOutput>,
+    Option<&'static Exposure>,
+);
+
+fn receive_state_readbacks(
+    receiver: Res<StateReadbackReceiver>,
+    mut cameras: Query<StateReadbackQuery>,
+) {
+    let receiver = receiver.0.lock().unwrap();
+    for readback in receiver.try_iter() {
+        let Ok((luminance, settled, output, exposure)) = cameras.get_mut(readback.entity) else {
+            continue;
+        };
+
+        if let Some(mut luminance) = luminance {
+            // The metered average is the log luminance of the exposed image, so undo the
+            // exposure. The adapted exposure that the camera applies was already undone.
+            let exposure = match &output {
+                Some(output) => output.base,
+                None => exposure.copied().unwrap_or_default(),
+            };
+            let to_nits = |log_lum| ev_to_multiplier(log_lum) / exposure.exposure();
+            let nits = to_nits(readback.average);
+            *luminance = MeteredLuminance {
+                ev100: nits_to_ev100(nits),
+                nits,
+                min_nits: to_nits(readback.range_low),
+                max_nits: to_nits(readback.range_high),
+            };
+        }
+
+        if let Some(mut settled) = settled {
+            settled.track(readback.exposure, readback.metered_frames);
+        }
+
+        if let Some(mut output) = output {
+            output.exposure = Some(readback.exposure);
+        }
+    }
+}
+
+type CameraExposureQuery = (
+    Entity,
+    &'static AutoExposure,
+    Option<&'static mut Exposure>,
+    Option<&'static mut CameraExposureOutput>,
+    Option<&'static ExposureOverride>,
+);
+
+/// Writes the adapted exposure of [`ExposureOutput::CameraExposure`] cameras into their
+/// [`Exposure`], and restores it once they stop using that output.
+fn apply_camera_exposure(
+    mut commands: Commands,
+    mut cameras: Query<CameraExposureQuery>,
+    mut removed: Query<(Entity, &CameraExposureOutput, &mut Exposure), Without<AutoExposure>>,
+) {
+    for (entity, auto_exposure, exposure, output, exposure_override) in cameras.iter_mut() {
+        let active =
+            auto_exposure.enabled && auto_exposure.output == ExposureOutput::CameraExposure;
+        match (active, exposure, output) {
+            (true, exposure, None) => {
+                commands.entity(entity).insert(CameraExposureOutput {
+                    base: exposure.as_deref().copied().unwrap_or_default(),
+                    exposure: None,
+                });
+            }
+            (true, exposure, Some(output)) => {
+                let Some(adapted) = exposure_override
+                    .map(|exposure| exposure.0)
+                    .or(output.exposure)
+                else {
+                    continue;
+                };
+                let ev100 = output.base.ev100 - adapted;
+                match exposure {
+                    Some(mut exposure) => {
+                        if exposure.ev100 != ev100 {
+                            exposure.ev100 = ev100;
+                        }
+                    }
+                    None => {
+                        commands.entity(entity).insert(Exposure { ev100 });
+                    }
+                }
+            }
+            (false, exposure, Some(output)) => {
+                if let Some(mut exposure) = exposure {
+                    *exposure = output.base;
+                }
+                commands.entity(entity).remove::<CameraExposureOutput>();
+            }
+            (false, _, None) => {}
+        }
+    }
+
+    for (entity, output, mut exposure) in removed.iter_mut() {
+        *exposure = output.base;
+        commands.entity(entity).remove::<CameraExposureOutput>();
+    }
+}
+
+fn prepare_state_readbacks(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    mut buffers: ResMut<StateReadbackBuffers>,
+    views: Query<Entity, (With<ViewStateReadback>, With<ViewAutoExposurePipeline>)>,
+) {
+    buffers.buffers.retain(|entity, _| views.contains(*entity));
+
+    for entity in views.iter() {
+        let pool = buffers.buffers.entry(entity).or_default();
+
+        // Reuse a buffer whose readback has arrived, so a new buffer is only created while the
+        // GPU is behind.
+        let readback = match pool
+            .iter()
+            .find(|readback| !readback.in_flight.load(Ordering::Acquire))
+        {
+            Some(readback) => readback,
+            None => {
+                pool.push(StateReadbackBuffer {
+                    buffer: device.create_buffer(&BufferDescriptor {
+                        label: Some("auto exposure state readback buffer"),
+                        size: STATE_SIZE,
+                        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    }),
+                    in_flight: default(),
+                });
+                pool.last().unwrap()
+            }
+        };
+
+        readback.in_flight.store(true, Ordering::Release);
+        let (buffer, in_flight) = (readback.buffer.clone(), readback.in_flight.clone());
+        buffers.pending.push((entity, buffer.clone(), in_flight));
+        commands
+            .entity(entity)
+            .insert(ViewStateReadbackBuffer { buffer });
+    }
+}
+
+fn map_state_readbacks(
+    device: Res<RenderDevice>,
+    sender: Res<StateReadbackSender>,
+    mut buffers: ResMut<StateReadbackBuffers>,
+) {
+    if buffers.pending.is_empty() {
+        return;
+    }
+
+    for (entity, buffer, in_flight) in buffers.pending.drain(..) {
+        let sender = sender.0.clone();
+        let mapped = buffer.clone();
+        device.map_buffer(&buffer.slice(..), MapMode::Read, move |result| {
+            if result.is_ok() {
+                let state = mapped.slice(..).get_mapped_range();
+                let exposure = f32::from_le_bytes(state[0..4].try_into().unwrap());
+                let average = f32::from_le_bytes(state[4..8].try_into().unwrap());
+                let metered_frames = f32::from_le_bytes(state[12..16].try_into().unwrap());
+                let range_low = f32::from_le_bytes(state[16..20].try_into().unwrap());
+                let range_high = f32::from_le_bytes(state[20..24].try_into().unwrap());
+                drop(state);
+                mapped.unmap();
+
+                // The receiver only goes away when the app is shutting down.
+                let _ = sender.send(StateReadback {
+                    entity,
+                    exposure,
+                    average,
+                    metered_frames,
+                    range_low,
+                    range_high,
+                });
+            }
+            in_flight.store(false, Ordering::Release);
+        });
+    }
+
+    // Don't wait for the GPU, the buffers are received once it has finished a later frame.
+    device.poll(Maintain::Poll);
+}
+
+fn receive_captures(
+    mut commands: Commands,
+    receiver: Res<ExposureCapturedReceiver>,
+    mut events: EventWriter<ExposureCapturedEvent>,
+    captures: Query<(), With<CaptureReady>>,
+    mut captured: Local<Vec<Entity>>,
+) {
+    // The exposure keeps snapping until the first snapped frame is received, so the frames in
+    // flight by then are snapped too. Only the first one is sent.
+    captured.clear();
+    let receiver = receiver.0.lock().unwrap();
+    for event in receiver.try_iter() {
+        if captures.contains(event.entity) && !captured.contains(&event.entity) {
+            captured.push(event.entity);
+            commands.entity(event.entity).remove::<CaptureReady>();
+            events.send(event);
+        }
+    }
+}
+
+fn prepare_capture_readbacks(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    mut pending: ResMut<PendingCaptureReadbacks>,
+    views: Query<(Entity, &ViewAutoExposurePipeline), With<CaptureReady>>,
+) {
+    if views.is_empty() {
+        return;
+    }
+
+    // The metered frames are copied before the frame is rendered, so the copy of the state after
+    // metering tells whether this frame metered, also for the members of a group that share the
+    // state of its first member.
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("auto exposure capture metered frames"),
+    });
+    for (entity, auto_exposure) in views.iter() {
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("auto exposure capture readback buffer"),
+            size: STATE_SIZE + 4,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_buffer_to_buffer(
+            &auto_exposure.state,
+            STATE_METERED_FRAMES_OFFSET,
+            &buffer,
+            STATE_SIZE,
+            4,
+        );
+
+        pending.readbacks.push((entity, buffer.clone()));
+        commands
+            .entity(entity)
+            .insert(ViewCaptureReadbackBuffer { buffer });
+    }
+    queue.submit([encoder.finish()]);
+}
+
+fn map_capture_readbacks(
+    device: Res<RenderDevice>,
+    sender: Res<ExposureCapturedSender>,
+    mut pending: ResMut<PendingCaptureReadbacks>,
+) {
+    if pending.readbacks.is_empty() {
+        return;
+    }
+
+    for (entity, buffer) in pending.readbacks.drain(..) {
+        let sender = sender.0.clone();
+        let mapped = buffer.clone();
+        device.map_buffer(&buffer.slice(..), MapMode::Read, move |result| {
+            if result.is_err() {
+                return;
+            }
+            let state = mapped.slice(..).get_mapped_range();
+            let read = |offset: u64| {
+                let offset = offset as usize;
+                f32::from_le_bytes(state[offset..offset + 4].try_into().unwrap())
+            };
+            let exposure = read(0);
+            let metered = read(STATE_METERED_FRAMES_OFFSET) != read(STATE_SIZE);
+            drop(state);
+            mapped.unmap();
+
+            // The exposure only snapped when the frame metered anything.
+            if metered {
+                // The receiver only goes away when the app is shutting down.
+                let _ = sender.send(ExposureCapturedEvent { entity, exposure });
+            }
+        });
+    }
+
+    // The event is sent once the GPU has finished the frame, without waiting for it here.
+    device.poll(Maintain::Poll);
+}
+
+fn receive_params(receiver: Res<ParamsReceiver>, mut current: ResMut<CurrentAutoExposureParams>) {
+    // Only the latest frame matters, when the render world sent more than one.
+    let receiver = receiver.0.lock().unwrap();
+    if let Some(params) = receiver.try_iter().last() {
+        current.params = params;
+    }
+}
+
+fn send_params(sender: Res<ParamsSender>, views: Query<(Entity, &ViewAutoExposurePipeline)>) {
+    let params = views
+        .iter()
+        .map(|(entity, auto_exposure)| (entity, auto_exposure.params))
+        .collect();
+
+    // The receiver only goes away when the app is shutting down.
+    let _ = sender.0.send(params);
+}
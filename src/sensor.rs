@@ -0,0 +1,221 @@
+use std::f32::consts::PI;
+
+use bevy::{
+    prelude::*,
+    render::{camera::Exposure, Extract, RenderApp},
+    transform::TransformSystem,
+};
+
+use crate::{
+    pipeline::HistogramBins, readback::CameraExposureOutput, AutoExposure, MeteringSource,
+};
+
+/// Component that turns an entity into a light sensor, which an [`AutoExposure`] camera can
+/// meter instead of its view, with [`MeteringSource::LightSensor`].
+///
+/// The sensor adds up the light that its `lights` cast on a gray card at its position, on the
+/// CPU, without rendering anything. This is much cheaper than metering the view, which suits
+/// simple games, for example to adapt to the light around the player when they enter a dark
+/// room. The reading is metered like a view with a single luminance, so the compensation curve,
+/// the speed of adaptation and the other settings apply as usual.
+///
+/// The sensor only approximates what the camera sees:
+/// - Only the registered `lights` and the [`AmbientLight`] are added up. Emissive materials,
+///   environment maps and the sky are ignored.
+/// - Lights aren't shadowed or occluded, so a light behind a wall still counts.
+/// - The card faces every light at once, so the reading doesn't depend on the orientation of
+///   the sensor.
+/// - The reading lands in a single histogram bin, so it's quantized to the bin size, and the
+///   percentiles and the metering mask have nothing to trim.
+/// - A reading below the minimum of the metering range holds the exposure, like a view that
+///   is darker than the range.
+///
+/// The sensed luminance is written to a [`SensedLuminance`] on the sensor, after transforms are
+/// propagated in [`PostUpdate`].
+#[derive(Component, Clone, Debug, Reflect)]
+#[reflect(Component, Default)]
+pub struct LightSensor {
+    /// The fraction of light that the gray card reflects. Defaults to 0.18, the reflectance of
+    /// a mid gray card.
+    pub reflectance: f32,
+    /// The [`PointLight`], [`SpotLight`] and [`DirectionalLight`] entities that light the
+    /// sensor. Other entities, and lights that aren't visible, are skipped.
+    pub lights: Vec<Entity>,
+}
+
+impl Default for LightSensor {
+    fn default() -> Self {
+        Self {
+            reflectance: 0.18,
+            lights: Vec::new(),
+        }
+    }
+}
+
+/// The luminance of the gray card of a [`LightSensor`], in log2 nits (cd/m²), before the
+/// [`Exposure`] of the camera that meters it. Negative infinity in the dark.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Reflect)]
+#[reflect(Component)]
+pub struct SensedLuminance(pub f32);
+
+/// The log2 luminance that an [`AutoExposure`] camera meters from its light sensor, in the
+/// units of its rendered view.
+#[derive(Component)]
+pub(crate) struct ViewSensedLuminance(pub f32);
+
+/// Updates the light sensors, and extracts their readings to the cameras that meter them.
+pub(crate) struct SensorPlugin;
+
+impl Plugin for SensorPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<LightSensor>()
+            .register_type::<SensedLuminance>()
+            .register_type::<Vec<Entity>>()
+            .add_systems(
+                PostUpdate,
+                sense_light.after(TransformSystem::TransformPropagate),
+            );
+
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app.add_systems(ExtractSchedule, extract_sensed_luminance);
+        }
+    }
+}
+
+/// The relative luminance of a linear color.
+fn luminance(color: Color) -> f32 {
+    let [r, g, b, _] = color.as_linear_rgba_f32();
+    0.2125 * r + 0.7154 * g + 0.0721 * b
+}
+
+/// The fraction of the light of a point or spot light that is left at `distance_squared`,
+/// which fades to zero at its range like bevy does.
+fn range_attenuation(distance_squared: f32, range: f32) -> f32 {
+    let factor = distance_squared / (range * range);
+    (1.0 - factor * factor).clamp(0.0, 1.0).powi(2)
+}
+
+type SensorQuery = (
+    Entity,
+    &'static LightSensor,
+    &'static GlobalTransform,
+    Option<&'static mut SensedLuminance>,
+);
+
+type SensedLightQuery = (
+    &'static GlobalTransform,
+    Option<&'static InheritedVisibility>,
+    AnyOf<(
+        &'static PointLight,
+        &'static SpotLight,
+        &'static DirectionalLight,
+    )>,
+);
+
+fn sense_light(
+    mut commands: Commands,
+    mut sensors: Query<SensorQuery>,
+    lights: Query<SensedLightQuery>,
+    ambient: Option<Res<AmbientLight>>,
+) {
+    for (entity, sensor, transform, sensed) in sensors.iter_mut() {
+        let position = transform.translation();
+
+        // The illuminance on the card, in lux.
+        let mut illuminance = 0.0;
+        for light in &sensor.lights {
+            let Ok((light_transform, visibility, (point, spot, directional))) = lights.get(*light)
+            else {
+                continue;
+            };
+            if visibility.is_some_and(|visibility| !visibility.get()) {
+                continue;
+            }
+
+            let to_light = light_transform.translation() - position;
+            let distance_squared = to_light.length_squared();
+            if let Some(point) = point {
+                // Closer than its radius, the light is treated as a sphere instead of a point.
+                illuminance += luminance(point.color) * point.intensity
+                    / (4.0 * PI)
+                    / distance_squared.max(point.radius * point.radius)
+                    * range_attenuation(distance_squared, point.range);
+            }
+            if let Some(spot) = spot {
+                let cos_outer = spot.outer_angle.cos();
+                let scale = 1.0 / (spot.inner_angle.cos() - cos_outer).max(1e-4);
+                let cos_angle = light_transform.forward().dot(-to_light.normalize_or_zero());
+                let cone = ((cos_angle - cos_outer) * scale).clamp(0.0, 1.0).powi(2);
+                illuminance += luminance(spot.color) * spot.intensity
+                    / (4.0 * PI)
+                    / distance_squared.max(spot.radius * spot.radius)
+                    * range_attenuation(distance_squared, spot.range)
+                    * cone;
+            }
+            if let Some(directional) = directional {
+                illuminance += luminance(directional.color) * directional.illuminance;
+            }
+        }
+
+        // A lambertian card reflects its share of the illuminance evenly in every direction,
+        // and the ambient light is applied to its albedo directly.
+        let ambient = ambient
+            .as_ref()
+            .map_or(0.0, |ambient| luminance(ambient.color) * ambient.brightness);
+        let nits = sensor.reflectance * (illuminance / PI + ambient);
+        let log_lum = nits.log2();
+
+        match sensed {
+            Some(mut sensed) => {
+                sensed.set_if_neq(SensedLuminance(log_lum));
+            }
+            None => {
+                commands.entity(entity).insert(SensedLuminance(log_lum));
+            }
+        }
+    }
+}
+
+type SensorCameraQuery = (
+    Entity,
+    &'static AutoExposure,
+    Option<&'static Exposure>,
+    Option<&'static CameraExposureOutput>,
+);
+
+fn extract_sensed_luminance(
+    mut commands: Commands,
+    cameras: Extract<Query<SensorCameraQuery, With<Camera>>>,
+    sensors: Extract<Query<&SensedLuminance>>,
+) {
+    for (entity, auto_exposure, exposure, output) in cameras.iter() {
+        let MeteringSource::LightSensor(sensor) = auto_exposure.metering_source else {
+            continue;
+        };
+        let Ok(sensed) = sensors.get(sensor) else {
+            continue;
+        };
+        // The view is rendered with the exposure of the camera, so the reading is too. The
+        // adapted exposure that the camera applies is undone by the metering.
+        let exposure = match output {
+            Some(output) => output.base,
+            None => exposure.copied().unwrap_or_default(),
+        }
+        .exposure();
+        commands
+            .get_or_spawn(entity)
+            .insert(ViewSensedLuminance(sensed.0 + exposure.log2()));
+    }
+}
+
+/// Returns the histogram bin that a view with a single log2 luminance is metered into, like
+/// the histogram pass bins a pixel.
+pub(crate) fn sensed_bin(log_lum: f32, auto_exposure: &AutoExposure, bins: &HistogramBins) -> u32 {
+    let (min, max) = auto_exposure.metering_range();
+    if log_lum.is_nan() || log_lum < min {
+        return 0;
+    }
+    let normalized = ((log_lum - min) / (max - min)).clamp(0.0, 1.0);
+    let last_bin = bins.bins - 1;
+    (normalized.powf(auto_exposure.bin_distribution.gamma()) * (last_bin - 1) as f32 + 1.0) as u32
+}
@@ -0,0 +1,143 @@
+use std::cell::RefCell;
+
+use bevy::{
+    asset::{Asset, AssetPath},
+    prelude::*,
+};
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+thread_local! {
+    static ASSET_SERVER: RefCell<Option<AssetServer>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with `asset_server` available to load the assets referenced by a deserialized
+/// [`AutoExposure`](crate::AutoExposure).
+///
+/// Asset handles, like the metering mask, are serialized as their asset path. Deserializing
+/// them loads that path again, which requires an asset server:
+///
+/// ```no_run
+/// # use bevy::prelude::*;
+/// # use bevy_mod_auto_exposure::{with_asset_server, AutoExposure};
+/// fn spawn_camera(mut commands: Commands, asset_server: Res<AssetServer>) {
+///     let preset = r#"(min: -12.0, max: 12.0, metering_mask: Some("mask.png"))"#;
+///     let auto_exposure: AutoExposure =
+///         with_asset_server(&asset_server, || ron::from_str(preset)).unwrap();
+///
+///     commands.spawn((Camera3dBundle::default(), auto_exposure));
+/// }
+/// ```
+///
+/// Deserializing a handle with a path outside of this function fails.
+pub fn with_asset_server<R>(asset_server: &AssetServer, f: impl FnOnce() -> R) -> R {
+    let previous = ASSET_SERVER.with(|server| server.replace(Some(asset_server.clone())));
+    let result = f();
+    ASSET_SERVER.with(|server| server.replace(previous));
+    result
+}
+
+/// Loads an asset with the asset server set by [`with_asset_server`].
+fn load<A: Asset, E: Error>(path: AssetPath<'static>) -> Result<Handle<A>, E> {
+    ASSET_SERVER.with(|server| match server.borrow().as_ref() {
+        Some(server) => Ok(server.load(path)),
+        None => Err(E::custom(format!(
+            "can't load \"{path}\" without an asset server, see `with_asset_server`"
+        ))),
+    })
+}
+
+/// (De)serializes a handle as its asset path. Handles without a path serialize as `None`, and
+/// deserialize to the default handle.
+pub(crate) mod handle {
+    use super::*;
+
+    pub fn serialize<A: Asset, S: Serializer>(
+        handle: &Handle<A>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        handle.path().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, A: Asset, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Handle<A>, D::Error> {
+        let path = Option::<AssetPath<'static>>::deserialize(deserializer)?;
+        path.map_or(Ok(Handle::default()), load)
+    }
+}
+
+/// (De)serializes an optional handle as its asset path. Handles without a path serialize as
+/// `None`.
+pub(crate) mod optional_handle {
+    use super::*;
+
+    pub fn serialize<A: Asset, S: Serializer>(
+        handle: &Option<Handle<A>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        handle
+            .as_ref()
+            .and_then(|handle| handle.path())
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, A: Asset, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Handle<A>>, D::Error> {
+        let path = Option::<AssetPath<'static>>::deserialize(deserializer)?;
+        path.map(load).transpose()
+    }
+}
+
+/// (De)serializes [`AutoExposure::metering_mask_blend`](crate::AutoExposure::metering_mask_blend),
+/// with the mask as its asset path.
+pub(crate) mod mask_blend {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct MaskBlend(#[serde(with = "handle")] Handle<Image>, f32);
+
+    pub fn serialize<S: Serializer>(
+        mask_blend: &Option<(Handle<Image>, f32)>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        mask_blend
+            .as_ref()
+            .map(|(mask, blend)| MaskBlend(mask.clone(), *blend))
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<(Handle<Image>, f32)>, D::Error> {
+        Ok(Option::<MaskBlend>::deserialize(deserializer)?
+            .map(|MaskBlend(mask, blend)| (mask, blend)))
+    }
+}
+
+/// (De)serializes [`AutoExposure::metering_mask_layer`](crate::AutoExposure::metering_mask_layer),
+/// with the mask as its asset path.
+pub(crate) mod mask_layer {
+    use super::*;
+    use crate::MaskCombine;
+
+    #[derive(Serialize, Deserialize)]
+    struct MaskLayer(#[serde(with = "handle")] Handle<Image>, MaskCombine);
+
+    pub fn serialize<S: Serializer>(
+        mask_layer: &Option<(Handle<Image>, MaskCombine)>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        mask_layer
+            .as_ref()
+            .map(|(mask, combine)| MaskLayer(mask.clone(), *combine))
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<(Handle<Image>, MaskCombine)>, D::Error> {
+        Ok(Option::<MaskLayer>::deserialize(deserializer)?
+            .map(|MaskLayer(mask, combine)| (mask, combine)))
+    }
+}
@@ -0,0 +1,65 @@
+//! Conversions between the exposure units used by [`AutoExposure`](crate::AutoExposure).
+//!
+//! `min`, `max` and the compensation curve are exposure values (EV), measured in stops: every
+//! EV up doubles the brightness of the image, and every EV down halves it.
+//!
+//! # Luminance calibration
+//!
+//! [`AutoExposure::mid_gray_luminance`](crate::AutoExposure::mid_gray_luminance) and
+//! [`MeteredLuminance`](crate::MeteredLuminance) work in real world luminance, in cd/m² (nits).
+//! They relate it to the metered image the way bevy renders physically based lights: before the
+//! camera's [`Exposure`](bevy::render::camera::Exposure) is applied, a value of one is one
+//! cd/m². The camera multiplies it by `2^-ev100 / 1.2`, so with the default `ev100` of 9.7, a
+//! 100 cd/m² surface renders at about 0.1. Scenes whose lights use arbitrary intensities have
+//! no real world luminance, so only the EV settings are meaningful for them.
+//!
+//! Luminance converts to EV100, the exposure value at ISO 100 that a reflected light meter
+//! reads for it, with the common calibration constant of 12.5, see [`nits_to_ev100`].
+
+/// Converts an exposure value to the factor it multiplies the brightness of the image by.
+///
+/// ```
+/// # use bevy_mod_auto_exposure::units::ev_to_multiplier;
+/// assert_eq!(ev_to_multiplier(0.0), 1.0);
+/// assert_eq!(ev_to_multiplier(1.0), 2.0);
+/// assert_eq!(ev_to_multiplier(-2.0), 0.25);
+/// ```
+pub fn ev_to_multiplier(ev: f32) -> f32 {
+    ev.exp2()
+}
+
+/// Converts a brightness multiplier to the exposure value that applies it.
+///
+/// ```
+/// # use bevy_mod_auto_exposure::units::multiplier_to_ev;
+/// assert_eq!(multiplier_to_ev(1.0), 0.0);
+/// assert_eq!(multiplier_to_ev(2.0), 1.0);
+/// assert_eq!(multiplier_to_ev(0.25), -2.0);
+/// ```
+pub fn multiplier_to_ev(multiplier: f32) -> f32 {
+    multiplier.log2()
+}
+
+/// Converts f-stops to an exposure value. A stop is a doubling of the light, so this is the
+/// identity, but it documents which unit a value is in.
+///
+/// ```
+/// # use bevy_mod_auto_exposure::units::{ev_to_multiplier, stops_to_ev};
+/// // Opening up three stops lets in eight times the light.
+/// assert_eq!(ev_to_multiplier(stops_to_ev(3.0)), 8.0);
+/// ```
+pub fn stops_to_ev(stops: f32) -> f32 {
+    stops
+}
+
+/// Converts a luminance, in cd/m² (nits), to the EV100 that a reflected light meter reads for it:
+/// `log2(nits * 100 / 12.5)`.
+///
+/// ```
+/// # use bevy_mod_auto_exposure::units::nits_to_ev100;
+/// assert_eq!(nits_to_ev100(0.125), 0.0);
+/// assert_eq!(nits_to_ev100(1.0), 3.0);
+/// ```
+pub fn nits_to_ev100(nits: f32) -> f32 {
+    multiplier_to_ev(nits * 100.0 / 12.5)
+}
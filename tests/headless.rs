@@ -0,0 +1,1637 @@
+//! Meters a solid image on the GPU, without a window, and checks the adapted exposure against
+//! the exposure that the metering math predicts.
+//!
+//! This needs a GPU adapter with compute shaders, which can be a software one like llvmpipe.
+//! The tests are run on llvmpipe through the GL backend, with and without `--all-features`,
+//! which includes the tests of the `debug` feature. That backend has no timestamp queries, so
+//! the GPU time that `AutoExposureDiagnosticsPlugin` reports isn't tested.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bevy::{
+    app::{Plugins, PluginsState},
+    core_pipeline::core_3d::graph::{Core3d, Node3d},
+    diagnostic::DiagnosticsStore,
+    ecs::system::RunSystemOnce,
+    log::LogPlugin,
+    prelude::*,
+    render::{
+        camera::{ClearColorConfig, Exposure, RenderTarget, Viewport},
+        pipelined_rendering::PipelinedRenderingPlugin,
+        render_asset::RenderAssetUsages,
+        render_graph::{RenderGraph, RenderGraphApp},
+        render_resource::{
+            BufferDescriptor, BufferId, BufferUsages, CachedPipelineState,
+            CommandEncoderDescriptor, Extent3d, Maintain, MapMode, PipelineCache,
+            PipelineDescriptor, TextureDimension, TextureFormat, TextureUsages,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        view::RenderLayers,
+        Render, RenderApp, RenderSet,
+    },
+    time::TimeUpdateStrategy,
+    window::ExitCondition,
+    winit::WinitPlugin,
+};
+use bevy_mod_auto_exposure::{
+    units::nits_to_ev100, AutoExposure, AutoExposureCommandsExt, AutoExposureGroup,
+    AutoExposureLabel, AutoExposureMeteringDiagnosticsPlugin, AutoExposurePlugin,
+    AutoExposureState, CaptureReady, ExposureCapturedEvent, ExposureSettled, LightSensor,
+    LuminanceModel, MaskCombine, MeteredLuminance, MeteringDiagnosticsSlot, MeteringMode,
+    MeteringSource, PercentileMode, ShareHistogram, ToneWeights, ViewExposureBuffer,
+    ViewHistogramBuffer,
+};
+#[cfg(feature = "debug")]
+use bevy_mod_auto_exposure::{AutoExposureDebugOverlay, HistogramReadback, HistogramReadbackEvent};
+
+/// The luminance of the metered image, in EV.
+const LUMINANCE_EV: f32 = -2.0;
+
+/// The frames to wait for the pipelines to compile and the exposure to be read back.
+const MAX_FRAMES: usize = 200;
+
+/// While present, the exposure snaps to the metered target every frame.
+#[derive(Resource)]
+struct Snap;
+
+#[test]
+fn meters_solid_image() {
+    let mut app = app();
+    let exposure = wait_for_exposure(&mut app);
+
+    // The flat default compensation curve exposes the image as mid gray, which undoes its
+    // luminance. The histogram bins quantize it to 16 / 254 EV.
+    let expected = -LUMINANCE_EV;
+    assert!(
+        (exposure - expected).abs() < 0.1,
+        "metered an exposure of {exposure} EV, expected {expected} EV"
+    );
+}
+
+#[test]
+fn meters_with_own_graph_edges() {
+    let mut app = app_with(AutoExposurePlugin::without_graph_edges());
+    let graph = app.sub_app(RenderApp).world.resource::<RenderGraph>();
+    let edges = &graph
+        .get_sub_graph(Core3d)
+        .unwrap()
+        .get_node_state(AutoExposureLabel)
+        .unwrap()
+        .edges;
+    assert!(
+        edges.input_edges().is_empty() && edges.output_edges().is_empty(),
+        "the node has edges that the plugin shouldn't have added"
+    );
+
+    // Wired like the plugin does without bloom, the view is metered as usual.
+    app.sub_app_mut(RenderApp).add_render_graph_edges(
+        Core3d,
+        (Node3d::EndMainPass, AutoExposureLabel, Node3d::Tonemapping),
+    );
+    let exposure = wait_for_exposure(&mut app);
+    let expected = -LUMINANCE_EV;
+    assert!(
+        (exposure - expected).abs() < 0.1,
+        "metered an exposure of {exposure} EV, expected {expected} EV"
+    );
+}
+
+#[test]
+fn meters_perceptual_lightness() {
+    let mut app = app();
+    // Run the startup systems, then meter a saturated blue by its perceptual lightness.
+    app.update();
+    let mut auto_exposure = app
+        .world
+        .query::<&mut AutoExposure>()
+        .single_mut(&mut app.world);
+    auto_exposure.luminance_model = LuminanceModel::Perceptual;
+    let source = match &auto_exposure.metering_source {
+        MeteringSource::Image(source) => source.clone(),
+        _ => unreachable!(),
+    };
+    let one = half_bytes(1.0);
+    app.world
+        .resource_mut::<Assets<Image>>()
+        .get_mut(&source)
+        .unwrap()
+        .data
+        .copy_from_slice(&[[0; 2], [0; 2], one, one].concat().repeat(64 * 64));
+
+    // The OKLab lightness of pure blue is 0.452, which cubes to a luminance of 0.0924, while its
+    // luma is only 0.0721, or 0.36 EV darker.
+    let exposure = wait_for_exposure(&mut app);
+    let expected = -0.0924f32.log2();
+    assert!(
+        (exposure - expected).abs() < 0.1,
+        "metered an exposure of {exposure} EV, expected {expected} EV"
+    );
+}
+
+#[test]
+fn meters_empty_range() {
+    let mut app = app();
+    // Run the startup systems, then meter a range that is empty, 1 EV below the luminance of the
+    // image.
+    app.update();
+    let mut auto_exposure = app
+        .world
+        .query::<&mut AutoExposure>()
+        .single_mut(&mut app.world);
+    auto_exposure.min = LUMINANCE_EV - 1.0;
+    auto_exposure.max = LUMINANCE_EV - 1.0;
+
+    // The range is widened just enough to meter, instead of mapping the histogram to
+    // infinities, and the image is clamped to the top of it.
+    let exposure = wait_for_exposure(&mut app);
+    let expected = 1.0 - LUMINANCE_EV;
+    assert!(
+        exposure.is_finite() && (exposure - expected).abs() < 0.1,
+        "metered an exposure of {exposure} EV, expected {expected} EV"
+    );
+}
+
+#[test]
+fn exposes_mid_gray_luminance() {
+    let mut app = app();
+    // Run the startup systems, then calibrate a luminance that renders 3 EV brighter than the
+    // image, with the default exposure of the camera.
+    app.update();
+    let mut auto_exposure = app
+        .world
+        .query::<&mut AutoExposure>()
+        .single_mut(&mut app.world);
+    auto_exposure.mid_gray_luminance =
+        Some((LUMINANCE_EV + 3.0).exp2() / Exposure::default().exposure());
+
+    // That luminance is exposed as mid gray, whatever the image meters.
+    let exposure = wait_for_exposure(&mut app);
+    let expected = -LUMINANCE_EV - 3.0;
+    assert!(
+        (exposure - expected).abs() < 0.1,
+        "metered an exposure of {exposure} EV, expected {expected} EV"
+    );
+}
+
+#[test]
+fn reports_metering_diagnostics() {
+    let mut app = app_with((
+        AutoExposurePlugin::default(),
+        AutoExposureMeteringDiagnosticsPlugin,
+    ));
+    let exposure = wait_for_exposure(&mut app);
+    // Stop snapping, so the exposure holds still between the next readbacks.
+    app.world.remove_resource::<Snap>();
+    for _ in 0..5 {
+        app.update();
+    }
+
+    let (camera, slot) = app
+        .world
+        .query::<(Entity, &MeteringDiagnosticsSlot)>()
+        .single(&app.world);
+    let (camera, slot) = (camera, *slot);
+    assert_eq!(slot.index(), 0);
+    let store = app.world.resource::<DiagnosticsStore>();
+    let value = |path| {
+        store
+            .get_measurement(&path)
+            .unwrap_or_else(|| panic!("no measurement of {path:?}"))
+            .value as f32
+    };
+
+    let reported = value(AutoExposureMeteringDiagnosticsPlugin::exposure(slot));
+    assert!(
+        (reported - exposure).abs() < 0.01,
+        "reported an exposure of {reported} EV, expected {exposure} EV"
+    );
+    // The image is metered without the camera's exposure, like a light meter reads it.
+    let average = value(AutoExposureMeteringDiagnosticsPlugin::average(slot));
+    let expected = nits_to_ev100(LUMINANCE_EV.exp2() / Exposure::default().exposure());
+    assert!(
+        (average - expected).abs() < 0.1,
+        "reported an average of {average} EV100, expected {expected} EV100"
+    );
+    let rate = value(AutoExposureMeteringDiagnosticsPlugin::adaptation_rate(slot));
+    assert!(
+        rate.abs() < 0.01,
+        "reported an adaptation rate of {rate} EV/s"
+    );
+
+    // Losing auto exposure frees the slot, clears its diagnostics and removes the readbacks that
+    // were added for them, but not the ones that the camera was spawned with.
+    let auto_exposure = app.world.entity_mut(camera).take::<AutoExposure>().unwrap();
+    app.update();
+    let store = app.world.resource::<DiagnosticsStore>();
+    let diagnostic = store
+        .get(&AutoExposureMeteringDiagnosticsPlugin::exposure(slot))
+        .unwrap();
+    assert!(!diagnostic.is_enabled && diagnostic.measurement().is_none());
+    let camera_ref = app.world.entity(camera);
+    assert!(!camera_ref.contains::<MeteringDiagnosticsSlot>());
+    assert!(!camera_ref.contains::<MeteredLuminance>());
+    assert!(camera_ref.contains::<ExposureSettled>());
+
+    // Getting auto exposure again takes the free slot.
+    app.world.entity_mut(camera).insert(auto_exposure);
+    app.update();
+    let camera_ref = app.world.entity(camera);
+    assert_eq!(camera_ref.get::<MeteringDiagnosticsSlot>(), Some(&slot));
+    assert!(camera_ref.contains::<MeteredLuminance>());
+    let store = app.world.resource::<DiagnosticsStore>();
+    assert!(
+        store
+            .get(&AutoExposureMeteringDiagnosticsPlugin::exposure(slot))
+            .unwrap()
+            .is_enabled
+    );
+}
+
+#[test]
+fn ignores_tiny_bright_light_in_wide_range() {
+    let mut app = app();
+    // Run the startup systems, then add a light that is 18 EV brighter than the rest of the
+    // image to a single pixel, and meter a range that fits it.
+    app.update();
+    let light = half_bytes(16.0f32.exp2());
+    let one = half_bytes(1.0);
+    let source = match &app
+        .world
+        .query::<&AutoExposure>()
+        .single(&app.world)
+        .metering_source
+    {
+        MeteringSource::Image(source) => source.clone(),
+        _ => unreachable!(),
+    };
+    app.world
+        .resource_mut::<Assets<Image>>()
+        .get_mut(&source)
+        .unwrap()
+        .data[..8]
+        .copy_from_slice(&[light, light, light, one].concat());
+    for mut auto_exposure in app
+        .world
+        .query::<&mut AutoExposure>()
+        .iter_mut(&mut app.world)
+    {
+        auto_exposure.min = -24.0;
+        auto_exposure.max = 24.0;
+        auto_exposure.high_percent = 100;
+        auto_exposure.percentile_mode = PercentileMode::Interpolated;
+    }
+
+    // Even though it isn't trimmed by the percentiles, the light only moves the average by its
+    // share of the pixels, instead of collapsing the exposure.
+    let exposure = wait_for_exposure(&mut app);
+    let expected = -LUMINANCE_EV - 18.0 / (64.0 * 64.0);
+    assert!(
+        (exposure - expected).abs() < 0.1,
+        "metered an exposure of {exposure} EV, expected {expected} EV"
+    );
+}
+
+#[test]
+fn reads_back_dynamic_range() {
+    let mut app = app();
+    let camera = brighten_top_half(&mut app);
+    app.world
+        .entity_mut(camera)
+        .insert(MeteredLuminance::default());
+
+    wait_for_exposure(&mut app);
+    let luminance = *app.world.get::<MeteredLuminance>(camera).unwrap();
+    let range = luminance.dynamic_range().unwrap();
+    assert!(
+        (range - 4.0).abs() < 0.2,
+        "read back a dynamic range of {range} stops, expected 4 stops"
+    );
+    // The default percentiles trim the dark half off the average, but not off the range.
+    assert!(
+        (luminance.nits / luminance.max_nits - 1.0).abs() < 0.01,
+        "{luminance:?}"
+    );
+}
+
+#[test]
+fn weighs_shadows_and_highlights() {
+    let mut app = app();
+    let camera = brighten_top_half(&mut app);
+
+    // Meter both halves, split between them, and weigh the dark bottom half three times as
+    // strongly as the bright top half, which is 4 EV brighter.
+    let mut auto_exposure = app.world.get_mut::<AutoExposure>(camera).unwrap();
+    auto_exposure.low_percent = 0;
+    auto_exposure.high_percent = 100;
+    auto_exposure.tone_weights = ToneWeights {
+        split: LUMINANCE_EV + 2.0,
+        shadows: 3.0,
+        highlights: 1.0,
+    };
+
+    let exposure = wait_for_exposure(&mut app);
+    let expected = -(3.0 * LUMINANCE_EV + (LUMINANCE_EV + 4.0)) / 4.0;
+    assert!(
+        (exposure - expected).abs() < 0.1,
+        "metered an exposure of {exposure} EV, expected {expected} EV"
+    );
+}
+
+#[test]
+fn meters_mask_frame() {
+    let mut app = app();
+    let camera = brighten_top_half(&mut app);
+
+    // A flip book of two frames, which weigh the top and the bottom half of the view.
+    let mut mask = Image::new(
+        Extent3d {
+            width: 1,
+            height: 2,
+            depth_or_array_layers: 2,
+        },
+        TextureDimension::D2,
+        vec![255, 0, 0, 255],
+        TextureFormat::R8Unorm,
+        RenderAssetUsages::default(),
+    );
+    mask.texture_descriptor.usage |= TextureUsages::COPY_SRC;
+    let mask = app.world.resource_mut::<Assets<Image>>().add(mask);
+    let mut auto_exposure = app.world.get_mut::<AutoExposure>(camera).unwrap();
+    auto_exposure.metering_mask = mask;
+    auto_exposure.wait_for_mask = true;
+
+    let top = wait_for_exposure(&mut app);
+    let expected = -LUMINANCE_EV - 4.0;
+    assert!(
+        (top - expected).abs() < 0.1,
+        "metered an exposure of {top} EV, expected {expected} EV"
+    );
+
+    app.world
+        .get_mut::<AutoExposure>(camera)
+        .unwrap()
+        .mask_frame = 1;
+    let bottom = wait_for_new_exposure(&mut app, top);
+    let expected = -LUMINANCE_EV;
+    assert!(
+        (bottom - expected).abs() < 0.1,
+        "metered an exposure of {bottom} EV, expected {expected} EV"
+    );
+    wait_for_pipelines(&mut app);
+}
+
+#[test]
+fn combines_mask_layer() {
+    let mut app = app();
+    let camera = brighten_top_half(&mut app);
+
+    // A layer that only weighs the bottom half, multiplied with the default mask that weighs
+    // the whole view.
+    let layer = app.world.resource_mut::<Assets<Image>>().add(Image::new(
+        Extent3d {
+            width: 1,
+            height: 2,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        vec![0, 255],
+        TextureFormat::R8Unorm,
+        RenderAssetUsages::default(),
+    ));
+    let mut auto_exposure = app.world.get_mut::<AutoExposure>(camera).unwrap();
+    auto_exposure.metering_mask_layer = Some((layer.clone(), MaskCombine::Multiply));
+    auto_exposure.wait_for_mask = true;
+
+    let bottom = wait_for_exposure(&mut app);
+    let expected = -LUMINANCE_EV;
+    assert!(
+        (bottom - expected).abs() < 0.1,
+        "metered an exposure of {bottom} EV, expected {expected} EV"
+    );
+
+    // The larger weight of both meters the whole view again, of which the default percentiles
+    // keep the bright top half.
+    app.world
+        .get_mut::<AutoExposure>(camera)
+        .unwrap()
+        .metering_mask_layer = Some((layer, MaskCombine::Max));
+    let whole = wait_for_new_exposure(&mut app, bottom);
+    let expected = -LUMINANCE_EV - 4.0;
+    assert!(
+        (whole - expected).abs() < 0.1,
+        "metered an exposure of {whole} EV, expected {expected} EV"
+    );
+    wait_for_pipelines(&mut app);
+}
+
+#[test]
+fn fades_metering_mode() {
+    let mut app = app();
+    let camera = brighten_top_half(&mut app);
+    let mut auto_exposure = app.world.get_mut::<AutoExposure>(camera).unwrap();
+    auto_exposure.metering_mode = MeteringMode::SpotRect {
+        rect: Rect::new(0.0, 0.0, 1.0, 0.5),
+    };
+    auto_exposure.metering_mode_fade = 1.0;
+    // The fade doesn't advance without time, so it holds the weighting of the top half.
+    auto_exposure.fixed_delta_time = Some(0.0);
+    let top = wait_for_exposure(&mut app);
+
+    // Only a change of the kind of mode fades.
+    app.world
+        .get_mut::<AutoExposure>(camera)
+        .unwrap()
+        .metering_mode = MeteringMode::Spot {
+        center: Vec2::new(0.5, 0.75),
+        radius: 0.2,
+    };
+    for _ in 0..30 {
+        app.update();
+    }
+    let faded = exposure(&mut app).unwrap();
+    assert!(
+        (faded - top).abs() < 0.1,
+        "metered an exposure of {faded} EV during the fade, expected {top} EV"
+    );
+
+    app.world
+        .get_mut::<AutoExposure>(camera)
+        .unwrap()
+        .fixed_delta_time = Some(1.0);
+    let bottom = wait_for_new_exposure(&mut app, top);
+    let expected = -LUMINANCE_EV;
+    assert!(
+        (bottom - expected).abs() < 0.1,
+        "metered an exposure of {bottom} EV, expected {expected} EV"
+    );
+    wait_for_pipelines(&mut app);
+}
+
+#[test]
+fn follows_moving_spot_without_fading() {
+    let mut app = app();
+    let camera = brighten_top_half(&mut app);
+    let mut auto_exposure = app.world.get_mut::<AutoExposure>(camera).unwrap();
+    auto_exposure.metering_mode = MeteringMode::Spot {
+        center: Vec2::new(0.5, 0.25),
+        radius: 0.1,
+    };
+    auto_exposure.metering_mode_fade = 1.0;
+    // A fade wouldn't advance without time, so it would hold the weighting of the top half.
+    auto_exposure.fixed_delta_time = Some(0.0);
+    let top = wait_for_exposure(&mut app);
+
+    // Move the spot every frame, down into the bottom half and then around in it.
+    for frame in 0..40 {
+        let center = Vec2::new(
+            if frame % 2 == 0 { 0.45 } else { 0.55 },
+            (0.25 + frame as f32 * 0.05).min(0.75),
+        );
+        app.world
+            .get_mut::<AutoExposure>(camera)
+            .unwrap()
+            .metering_mode = MeteringMode::Spot {
+            center,
+            radius: 0.1,
+        };
+        app.update();
+    }
+    let bottom = exposure(&mut app).unwrap();
+    let expected = -LUMINANCE_EV;
+    assert!(
+        (bottom - expected).abs() < 0.1,
+        "metered an exposure of {bottom} EV after moving the spot from {top} EV, expected \
+        {expected} EV"
+    );
+    wait_for_pipelines(&mut app);
+}
+
+#[test]
+fn shares_histogram() {
+    let mut app = app();
+    app.update();
+    let camera = app
+        .world
+        .query_filtered::<Entity, With<AutoExposure>>()
+        .single(&app.world);
+    app.world.entity_mut(camera).insert(ShareHistogram);
+    let shared = SharedHistogram::default();
+    app.sub_app_mut(RenderApp)
+        .insert_resource(shared.clone())
+        .add_systems(
+            Render,
+            read_shared_histogram
+                .in_set(RenderSet::Cleanup)
+                .before(World::clear_entities),
+        );
+
+    wait_for_exposure(&mut app);
+    let (bins, histogram) = shared.0.lock().unwrap().take().unwrap();
+    assert_eq!(bins.len(), histogram.bins as usize);
+
+    // The solid image lands in a single bin, at its luminance.
+    let filled: Vec<_> = bins
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count > 0)
+        .collect();
+    assert_eq!(filled.len(), 1, "filled more than one bin: {filled:?}");
+    let normalized =
+        ((filled[0].0 - 1) as f32 / (histogram.bins - 2) as f32).powf(1.0 / histogram.bin_gamma);
+    let log_lum = histogram.min + normalized * (histogram.max - histogram.min);
+    let bin_size = (histogram.max - histogram.min) / (histogram.bins - 2) as f32;
+    assert!(
+        (log_lum - LUMINANCE_EV).abs() <= bin_size,
+        "shared a histogram at {log_lum} EV, expected {LUMINANCE_EV} EV"
+    );
+}
+
+/// The last histogram that was shared with a [`ViewHistogramBuffer`], and the buffer.
+#[derive(Resource, Clone, Default)]
+struct SharedHistogram(Arc<Mutex<Option<SharedBins>>>);
+
+type SharedBins = (Vec<u32>, ViewHistogramBuffer);
+
+/// Copies the shared histograms out once the frame is rendered, like a render node would read
+/// them.
+fn read_shared_histogram(
+    shared: Res<SharedHistogram>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    views: Query<&ViewHistogramBuffer>,
+) {
+    for histogram in views.iter() {
+        let size = histogram.buffer.size();
+        let readback = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor::default());
+        encoder.copy_buffer_to_buffer(&histogram.buffer, 0, &readback, 0, size);
+        queue.submit([encoder.finish()]);
+
+        device.map_buffer(&readback.slice(..), MapMode::Read, |_| {});
+        device.poll(Maintain::Wait);
+        let bins = readback
+            .slice(..)
+            .get_mapped_range()
+            .chunks_exact(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        *shared.0.lock().unwrap() = Some((bins, histogram.clone()));
+    }
+}
+
+/// Runs the startup systems, then brightens the top half of the metered image by 4 EV. Returns
+/// the camera.
+fn brighten_top_half(app: &mut App) -> Entity {
+    app.update();
+    let bright = half_bytes((LUMINANCE_EV + 4.0).exp2());
+    let one = half_bytes(1.0);
+    let (camera, source) = match app
+        .world
+        .query::<(Entity, &AutoExposure)>()
+        .single(&app.world)
+    {
+        (
+            camera,
+            AutoExposure {
+                metering_source: MeteringSource::Image(source),
+                ..
+            },
+        ) => (camera, source.clone()),
+        _ => unreachable!(),
+    };
+    app.world
+        .resource_mut::<Assets<Image>>()
+        .get_mut(&source)
+        .unwrap()
+        .data[..32 * 64 * 8]
+        .copy_from_slice(&[bright, bright, bright, one].concat().repeat(32 * 64));
+    camera
+}
+
+#[test]
+fn meters_group_as_one() {
+    let mut app = app();
+    // Run the startup systems, then add a second camera that meters an image 4 EV darker, and
+    // group both cameras.
+    app.update();
+    let (entity, camera, auto_exposure) = app
+        .world
+        .query::<(Entity, &Camera, &AutoExposure)>()
+        .single(&app.world);
+    let camera = camera.clone();
+    let auto_exposure = AutoExposure {
+        low_percent: 0,
+        high_percent: 100,
+        ..auto_exposure.clone()
+    };
+    app.world
+        .entity_mut(entity)
+        .insert((auto_exposure.clone(), AutoExposureGroup(0)));
+
+    let gray = half_bytes((LUMINANCE_EV - 4.0).exp2());
+    let one = half_bytes(1.0);
+    let darker = app
+        .world
+        .resource_mut::<Assets<Image>>()
+        .add(Image::new_fill(
+            Extent3d {
+                width: 64,
+                height: 64,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[gray, gray, gray, one].concat(),
+            TextureFormat::Rgba16Float,
+            RenderAssetUsages::default(),
+        ));
+    app.world.spawn((
+        Camera3dBundle {
+            camera: Camera { order: 1, ..camera },
+            ..default()
+        },
+        AutoExposure {
+            metering_source: MeteringSource::Image(darker),
+            ..auto_exposure
+        },
+        AutoExposureGroup(0),
+        ExposureSettled::default(),
+    ));
+
+    // Both cameras are exposed for the average of both images, once the histogram of the group
+    // was accumulated by both and averaged in the next frame.
+    let mut frames = 0;
+    while app
+        .world
+        .query::<&ExposureSettled>()
+        .iter(&app.world)
+        .any(|settled| settled.exposure().is_none())
+    {
+        assert!(frames < MAX_FRAMES, "no metered exposure was read back");
+        app.update();
+        frames += 1;
+    }
+    for _ in 0..5 {
+        app.update();
+    }
+    let expected = -LUMINANCE_EV + 2.0;
+    for settled in app.world.query::<&ExposureSettled>().iter(&app.world) {
+        let exposure = settled.exposure().unwrap();
+        assert!(
+            (exposure - expected).abs() < 0.1,
+            "metered an exposure of {exposure} EV, expected {expected} EV"
+        );
+    }
+}
+
+#[test]
+fn keeps_exposure_when_range_changes() {
+    let mut app = app();
+    let mut previous = wait_for_exposure(&mut app);
+
+    app.world.remove_resource::<Snap>();
+    for mut auto_exposure in app
+        .world
+        .query::<&mut AutoExposure>()
+        .iter_mut(&mut app.world)
+    {
+        auto_exposure.min = -4.0;
+        auto_exposure.max = 12.0;
+    }
+
+    // The image is within both ranges, so the exposure should barely move, instead of
+    // restarting from the initial exposure.
+    for _ in 0..20 {
+        app.update();
+        let exposure = exposure(&mut app).unwrap();
+        assert!(
+            (exposure - previous).abs() < 0.1,
+            "the exposure jumped from {previous} EV to {exposure} EV"
+        );
+        previous = exposure;
+    }
+}
+
+#[test]
+fn restarts_eased_transition_when_range_changes() {
+    let mut app = app();
+    wait_for_exposure(&mut app);
+
+    // Start an eased transition 4 EV darker.
+    app.world.remove_resource::<Snap>();
+    for mut auto_exposure in app
+        .world
+        .query::<&mut AutoExposure>()
+        .iter_mut(&mut app.world)
+    {
+        auto_exposure.fixed_delta_time = Some(1.0 / 60.0);
+        auto_exposure.correction = -4.0;
+        auto_exposure.easing_curve = Some(vec![Vec2::ZERO, Vec2::ONE]);
+    }
+    for _ in 0..2 {
+        app.update();
+    }
+
+    // The image is brighter than the new range, so it is metered at its top, which moves the
+    // target halfway back. The exposure must not finish the old transition past the new target.
+    for mut auto_exposure in app
+        .world
+        .query::<&mut AutoExposure>()
+        .iter_mut(&mut app.world)
+    {
+        auto_exposure.max = LUMINANCE_EV - 2.0;
+    }
+    let expected = -(LUMINANCE_EV - 2.0) - 4.0;
+    let mut exposure = 0.0;
+    for _ in 0..60 {
+        app.update();
+        exposure = self::exposure(&mut app).unwrap();
+        assert!(
+            exposure > expected - 0.05,
+            "the exposure overshot to {exposure} EV, past the target of {expected} EV"
+        );
+    }
+    assert!(
+        (exposure - expected).abs() < 0.2,
+        "adapted to {exposure} EV, expected {expected} EV"
+    );
+    wait_for_pipelines(&mut app);
+}
+
+#[test]
+fn adapts_deterministically_with_fixed_delta() {
+    let first = fixed_delta_trajectory(None);
+    let second = fixed_delta_trajectory(None);
+    assert_eq!(first, second);
+}
+
+#[test]
+fn linear_easing_adapts_like_no_easing() {
+    let exponential = fixed_delta_trajectory(None);
+    let eased = fixed_delta_trajectory(Some(vec![Vec2::ZERO, Vec2::ONE]));
+    for (exponential, eased) in exponential.iter().zip(&eased) {
+        assert!(
+            (exponential - eased).abs() < 1e-3,
+            "adapted to {eased:?} with a linear easing curve, expected {exponential:?}"
+        );
+    }
+}
+
+/// Snaps the exposure to the image, then adapts to a brighter target with a fixed delta and the
+/// easing curve, and returns the exposures that are read back on the way.
+fn fixed_delta_trajectory(easing_curve: Option<Vec<Vec2>>) -> Vec<f32> {
+    let mut app = app();
+    let mut previous = wait_for_exposure(&mut app);
+
+    app.world.remove_resource::<Snap>();
+    for mut auto_exposure in app
+        .world
+        .query::<&mut AutoExposure>()
+        .iter_mut(&mut app.world)
+    {
+        auto_exposure.fixed_delta_time = Some(1.0 / 60.0);
+        auto_exposure.correction = 2.0;
+        auto_exposure.easing_curve = easing_curve.clone();
+    }
+
+    // Every frame adapts, but the readbacks arrive a varying number of frames later, so only
+    // the adapted exposures of the first frames are compared, once the adaptation is stopped.
+    let mut trajectory = Vec::new();
+    for frames in 1..4 {
+        for _ in 0..frames {
+            app.update();
+        }
+        set_speed(&mut app, 0.0);
+        previous = wait_for_new_exposure(&mut app, previous);
+        trajectory.push(previous);
+        set_speed(&mut app, 3.0);
+    }
+    wait_for_pipelines(&mut app);
+    trajectory
+}
+
+#[test]
+fn adapts_independently_of_frame_rate() {
+    // A third of a second at both frame rates.
+    let slow = frame_rate_trajectory(30.0, 10);
+    let fast = frame_rate_trajectory(144.0, 48);
+    assert!(
+        (slow - fast).abs() < 1e-3,
+        "adapted by {slow} EV at 30 fps, but by {fast} EV at 144 fps"
+    );
+
+    // The exposure approaches the target exponentially, by the adaptation speed.
+    let expected = 2.0 * (1.0 - (-3.0f32 / 3.0).exp());
+    assert!(
+        (slow - expected).abs() < 1e-2,
+        "adapted by {slow} EV, expected {expected} EV"
+    );
+}
+
+/// Snaps the exposure to the image, then adapts to a target 2 EV brighter for `frames` frames of
+/// a fixed [`Time`] delta at `fps`, and returns how far the exposure moved.
+fn frame_rate_trajectory(fps: f64, frames: usize) -> f32 {
+    let mut app = app();
+    let metered = wait_for_exposure(&mut app);
+
+    app.world.remove_resource::<Snap>();
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+        1.0 / fps,
+    )));
+    set_speed(&mut app, 3.0);
+    for mut auto_exposure in app
+        .world
+        .query::<&mut AutoExposure>()
+        .iter_mut(&mut app.world)
+    {
+        auto_exposure.correction = 2.0;
+    }
+    for _ in 0..frames {
+        app.update();
+    }
+
+    set_speed(&mut app, 0.0);
+    let adapted = wait_for_new_exposure(&mut app, metered);
+    wait_for_pipelines(&mut app);
+    adapted - metered
+}
+
+#[test]
+fn limits_adaptation_rate() {
+    let mut app = app();
+    let metered = wait_for_exposure(&mut app);
+
+    // A fast adaptation towards a target 4 EV brighter is held to a ramp of 1 EV per second.
+    app.world.remove_resource::<Snap>();
+    set_speed(&mut app, 100.0);
+    for mut auto_exposure in app
+        .world
+        .query::<&mut AutoExposure>()
+        .iter_mut(&mut app.world)
+    {
+        auto_exposure.fixed_delta_time = Some(1.0 / 60.0);
+        auto_exposure.max_ev_per_second = Some(1.0);
+        auto_exposure.correction = 4.0;
+    }
+    for _ in 0..30 {
+        app.update();
+    }
+
+    set_speed(&mut app, 0.0);
+    let adapted = wait_for_new_exposure(&mut app, metered) - metered;
+    wait_for_pipelines(&mut app);
+    assert!(
+        (adapted - 0.5).abs() < 0.01,
+        "adapted by {adapted} EV in half a second, expected 0.5 EV"
+    );
+}
+
+fn set_speed(app: &mut App, speed: f32) {
+    for mut auto_exposure in app
+        .world
+        .query::<&mut AutoExposure>()
+        .iter_mut(&mut app.world)
+    {
+        auto_exposure.speed_up = speed;
+        auto_exposure.speed_down = speed;
+    }
+}
+
+/// Updates the app until the exposure that is read back has moved on from `previous` and
+/// settled, and returns it.
+fn wait_for_new_exposure(app: &mut App, previous: f32) -> f32 {
+    for _ in 0..MAX_FRAMES {
+        app.update();
+        let settled = app.world.query::<&ExposureSettled>().single(&app.world);
+        if settled.is_settled() && settled.exposure() != Some(previous) {
+            return exposure(app).unwrap();
+        }
+    }
+
+    panic!("the exposure didn't settle within {MAX_FRAMES} frames");
+}
+
+#[test]
+fn adapts_gradually_without_reset() {
+    let mut app = app();
+    let metered = wait_for_exposure(&mut app);
+
+    // Without a reset, the exposure adapts towards a brighter target over a few frames instead
+    // of snapping to it.
+    app.world.remove_resource::<Snap>();
+    set_speed(&mut app, 3.0);
+    for mut auto_exposure in app
+        .world
+        .query::<&mut AutoExposure>()
+        .iter_mut(&mut app.world)
+    {
+        auto_exposure.fixed_delta_time = Some(1.0 / 60.0);
+        auto_exposure.correction = 2.0;
+    }
+    for _ in 0..3 {
+        app.update();
+    }
+
+    set_speed(&mut app, 0.0);
+    let adapted = wait_for_new_exposure(&mut app, metered) - metered;
+    assert!(
+        adapted > 0.0 && adapted < 1.0,
+        "adapted by {adapted} EV in three frames, expected a fraction of 2 EV"
+    );
+    wait_for_pipelines(&mut app);
+}
+
+#[test]
+fn captures_once_metered() {
+    let mut app = app();
+    // Run the startup systems, then capture right away, while the pipelines are still
+    // compiling. Without adaptation, only the snap moves the exposure.
+    app.world.remove_resource::<Snap>();
+    app.update();
+    set_speed(&mut app, 0.0);
+    let camera = app
+        .world
+        .query_filtered::<Entity, With<AutoExposure>>()
+        .single(&app.world);
+    app.world.entity_mut(camera).insert(CaptureReady);
+
+    let mut captured = Vec::new();
+    for _ in 0..MAX_FRAMES {
+        app.update();
+        captured.extend(
+            app.world
+                .resource_mut::<Events<ExposureCapturedEvent>>()
+                .drain(),
+        );
+        if !captured.is_empty() {
+            break;
+        }
+    }
+    // The frames in flight when the capture was received don't send another one.
+    for _ in 0..10 {
+        app.update();
+        captured.extend(
+            app.world
+                .resource_mut::<Events<ExposureCapturedEvent>>()
+                .drain(),
+        );
+    }
+    wait_for_pipelines(&mut app);
+
+    assert_eq!(captured.len(), 1, "captured {captured:?}");
+    assert_eq!(captured[0].entity, camera);
+    let expected = -LUMINANCE_EV;
+    assert!(
+        (captured[0].exposure - expected).abs() < 0.1,
+        "captured an exposure of {} EV, expected {expected} EV",
+        captured[0].exposure
+    );
+    assert!(!app.world.entity(camera).contains::<CaptureReady>());
+}
+
+#[test]
+fn restores_saved_exposure() {
+    let mut app = app();
+    let metered = wait_for_exposure(&mut app);
+
+    // Hold the exposure, so only the restore moves it.
+    app.world.remove_resource::<Snap>();
+    set_speed(&mut app, 0.0);
+    let restored = metered + 3.0;
+    app.add_systems(
+        Update,
+        (move |mut state: AutoExposureState, cameras: Query<Entity, With<AutoExposure>>| {
+            assert_eq!(state.save_state(cameras.single()), Some(metered));
+            state.load_state(cameras.single(), restored);
+        })
+        .run_if(run_once()),
+    );
+
+    let exposure = wait_for_new_exposure(&mut app, metered);
+    assert_eq!(exposure, restored);
+    wait_for_pipelines(&mut app);
+}
+
+#[test]
+fn keeps_state_when_readded() {
+    let mut app = app();
+    let metered = wait_for_exposure(&mut app);
+
+    app.world.remove_resource::<Snap>();
+    let camera = app
+        .world
+        .query_filtered::<Entity, With<AutoExposure>>()
+        .single(&app.world);
+    let states = StateBuffers::default();
+    app.sub_app_mut(RenderApp)
+        .insert_resource(states.clone())
+        .add_systems(
+            Render,
+            read_state_buffers
+                .in_set(RenderSet::Cleanup)
+                .before(World::clear_entities),
+        );
+    app.update();
+    let state = states.get(camera).unwrap();
+
+    // Without the component, the camera is neither metered nor read back.
+    let auto_exposure = app.world.entity_mut(camera).take::<AutoExposure>().unwrap();
+    app.update();
+    assert_eq!(states.get(camera), None);
+
+    // A component that is added back in the next frame keeps the state, so the exposure
+    // continues from the metered one instead of restarting from the initial exposure.
+    app.world.entity_mut(camera).insert(AutoExposure {
+        initial_ev: Some(-4.0),
+        ..auto_exposure
+    });
+    for _ in 0..10 {
+        app.update();
+        let exposure = exposure(&mut app).unwrap();
+        assert!(
+            (exposure - metered).abs() < 0.1,
+            "the exposure restarted at {exposure} EV, expected about {metered} EV"
+        );
+    }
+    assert_eq!(states.get(camera), Some(state));
+}
+
+#[test]
+fn keeps_state_when_toggled() {
+    let mut app = app();
+    let metered = wait_for_exposure(&mut app);
+
+    app.world.remove_resource::<Snap>();
+    let camera = app
+        .world
+        .query_filtered::<Entity, With<AutoExposure>>()
+        .single(&app.world);
+    let states = StateBuffers::default();
+    app.sub_app_mut(RenderApp)
+        .insert_resource(states.clone())
+        .add_systems(
+            Render,
+            read_state_buffers
+                .in_set(RenderSet::Cleanup)
+                .before(World::clear_entities),
+        );
+    app.update();
+    let state = states.get(camera).unwrap();
+
+    // A disabled camera is neither metered nor read back.
+    set_enabled(&mut app, camera, false);
+    for _ in 0..10 {
+        app.update();
+    }
+    assert_eq!(states.get(camera), None);
+    assert_eq!(exposure(&mut app), Some(metered));
+
+    // Unlike removing the component for longer than a frame, disabling it keeps the state, so
+    // the exposure continues from the metered one instead of restarting from the initial
+    // exposure.
+    set_enabled(&mut app, camera, true);
+    app.world
+        .get_mut::<AutoExposure>(camera)
+        .unwrap()
+        .initial_ev = Some(-4.0);
+    for _ in 0..10 {
+        app.update();
+        let exposure = exposure(&mut app).unwrap();
+        assert!(
+            (exposure - metered).abs() < 0.1,
+            "the exposure restarted at {exposure} EV, expected about {metered} EV"
+        );
+    }
+    assert_eq!(states.get(camera), Some(state));
+}
+
+fn set_enabled(app: &mut App, camera: Entity, enabled: bool) {
+    app.world.get_mut::<AutoExposure>(camera).unwrap().enabled = enabled;
+}
+
+/// The ids of the [`ViewExposureBuffer`]s of the last rendered frame, by view.
+#[derive(Resource, Clone, Default)]
+struct StateBuffers(Arc<Mutex<HashMap<Entity, BufferId>>>);
+
+impl StateBuffers {
+    fn get(&self, camera: Entity) -> Option<BufferId> {
+        self.0.lock().unwrap().get(&camera).copied()
+    }
+}
+
+fn read_state_buffers(states: Res<StateBuffers>, views: Query<(Entity, &ViewExposureBuffer)>) {
+    *states.0.lock().unwrap() = views
+        .iter()
+        .map(|(entity, state)| (entity, state.buffer.id()))
+        .collect();
+}
+
+/// The overlay pipeline is specialized for the sample count of the view, or drawing it on a
+/// multisampled view fails validation.
+#[cfg(feature = "debug")]
+#[test]
+fn draws_debug_overlay_with_msaa() {
+    let mut app = app();
+    app.insert_resource(Msaa::Sample4);
+    app.update();
+    let camera = app
+        .world
+        .query_filtered::<Entity, With<AutoExposure>>()
+        .single(&app.world);
+    app.world
+        .entity_mut(camera)
+        .insert(AutoExposureDebugOverlay::default());
+
+    wait_for_exposure(&mut app);
+    for _ in 0..10 {
+        app.update();
+    }
+    let failed = app
+        .sub_app(RenderApp)
+        .world
+        .resource::<PipelineCache>()
+        .pipelines()
+        .any(|pipeline| matches!(pipeline.state, CachedPipelineState::Err(_)));
+    assert!(!failed, "a pipeline failed to compile");
+}
+
+#[cfg(feature = "debug")]
+#[test]
+fn reads_back_histogram() {
+    let mut app = app();
+    wait_for_exposure(&mut app);
+    let camera = app
+        .world
+        .query_filtered::<Entity, With<AutoExposure>>()
+        .single(&app.world);
+    app.world
+        .entity_mut(camera)
+        .insert(HistogramReadback::default());
+    app.add_systems(Update, |mut readbacks: Query<&mut HistogramReadback>| {
+        readbacks.single_mut().request();
+    });
+
+    for _ in 0..MAX_FRAMES {
+        app.update();
+        let Some(event) = app
+            .world
+            .resource_mut::<Events<HistogramReadbackEvent>>()
+            .drain()
+            .next()
+        else {
+            continue;
+        };
+        assert_eq!(event.entity, camera);
+        // Every pixel of the solid image is metered with the full weight of 8.
+        let count: u32 = event.bins.iter().sum();
+        assert_eq!(count, 64 * 64 * 8);
+        return;
+    }
+
+    panic!("no histogram was read back within {MAX_FRAMES} frames");
+}
+
+#[test]
+fn keeps_exposure_when_viewport_resizes() {
+    let mut app = app();
+    // Run the startup systems, then meter the view, which is cleared to the same gray as the
+    // image.
+    app.update();
+    for (mut camera, mut auto_exposure) in app
+        .world
+        .query::<(&mut Camera, &mut AutoExposure)>()
+        .iter_mut(&mut app.world)
+    {
+        let gray = LUMINANCE_EV.exp2();
+        camera.clear_color = ClearColorConfig::Custom(Color::rgb_linear(gray, gray, gray));
+        auto_exposure.metering_source = MeteringSource::View;
+    }
+    let expected = wait_for_exposure(&mut app);
+    assert!(
+        (expected + LUMINANCE_EV).abs() < 0.1,
+        "metered an exposure of {expected} EV, expected {} EV",
+        -LUMINANCE_EV
+    );
+
+    // The exposure snaps to the metered target every frame, so a single frame that meters the
+    // resized viewport wrongly would show up.
+    for frame in 0..20 {
+        for mut camera in app.world.query::<&mut Camera>().iter_mut(&mut app.world) {
+            camera.viewport = Some(Viewport {
+                physical_position: UVec2::splat(frame),
+                physical_size: UVec2::new(16 + frame, 44 - frame),
+                ..default()
+            });
+        }
+        app.update();
+        let exposure = exposure(&mut app).unwrap();
+        assert!(
+            (exposure - expected).abs() < 0.1,
+            "the exposure jumped from {expected} EV to {exposure} EV in frame {frame}"
+        );
+    }
+}
+
+#[test]
+fn meters_offset_viewport() {
+    let mut app = app();
+    // Run the startup systems, then meter the view through a viewport over the right half of
+    // the target. The clear fills the whole target with a bright color, and a gray quad covers
+    // the viewport, so only the left half that is outside the viewport is bright.
+    app.update();
+    let bright = (LUMINANCE_EV + 4.0).exp2();
+    for (mut camera, mut auto_exposure) in app
+        .world
+        .query::<(&mut Camera, &mut AutoExposure)>()
+        .iter_mut(&mut app.world)
+    {
+        camera.clear_color = ClearColorConfig::Custom(Color::rgb_linear(bright, bright, bright));
+        camera.viewport = Some(Viewport {
+            physical_position: UVec2::new(32, 0),
+            physical_size: UVec2::new(32, 64),
+            ..default()
+        });
+        auto_exposure.metering_source = MeteringSource::View;
+    }
+    spawn_unlit_quad(&mut app, LUMINANCE_EV.exp2(), Vec3::new(0.0, 0.0, -1.0), 0);
+
+    // The quad is only drawn once its pipeline has compiled, which is waited for too, so the
+    // exposure of a few frames later is read back.
+    wait_for_exposure(&mut app);
+    for _ in 0..10 {
+        app.update();
+    }
+    let exposure = exposure(&mut app).unwrap();
+    assert!(
+        (exposure + LUMINANCE_EV).abs() < 0.1,
+        "metered an exposure of {exposure} EV, expected {} EV",
+        -LUMINANCE_EV
+    );
+}
+
+#[test]
+fn meters_viewports_independently() {
+    let mut app = app();
+    // Run the startup systems, then meter the view, which is cleared to the same gray as the
+    // image, with a bright quad over its left half.
+    app.update();
+    let (camera, auto_exposure) = app
+        .world
+        .query::<(&mut Camera, &mut AutoExposure)>()
+        .single_mut(&mut app.world);
+    let gray = LUMINANCE_EV.exp2();
+    let camera = camera.into_inner();
+    camera.clear_color = ClearColorConfig::Custom(Color::rgb_linear(gray, gray, gray));
+    let auto_exposure = auto_exposure.into_inner();
+    auto_exposure.metering_source = MeteringSource::View;
+    let (camera, auto_exposure) = (camera.clone(), auto_exposure.clone());
+
+    // The first camera sees a bright quad over its left half.
+    let bright = (LUMINANCE_EV + 4.0).exp2();
+    spawn_unlit_quad(&mut app, bright, Vec3::new(-1.0, 0.0, -1.0), 0);
+
+    // An inset camera over the right half of the same target, which clears the whole target
+    // once the first camera was metered, but only draws a gray quad over its viewport. It
+    // meters the gray only, not the bright clear around it.
+    spawn_unlit_quad(&mut app, gray, Vec3::new(0.0, 0.0, -1.0), 1);
+    let inset = app
+        .world
+        .spawn((
+            Camera3dBundle {
+                camera: Camera {
+                    order: 1,
+                    viewport: Some(Viewport {
+                        physical_position: UVec2::new(32, 0),
+                        physical_size: UVec2::new(32, 64),
+                        ..default()
+                    }),
+                    clear_color: ClearColorConfig::Custom(Color::rgb_linear(
+                        bright, bright, bright,
+                    )),
+                    ..camera
+                },
+                ..default()
+            },
+            RenderLayers::layer(1),
+            auto_exposure,
+            ExposureSettled::default(),
+        ))
+        .id();
+
+    let mut frames = 0;
+    while app
+        .world
+        .query::<&ExposureSettled>()
+        .iter(&app.world)
+        .any(|settled| settled.exposure().is_none())
+    {
+        assert!(frames < MAX_FRAMES, "no metered exposure was read back");
+        app.update();
+        frames += 1;
+    }
+    wait_for_pipelines(&mut app);
+    app.update();
+
+    // The inset only meters the gray in its viewport, while the first camera meters the bright
+    // quad too.
+    let mut settled = app.world.query::<(Entity, &ExposureSettled)>();
+    for (entity, settled) in settled.iter(&app.world) {
+        let exposure = settled.exposure().unwrap();
+        if entity == inset {
+            let expected = -LUMINANCE_EV;
+            assert!(
+                (exposure - expected).abs() < 0.1,
+                "the inset metered an exposure of {exposure} EV, expected {expected} EV"
+            );
+        } else {
+            assert!(
+                exposure < -LUMINANCE_EV - 1.0,
+                "the full view metered an exposure of {exposure} EV, expected less than {} EV",
+                -LUMINANCE_EV - 1.0
+            );
+        }
+    }
+}
+
+#[test]
+fn idles_without_camera() {
+    // Without a camera, there is nothing to meter, and the render systems and the node must
+    // not need any of the buffers that a camera creates.
+    let mut app = app_without_camera(AutoExposurePlugin::default());
+    for _ in 0..10 {
+        app.update();
+    }
+
+    // A camera that shows up later is still metered.
+    app.world.run_system_once(setup);
+    app.insert_resource(Snap)
+        .add_systems(Update, reset.run_if(resource_exists::<Snap>));
+    let exposure = wait_for_exposure(&mut app);
+    let expected = -LUMINANCE_EV;
+    assert!(
+        (exposure - expected).abs() < 0.1,
+        "metered an exposure of {exposure} EV, expected {expected} EV"
+    );
+}
+
+#[test]
+fn skips_metering_until_pipelines_compile() {
+    let mut app = app();
+    let unready = UnreadyFrames::default();
+    app.sub_app_mut(RenderApp)
+        .insert_resource(unready.clone())
+        .add_systems(Render, count_unready_frames.in_set(RenderSet::Prepare));
+
+    // The pipeline cache only compiles queued pipelines in `RenderSet::Render`, so the first
+    // frame is always prepared while the metering pipelines are compiling. The exposure is held
+    // until they are ready.
+    let exposure = wait_for_exposure(&mut app);
+    assert!(
+        *unready.0.lock().unwrap() > 0,
+        "the metering pipelines compiled before the first frame was rendered"
+    );
+    let expected = -LUMINANCE_EV;
+    assert!(
+        (exposure - expected).abs() < 0.1,
+        "metered an exposure of {exposure} EV, expected {expected} EV"
+    );
+}
+
+/// The number of frames that were prepared before the metering pipelines compiled.
+#[derive(Resource, Clone, Default)]
+struct UnreadyFrames(Arc<Mutex<usize>>);
+
+fn count_unready_frames(unready: Res<UnreadyFrames>, pipeline_cache: Res<PipelineCache>) {
+    // Queued pipelines only show up in the cache once it starts compiling them.
+    let compiled = pipeline_cache.pipelines().any(|pipeline| {
+        matches!(
+            &pipeline.descriptor,
+            PipelineDescriptor::ComputePipelineDescriptor(descriptor)
+                if descriptor.label.as_deref() == Some("luminance compute pipeline")
+        ) && matches!(pipeline.state, CachedPipelineState::Ok(_))
+    });
+    if !compiled {
+        *unready.0.lock().unwrap() += 1;
+    }
+}
+
+#[test]
+fn meters_light_sensor() {
+    let mut app = app();
+    app.insert_resource(AmbientLight {
+        brightness: 0.0,
+        ..default()
+    });
+    app.update();
+
+    // Light a gray card 3 EV brighter than the image, after the default exposure of the camera.
+    let sensed_ev = LUMINANCE_EV + 3.0;
+    let illuminance =
+        sensed_ev.exp2() / Exposure::default().exposure() * std::f32::consts::PI / 0.18;
+    let light = app
+        .world
+        .spawn(DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                illuminance,
+                ..default()
+            },
+            ..default()
+        })
+        .id();
+    let sensor = app
+        .world
+        .spawn((
+            LightSensor {
+                lights: vec![light],
+                ..default()
+            },
+            TransformBundle::default(),
+        ))
+        .id();
+    for mut auto_exposure in app
+        .world
+        .query::<&mut AutoExposure>()
+        .iter_mut(&mut app.world)
+    {
+        auto_exposure.metering_source = MeteringSource::LightSensor(sensor);
+    }
+
+    let exposure = wait_for_exposure(&mut app);
+    let expected = -sensed_ev;
+    assert!(
+        (exposure - expected).abs() < 0.1,
+        "metered an exposure of {exposure} EV, expected {expected} EV"
+    );
+}
+
+fn app() -> App {
+    app_with(AutoExposurePlugin::default())
+}
+
+fn app_with<M>(plugins: impl Plugins<M>) -> App {
+    let mut app = app_without_camera(plugins);
+    app.insert_resource(Snap)
+        .add_systems(Startup, setup)
+        .add_systems(Update, reset.run_if(resource_exists::<Snap>));
+    app
+}
+
+fn app_without_camera<M>(plugins: impl Plugins<M>) -> App {
+    let mut app = App::new();
+    app.add_plugins(
+        DefaultPlugins
+            .build()
+            .disable::<WinitPlugin>()
+            .disable::<PipelinedRenderingPlugin>()
+            .disable::<LogPlugin>()
+            .set(WindowPlugin {
+                primary_window: None,
+                exit_condition: ExitCondition::DontExit,
+                close_when_requested: false,
+            }),
+    )
+    .add_plugins(plugins);
+
+    while app.plugins_state() != PluginsState::Ready {
+        bevy::tasks::tick_global_task_pools_on_main_thread();
+    }
+    app.finish();
+    app.cleanup();
+    app
+}
+
+/// Updates the app until the first metered exposure is read back, and returns it. The pipelines
+/// that are still compiling by then are waited for too, see [`wait_for_pipelines`].
+fn wait_for_exposure(app: &mut App) -> f32 {
+    for _ in 0..MAX_FRAMES {
+        app.update();
+        if let Some(exposure) = exposure(app) {
+            wait_for_pipelines(app);
+            return exposure;
+        }
+    }
+
+    panic!("no metered exposure was read back within {MAX_FRAMES} frames");
+}
+
+/// Updates the app until every pipeline has compiled. On the GL backend, pipelines that are still
+/// compiling when the app is dropped break the context of the next app.
+fn wait_for_pipelines(app: &mut App) {
+    for _ in 0..MAX_FRAMES {
+        let compiling = app
+            .sub_app(RenderApp)
+            .world
+            .resource::<PipelineCache>()
+            .pipelines()
+            .any(|pipeline| {
+                matches!(
+                    pipeline.state,
+                    CachedPipelineState::Queued | CachedPipelineState::Creating(_)
+                )
+            });
+        if !compiling {
+            return;
+        }
+        app.update();
+    }
+}
+
+fn exposure(app: &mut App) -> Option<f32> {
+    app.world
+        .query::<&ExposureSettled>()
+        .single(&app.world)
+        .exposure()
+}
+
+fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let size = Extent3d {
+        width: 64,
+        height: 64,
+        depth_or_array_layers: 1,
+    };
+
+    // A gray with equal channels has the same luminance as each channel. The target image must
+    // stay in the main world, or the camera can't find its size.
+    let gray = half_bytes(LUMINANCE_EV.exp2());
+    let one = half_bytes(1.0);
+    let source = images.add(Image::new_fill(
+        size,
+        TextureDimension::D2,
+        &[gray, gray, gray, one].concat(),
+        TextureFormat::Rgba16Float,
+        RenderAssetUsages::default(),
+    ));
+
+    let mut target = Image::new_fill(
+        size,
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::Bgra8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    target.texture_descriptor.usage |= TextureUsages::RENDER_ATTACHMENT;
+    let target = images.add(target);
+
+    commands.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                hdr: true,
+                target: RenderTarget::Image(target),
+                ..default()
+            },
+            ..default()
+        },
+        AutoExposure {
+            metering_source: MeteringSource::Image(source),
+            ..default()
+        },
+        ExposureSettled::default(),
+    ));
+}
+
+/// Snaps the exposure to the metered target, so the first read back exposure is already the
+/// target.
+fn reset(mut commands: Commands, cameras: Query<Entity, With<AutoExposure>>) {
+    for entity in cameras.iter() {
+        commands.entity(entity).reset_auto_exposure();
+    }
+}
+
+/// Spawns an unlit 2x2 quad of a gray with the luminance `value`, on a render layer.
+fn spawn_unlit_quad(app: &mut App, value: f32, translation: Vec3, layer: u8) {
+    let mesh = app
+        .world
+        .resource_mut::<Assets<Mesh>>()
+        .add(Rectangle::new(2.0, 2.0));
+    let material = app
+        .world
+        .resource_mut::<Assets<StandardMaterial>>()
+        .add(StandardMaterial {
+            base_color: Color::rgb_linear(value, value, value),
+            unlit: true,
+            ..default()
+        });
+    app.world.spawn((
+        PbrBundle {
+            mesh,
+            material,
+            transform: Transform::from_translation(translation),
+            ..default()
+        },
+        RenderLayers::layer(layer),
+    ));
+}
+
+/// Encodes a small power of two as a little endian half float.
+fn half_bytes(value: f32) -> [u8; 2] {
+    let exponent = value.log2() as i32 + 15;
+    ((exponent as u16) << 10).to_le_bytes()
+}